@@ -7,7 +7,7 @@ from rich import print
 
 
 async def main() -> None:
-    result = await shl._execute_command(t"cat playground.py | wc -l")  # pyright: ignore[reportAttributeAccessIssue]
+    result = await shl.sh(t"cat playground.py | wc -l").text()
     print(result)
 
 
@@ -14,12 +14,18 @@ if hasattr(shl, "__all__"):
 
 
 class Command[T = Command]:
-    def __init__(self, command: Template) -> None:
+    def __init__(self, command: Template, args: list[str] | None = None) -> None:
         self._command: Template = command
+        self._args: list[str] = args or []
+        "The positional parameters, `$1`, `$2`, ..."
         self._quiet: bool = False
         "If set, the command will not print its output to stdout/stderr."
         self._text: str | None = None
         "If set, its the encoding for capturing stdout."
+        self._kill_jobs: bool = False
+        "If set, background jobs still running at the end are killed instead of waited for."
+        self._max_call_depth: int | None = None
+        "If set, the maximum nesting depth of function calls."
 
     def text(self, encoding: str = "utf-8") -> Command[str]:
         """Returns the stdout of the command as a string when awaited."""
@@ -38,15 +44,27 @@ class Command[T = Command]:
         self._quiet = True
         return self
 
+    def kill_jobs(self) -> Command[T]:
+        """Kill background jobs that are still running when the command finishes."""
+        self._kill_jobs = True
+        return self
+
+    def max_call_depth(self, depth: int) -> Command[T]:
+        """Fail function calls nested deeper than `depth`, which stops runaway recursion."""
+        self._max_call_depth = depth
+        return self
+
     def __await__(self) -> Generator[None, None, CompletedCommand]:
         return _execute_command(self).__await__()  # pyright: ignore[reportUndefinedVariable]
 
 
 class CompletedCommand:
-    def __init__(self, returncode: int = 0) -> None:
+    def __init__(self, returncode: int = 0, pipestatus: list[int] | None = None) -> None:
         self.returncode: int = returncode
+        self.pipestatus: list[int] = [returncode] if pipestatus is None else pipestatus
+        "The status of every stage of the last pipeline that ran, like `$PIPESTATUS`."
 
 
-def sh(command: Template) -> Command:
-    """Create a command to be executed."""
-    return Command(command)
+def sh(command: Template, *args: str) -> Command:
+    """Create a command to be executed, `args` are its positional parameters."""
+    return Command(command, list(args))
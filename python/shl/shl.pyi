@@ -0,0 +1 @@
+COREUTILS: bool
@@ -1,11 +1,379 @@
 from __future__ import annotations
 
+import io
+import os
+from typing import TYPE_CHECKING
+
 import pytest
 import shl
 
+if TYPE_CHECKING:
+    from pathlib import Path
+
 
 @pytest.mark.asyncio
 async def test_sh() -> None:
     p = await shl.sh(t'echo "Hello, World!"')
     assert isinstance(p, shl.CompletedCommand)
     assert p.returncode == 0
+
+
+@pytest.mark.asyncio
+async def test_variables() -> None:
+    assert await shl.sh(t"FOO=bar; echo $FOO").text() == "bar\n"
+    assert await shl.sh(t"FOO='a  b'; printf '[%s]' $FOO \"$FOO\"").text() == "[a][b][a  b]"
+    assert await shl.sh(t"FOO=bar; sh -c 'echo $FOO'").text() == "\n"
+    assert await shl.sh(t"FOO=bar sh -c 'echo $FOO'").text() == "bar\n"
+    assert await shl.sh(t"x=$(exit 3); echo $?").text() == "3\n"
+    p = await shl.sh(t"set -e; x=$(false); echo after").quiet()
+    assert p.returncode == 1
+
+
+@pytest.mark.asyncio
+async def test_and_or() -> None:
+    assert await shl.sh(t"true && echo a || echo b").text() == "a\n"
+    assert await shl.sh(t"false && echo a || echo b").text() == "b\n"
+    p = await shl.sh(t"true && sh -c 'exit 4' || true && false").quiet()
+    assert p.returncode == 1
+
+
+@pytest.mark.asyncio
+async def test_if() -> None:
+    assert await shl.sh(t"if false; then echo a; elif true; then echo b; else echo c; fi").text() == "b\n"
+    assert await shl.sh(t"if false; then echo a; else echo c; fi").text() == "c\n"
+    assert await shl.sh(t"if true; then echo x; echo y; fi | wc -l").text() == "2\n"
+    assert await shl.sh(t"if true; then cat; fi <<< hi").text() == "hi\n"
+
+
+@pytest.mark.asyncio
+async def test_subshell(tmp_path: Path) -> None:
+    assert await shl.sh(t"X=1; (X=2; echo $X); echo $X").text() == "2\n1\n"
+    assert await shl.sh(t"(echo a; echo b) | wc -l").text() == "2\n"
+    out = str(tmp_path / "out.log")
+    await shl.sh(t"(echo a; echo b) > {out}")
+    assert (tmp_path / "out.log").read_text() == "a\nb\n"
+
+
+@pytest.mark.asyncio
+async def test_cond_expr(tmp_path: Path) -> None:
+    pattern = "h*"
+    assert await shl.sh(t"X=hello; [[ $X == h* ]] && echo y").text() == "y\n"
+    assert await shl.sh(t'[[ hello == "h*" ]] || echo n').text() == "n\n"
+    assert await shl.sh(t"[[ hello == {pattern} ]] || echo n").text() == "n\n"
+    assert await shl.sh(t"[[ 3 -lt 10 && abc < abd ]] && echo y").text() == "y\n"
+    assert await shl.sh(t"[[ bar =~ ^(foo|bar)$ ]] && echo y").text() == "y\n"
+    assert await shl.sh(t"[[ ! -e {str(tmp_path / 'nope')} && -d {str(tmp_path)} ]] && echo y").text() == "y\n"
+    p = await shl.sh(t"[[ x -eq 1 ]]").quiet()
+    assert p.returncode == 2
+    assert await shl.sh(t"[[ x == [[:alpha:]] ]] && echo y; {{ [[ x ]] && echo g; }}").text() == "y\ng\n"
+    assert await shl.sh(t"echo [[:alpha:]].none a]]").text() == "[[:alpha:]].none a]]\n"
+
+
+@pytest.mark.asyncio
+async def test_background_jobs() -> None:
+    assert await shl.sh(t"sleep 0.2 && echo bg & echo fg").text() == "fg\nbg\n"
+    assert await shl.sh(t"sh -c 'exit 3' & wait $! || echo failed").text() == "failed\n"
+    assert await shl.sh(t"sh -c 'kill -TERM $$' & wait $!; echo $?").text() == "143\n"
+    assert await shl.sh(t"(sleep 0.1; echo a) & echo b & wait; echo done").text() == "b\na\ndone\n"
+    assert await shl.sh(t"f() {{ echo \"f $FOO\"; }}; FOO=1 f & wait; echo \"[$FOO]\"").text() == "f 1\n[]\n"
+    assert await shl.sh(t"sleep 0.5 && echo late & echo early").kill_jobs().text() == "early\n"
+
+
+@pytest.mark.asyncio
+async def test_redirects(tmp_path: Path) -> None:
+    out = str(tmp_path / "out.log")
+    await shl.sh(t"echo a > {out}; echo b >> {out}")
+    assert (tmp_path / "out.log").read_text() == "a\nb\n"
+    assert await shl.sh(t"tr a-z A-Z < {tmp_path / 'out.log'}").text() == "A\nB\n"
+    await shl.sh(t"sh -c 'echo out; echo err >&2' &> {out}")
+    assert (tmp_path / "out.log").read_text() == "out\nerr\n"
+    await shl.sh(t"sh -c 'echo err >&2' 2> {out}")
+    assert (tmp_path / "out.log").read_text() == "err\n"
+    assert await shl.sh(t"sh -c 'echo err >&2' 2>&1 | tr a-z A-Z").text() == "ERR\n"
+    assert await shl.sh(t"echo out 1>&2").text() == ""
+    assert await shl.sh(t"(echo a; echo b) | cat > {out}; cat < {tmp_path / 'out.log'}").text() == "a\nb\n"
+    assert await shl.sh(t"cat < {tmp_path / 'missing'} || echo failed").text() == "failed\n"
+
+
+@pytest.mark.asyncio
+async def test_pyobject_redirects(tmp_path: Path) -> None:
+    assert await shl.sh(t"tr a-z A-Z < {b'hello'}").text() == "HELLO"
+    assert await shl.sh(t"cat < {'text'}").text() == "text"
+    buf = bytearray()
+    await shl.sh(t"sh -c 'echo out; echo err >&2' &> {buf}")
+    assert buf == b"out\nerr\n"
+    await shl.sh(t"echo new > {buf}")
+    assert buf == b"new\n"
+    await shl.sh(t"echo more >> {buf}")
+    assert buf == b"new\nmore\n"
+    path = tmp_path / "out.log"
+    await shl.sh(t"echo a > {path}")
+    assert path.read_text() == "a\n"
+    with path.open("ab") as f:
+        await shl.sh(t"echo b >> {f}")
+    with path.open("rb") as f:
+        assert await shl.sh(t"cat < {f}").text() == "a\nb\n"
+    bio = io.BytesIO()
+    await shl.sh(t"echo c > {bio}")
+    assert bio.getvalue() == b"c\n"
+    assert await shl.sh(t"cat < {io.BytesIO(b'd')}").text() == "d"
+
+
+@pytest.mark.asyncio
+async def test_glob(tmp_path: Path) -> None:
+    for name in ["b.txt", "a.txt", "c.md", ".hidden.txt", "sub/d.txt"]:
+        (tmp_path / name).parent.mkdir(exist_ok=True)
+        (tmp_path / name).touch()
+    d = str(tmp_path)
+    assert await shl.sh(t"echo {d}/*.txt").text() == f"{d}/a.txt {d}/b.txt\n"
+    assert await shl.sh(t"echo {d}/**/*.txt").text() == f"{d}/a.txt {d}/b.txt {d}/sub/d.txt\n"
+    assert await shl.sh(t"echo {d}/*.nope").text() == f"{d}/*.nope\n"
+    assert await shl.sh(t'echo "{d}/*.txt" {d}/\\*.md').text() == f"{d}/*.txt {d}/*.md\n"
+    assert await shl.sh(t"x='a\\b'; echo $x; x=C:\\\\dir; echo $x").text() == "a\\b\nC:\\dir\n"
+    assert await shl.sh(t"echo $(printf 'a\\\\b')").text() == "a\\b\n"
+
+
+@pytest.mark.asyncio
+async def test_brace_expansion(tmp_path: Path) -> None:
+    assert await shl.sh(t"echo file.{{txt,bak}} src/{{a,b}}/{{x,y}}").text() == "file.txt file.bak src/a/x src/a/y src/b/x src/b/y\n"
+    assert await shl.sh(t"echo {{a,b{{1,2}}}}c").text() == "ac b1c b2c\n"
+    assert await shl.sh(t"echo {{1..3}} {{3..1}} {{0..10..5}} {{08..10}} {{a..c}}").text() == "1 2 3 3 2 1 0 5 10 08 09 10 a b c\n"
+    assert await shl.sh(t'echo {{a}} {{}} a{{b,c "{{x,y}}"').text() == "{a} {} a{b,c {x,y}\n"
+    assert await shl.sh(t"x=q; xa=1; echo $x{{a,b}}.c ${{x}}{{a,b}}").text() == "1.c .c qa qb\n"
+    (tmp_path / "a.txt").touch()
+    (tmp_path / "b.md").touch()
+    d = str(tmp_path)
+    assert await shl.sh(t"echo {d}/{{*.txt,*.md}}").text() == f"{d}/a.txt {d}/b.md\n"
+
+
+@pytest.mark.asyncio
+async def test_tilde() -> None:
+    assert await shl.sh(t"HOME=/h; echo ~ ~/x a~ '~' \\~").text() == "/h /h/x a~ ~ ~\n"
+    assert await shl.sh(t"HOME=/h; P=~/bin:~/sbin; echo $P").text() == "/h/bin:/h/sbin\n"
+    assert await shl.sh(t"echo ~root ~no_such_user_").text() == "/root ~no_such_user_\n"
+    assert await shl.sh(t"OLDPWD=/old; echo ~-/x").text() == "/old/x\n"
+
+
+@pytest.mark.asyncio
+async def test_positional_params() -> None:
+    assert await shl.sh(t'echo $# $1 "$2"', "a", "b c").text() == "2 a b c\n"
+    assert await shl.sh(t"printf '[%s]' \"$@\" $*", "a", "b c").text() == "[a][b c][a][b][c]"
+    assert await shl.sh(t'IFS=,; echo "$*"', "a", "b").text() == "a,b\n"
+    assert await shl.sh(t"printf '[%s]' \"${{@:-z}}\"", "a b", "c").text() == "[a b][c]"
+    assert await shl.sh(t"printf '[%s]' \"${{@:-z}}\"").text() == "[z]"
+    assert await shl.sh(t"set -- 1 2 3 4 5 6 7 8 9 10; shift; echo ${{9}} $#").text() == "10 9\n"
+    assert await shl.sh(t"shift; echo $?").text() == "1\n"
+    assert await shl.sh(t"false; echo $?; true; echo $?").text() == "1\n0\n"
+    assert await shl.sh(t"echo $0 $$").text() == f"shl {os.getpid()}\n"
+
+
+@pytest.mark.asyncio
+async def test_var_expansion() -> None:
+    assert await shl.sh(t'x=; echo ${{x:-a}} "${{x-b}}" ${{y:=c}} $y ${{x:+d}} ${{y:+e}}').text() == "a  c c e\n"
+    path = "/usr/lib/archive.tar.gz"
+    assert await shl.sh(t"p={path}; echo ${{#p}} ${{p##*/}} ${{p%/*}} ${{p#*.}} ${{p%%.*}}").text() == "23 archive.tar.gz /usr/lib tar.gz /usr/lib/archive\n"
+    assert await shl.sh(t"x=aXbXc; echo ${{x/X/-}} ${{x//X/-}} ${{x/#a/S}} ${{x/%c/E}}").text() == "a-bXc a-b-c SXbXc aXbXE\n"
+    assert await shl.sh(t"x=hello; echo ${{x:1}} ${{x:1:3}} ${{x: -3:2}} ${{x:1:-1}}").text() == "ello ell ll ell\n"
+    assert await shl.sh(t"echo ${{u:-{path}}}").text() == f"{path}\n"
+    assert await shl.sh(t'x="a  b"; printf "[%s]" ${{x:-}} "${{x:-}}"').text() == "[a][b][a  b]"
+    p = await shl.sh(t"echo ${{x:?not set}}; echo $?").quiet()
+    assert p.returncode == 1
+    assert await shl.sh(t"(echo ${{x:?not set}}); echo $?").text() == "1\n"
+    assert await shl.sh(t"x=hello; echo ${{#x[@]}} ${{#x[0]}} ${{#x[1]}}").text() == "1 5 0\n"
+    with pytest.raises(ValueError, match="Bad substitution"):
+        await shl.sh(t"echo ${{#x%}}").text()
+
+
+@pytest.mark.asyncio
+async def test_arithmetic() -> None:
+    assert await shl.sh(t"echo $((1 + 2 * 3)) $(( (1+2) * 3 )) $((-7 / 2)) $((2 ** 10)) $((0x1f)) $((2#101))").text() == "7 9 -3 1024 31 5\n"
+    assert await shl.sh(t"x=5; echo $((x += 2)) $((x++)) $((--x)) $((x > 6 ? x : 0)) $((~x & 0xff))").text() == "7 7 7 7 248\n"
+    n = "4"
+    assert await shl.sh(t"echo $(( {n} * $(echo 3) ))").text() == "12\n"
+    assert await shl.sh(t"i=0; ((i++)); (( i == 1 )) && echo yes; ((0)); echo $?").text() == "yes\n1\n"
+    assert await shl.sh(t"echo $((1 / 0)); echo $?").text() == "1\n"
+    assert await shl.sh(t"echo $((65#1)); echo $((1#0)); echo $((2#2)); echo $((16#g))").text() == ""
+    assert await shl.sh(t"echo $((64#A)) $((36#A)) $((64#@)) $((64#_))").text() == "36 10 62 63\n"
+    assert await shl.sh(t"echo $((echo {n}); echo b); ((echo c) )").text() == "4 b\nc\n"
+
+
+@pytest.mark.asyncio
+async def test_for_loops(tmp_path: Path) -> None:
+    names = ["a", "b c"]
+    assert await shl.sh(t'for f in {names} d; do echo "[$f]"; done').text() == "[a]\n[b c]\n[d]\n"
+    assert await shl.sh(t"for x\ndo\n  echo $x\ndone", "p", "q").text() == "p\nq\n"
+    assert await shl.sh(t"for ((i = 0; i < 3; i++)); do echo $i; done; echo $i").text() == "0\n1\n2\n3\n"
+    out = tmp_path / "out"
+    script = t"for ((i = $(echo x >> {out}; echo 0); i < 3; i++)); do :; done; wc -l < {out}"
+    assert (await shl.sh(script).text()).strip() == "1"
+    assert await shl.sh(t"x='a;b'; for ((i = 0; i < ${{#x}}; i += 2)); do echo $i; done").text() == "0\n2\n"
+    assert await shl.sh(t"for x in a b; do echo $x; done > {out}; cat {out}").text() == "a\nb\n"
+    assert await shl.sh(t"for x in a b; do echo $x; done | tr a-z A-Z").text() == "A\nB\n"
+
+
+@pytest.mark.asyncio
+async def test_while_loops(tmp_path: Path) -> None:
+    assert await shl.sh(t"i=0; while (( i < 3 )); do echo $i; ((i++)); done").text() == "0\n1\n2\n"
+    assert await shl.sh(t"i=0\nuntil [[ $i == 2 ]]\ndo\n  i=$((i + 1))\ndone\necho $i").text() == "2\n"
+    log = tmp_path / "app.log"
+    log.write_text("INFO start\nERROR disk full\nINFO done\nERROR oom\n")
+    script = t"while read -r level msg; do [[ $level == ERROR ]] && echo \"$msg\"; done < {log}"
+    assert await shl.sh(script).text() == "disk full\noom\n"
+    assert await shl.sh(t"cat {log} | while IFS= read -r l; do echo \"<$l>\"; done | head -1").text() == "<INFO start>\n"
+    script = t"for i in 1 2 3; do for j in a b c; do [[ $j == b ]] && continue 2; [[ $i == 3 ]] && break 2; echo $i$j; done; done"
+    assert await shl.sh(script).text() == "1a\n2a\n"
+    assert await shl.sh(t"i=0; while true; do ((++i > 3)) && break; done; echo $i").text() == "4\n"
+    assert await shl.sh(t"while true; do if break; then echo x; fi; done; echo out").text() == "out\n"
+    assert await shl.sh(t"for i in 1 2; do if false; then :; elif continue; then echo x; fi; echo no; done").text() == ""
+    assert await shl.sh(t"f() {{ if return 3; then echo x; fi; echo no; }}; f; echo $?").text() == "3\n"
+
+
+@pytest.mark.asyncio
+async def test_case() -> None:
+    script = t"""
+    for arg in start --verbose -x build.tar.gz ''; do
+      case $arg in
+        start | stop) echo "command $arg" ;;
+        --verbose | -v) echo verbose ;;
+        -?) echo "flag $arg" ;;
+        *.tar.[gx]z) echo archive ;;
+        '') echo empty ;;
+        *) echo other ;;
+      esac
+    done
+    """
+    assert await shl.sh(script).text() == "command start\nverbose\nflag -x\narchive\nempty\n"
+    assert await shl.sh(t"case a in a) echo 1 ;& b) echo 2 ;; c) echo 3 ;; esac").text() == "1\n2\n"
+    assert await shl.sh(t"case ab in a*) echo 1 ;;& *b) echo 2 ;;& c) echo 3 ;; esac").text() == "1\n2\n"
+    assert await shl.sh(t"echo $(case x in (x) echo sub ;; esac) | tr a-z A-Z").text() == "SUB\n"
+    assert await shl.sh(t"case '*' in \"*\") echo literal ;; *) echo glob ;; esac").text() == "literal\n"
+    assert await shl.sh(t"case x in [[:alpha:]]) echo class ;; esac").text() == "class\n"
+    script = t"for w in in esac x; do case $w in in) echo 1 ;; esac) echo 2 ;; *) echo 3 ;; esac; done"
+    assert await shl.sh(script).text() == "1\n2\n3\n"
+
+
+@pytest.mark.asyncio
+async def test_functions(tmp_path: Path) -> None:
+    script = t'greet() {{ local name=$1; echo "hi $name"; }}; greet "a b"; echo "[$name]"'
+    assert await shl.sh(script).text() == "hi a b\n[]\n"
+    assert await shl.sh(t"function add {{ return $(( $1 + $2 )); }}\nadd 2 3; echo $?").text() == "5\n"
+    assert await shl.sh(t"f() {{ for x in 1 2 3; do [[ $x == 2 ]] && return; echo $x; done; }}; f; echo $#", "a").text() == "1\n1\n"
+    out = tmp_path / "out"
+    assert await shl.sh(t"log() {{ echo \"$@\"; }} >> {out}; log a; log b; cat {out}").text() == "a\nb\n"
+    assert await shl.sh(t"true() {{ return 1; }}; true || echo shadowed").text() == "shadowed\n"
+    assert await shl.sh(t"{{ echo a; echo b; }} | wc -l").text() == "2\n"
+    p = await shl.sh(t"f() {{ f; }}; f").max_call_depth(5).quiet()
+    assert p.returncode == 1
+    p = await shl.sh(t"no-such-command-shl").quiet()
+    assert p.returncode == 127
+
+
+@pytest.mark.asyncio
+async def test_heredocs(tmp_path: Path) -> None:
+    name = "$world"
+    script = t"x=1; cat <<EOF\n\"$x\" $(echo sub) $((x + 1)) \\$x {name}\nEOF\necho done"
+    assert await shl.sh(script).text() == "\"1\" sub 2 $x $world\ndone\n"
+    assert await shl.sh(t"x=1; cat <<'EOF'\n$x $(echo no)\nEOF").text() == "$x $(echo no)\n"
+    assert await shl.sh(t"cat <<-EOF | tr a-z A-Z\n\tone\n\t\ttwo\n\tEOF").text() == "ONE\nTWO\n"
+    assert await shl.sh(t"cat <<A; cat <<B\na\nA\nb\nB").text() == "a\nb\n"
+    script = t"while read -r n; do true; echo $((n * 2)); done <<EOF\n1\n2\nEOF"
+    assert await shl.sh(script).text() == "2\n4\n"
+    out = tmp_path / "out"
+    assert await shl.sh(t"cat > {out} <<EOF\nsaved\nEOF\ncat {out}").text() == "saved\n"
+
+
+@pytest.mark.asyncio
+async def test_herestrings() -> None:
+    text = "apple\nbanana\ncherry"
+    assert await shl.sh(t"grep an <<< {text}").text() == "banana\n"
+    assert await shl.sh(t'x="a b  c"; tr a-z A-Z <<< $x').text() == "A B  C\n"
+    assert await shl.sh(t'read -r first rest <<< "1 2 3"; echo "$rest|$first"').text() == "2 3|1\n"
+    assert await shl.sh(t"wc -c <<<word").text() == "5\n"
+    assert await shl.sh(t"cat <<< {b'raw'}").text() == "raw\n"
+    assert await shl.sh(t"cat <<< {bytearray(b'ba')}").text() == "ba\n"
+    assert await shl.sh(t"cat <<< {memoryview(b'mv')}").text() == "mv\n"
+    assert await shl.sh(t"cat <<< {42}").text() == "42\n"
+
+
+@pytest.mark.asyncio
+async def test_process_substitution(tmp_path: Path) -> None:
+    a = tmp_path / "a"
+    b = tmp_path / "b"
+    a.write_text("pear\napple\n")
+    b.write_text("apple\nplum\n")
+    p = await shl.sh(t"diff <(sort {a}) <(sort {b})").quiet()
+    assert p.returncode == 1
+    assert await shl.sh(t"comm -12 <(sort {a}) <(sort {b})").text() == "apple\n"
+    assert await shl.sh(t'while read -r l; do echo "<$l>"; done < <(echo x; echo y)').text() == "<x>\n<y>\n"
+    out = tmp_path / "out"
+    assert await shl.sh(t"echo hi | tee >(tr a-z A-Z > {out}) > /dev/null; cat {out}").text() == "HI\n"
+    assert await shl.sh(t'f() {{ cat "$1"; }}; f <(echo from function)').text() == "from function\n"
+    assert await shl.sh(t"head -1 <(yes)").text() == "y\n"
+    assert await shl.sh(t"echo hi > >(cat)").text() == "hi\n"
+    assert await shl.sh(t"echo in | cat <(cat)").text() == "in\n"
+
+
+@pytest.mark.asyncio
+async def test_core_builtins(tmp_path: Path) -> None:
+    (tmp_path / "sub").mkdir()
+    cwd = os.getcwd()
+    assert await shl.sh(t"cd {tmp_path}/sub && pwd && cd .. && ls").text() == f"{tmp_path}/sub\nsub\n"
+    assert os.getcwd() == cwd
+    assert await shl.sh(t"cd {tmp_path}; cd /; cd - > /dev/null; echo $PWD $OLDPWD").text() == f"{tmp_path} /\n"
+    assert await shl.sh(t"cd {tmp_path} | true; pwd").text() == f"{cwd}\n"
+    p = await shl.sh(t"cd {tmp_path}/missing").quiet()
+    assert p.returncode == 1
+    assert await shl.sh(t"export A=1; B=2; sh -c 'echo $A-$B'; unset A; sh -c 'echo $A-'").text() == "1-\n-\n"
+    p = await shl.sh(t"echo a; f() {{ exit 3; }}; f; echo b").quiet()
+    assert p.returncode == 3
+    assert await shl.sh(t"if exit 0; then echo ran; fi; echo after").text() == ""
+    p = await shl.sh(t"if false; then :; elif exit 3; then echo ran; fi; echo after").quiet()
+    assert p.returncode == 3
+    assert await shl.sh(t"(exit 4); echo $?; true && : && echo ok; false || echo failed").text() == "4\nok\nfailed\n"
+
+
+@pytest.mark.asyncio
+@pytest.mark.skipif(not shl.COREUTILS, reason="built without the coreutils feature")
+async def test_coreutils(tmp_path: Path) -> None:
+    # With nothing in $PATH, only the in-process utilities can run.
+    assert await shl.sh(t"PATH=/nonexistent; cd {tmp_path}; mkdir -p a/b; touch a/f; ls a").text() == "b\nf\n"
+    assert await shl.sh(t"PATH=/nonexistent; seq 10 | head -n 3 | tail -n 1; seq 5 | wc -l").text() == "3\n5\n"
+    assert await shl.sh(t"PATH=/nonexistent; yes | head -n 2; echo -e 'a\\tb'").text() == "y\ny\na\tb\n"
+    assert await shl.sh(t"PATH=/nonexistent; basename /x/y.txt .txt; dirname /x/y.txt").text() == "y\n/x\n"
+    cmd = t"PATH=/nonexistent; cd {tmp_path}; echo hi > f; cp f g; mv g h; rm f; cat h; ls"
+    assert await shl.sh(cmd).text() == "hi\na\nh\n"
+    p = await shl.sh(t"PATH=/nonexistent; cat {tmp_path}/missing").quiet()
+    assert p.returncode == 1
+
+
+@pytest.mark.asyncio
+async def test_shell_options() -> None:
+    p = await shl.sh(t"set -e; echo a; false; echo b").quiet()
+    assert p.returncode == 1
+    cmd = t"set -e; false && true; false || true; if false; then :; fi; f() {{ false; }}; f || echo ok"
+    assert await shl.sh(cmd).text() == "ok\n"
+    assert await shl.sh(t"set -o pipefail; false | true; echo $?; set +o pipefail; false | true; echo $?").text() == "1\n0\n"
+    p = await shl.sh(t"set -u; echo ${{x:-default}}; echo $x; echo no").quiet()
+    assert p.returncode == 1
+    assert await shl.sh(t"set -u; echo ${{x:-default}}").text() == "default\n"
+    assert await shl.sh(t"set -x; echo 'a b' 2>/dev/null; set +x; echo c 2>&1").text() == "a b\nc\n"
+    assert await shl.sh(t"{{ set -x; echo 'a b'; set +x; }} 2>&1").text() == "+ echo 'a b'\na b\n+ set +x\n"
+    assert await shl.sh(t"set -f; echo /*; shopt -u noglob; shopt noglob").text() == "/*\nnoglob         \toff\n"
+
+
+@pytest.mark.asyncio
+async def test_pipeline_status() -> None:
+    assert await shl.sh(t"true | false; echo $?; false | true; echo $?").text() == "1\n0\n"
+    cmd = t'false | (exit 3) | true; echo ${{PIPESTATUS[@]}} ${{PIPESTATUS[1]}}; for s in "${{PIPESTATUS[@]}}"; do echo "<$s>"; done'
+    assert await shl.sh(cmd).text() == "1 3 0 3\n<0>\n"
+    assert await shl.sh(t"true | false | true; echo ${{#PIPESTATUS[@]}} ${{#PIPESTATUS[*]}}").text() == "3 3\n"
+    assert await shl.sh(t"! true; echo $?; ! false | true; echo $? ${{PIPESTATUS[@]}}").text() == "1\n1 1 0\n"
+    assert await shl.sh(t"set -e; ! true; if ! false; then echo negated; fi").text() == "negated\n"
+    p = await shl.sh(t"true | (exit 2) | true").quiet()
+    assert p.returncode == 0
+    assert p.pipestatus == [0, 2, 0]
+    p = await shl.sh(t"exit 5").quiet()
+    assert p.pipestatus == [5]
+    p = await shl.sh(t"sh -c 'kill -TERM $$' | sh -c 'kill -TERM $$'").quiet()
+    assert p.returncode == 143
+    assert p.pipestatus == [143, 143]
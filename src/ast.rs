@@ -1,4 +1,5 @@
 use bytes::Bytes;
+use std::sync::Arc;
 
 #[derive(Debug, Clone)]
 pub struct Script {
@@ -17,9 +18,18 @@ pub enum Expr {
     Pipeline(Pipeline),
     Cmd(Cmd),
     SubShell(SubShell),
+    Group(Group),
     If(If),
+    For(For),
+    While(While),
+    Case(Case),
     CondExpr(CondExpr),
+    Function(Function),
+    /// `(( expr ))`, the expression is a word that is expanded and then evaluated.
+    Arith(Atom),
     Async(Box<Expr>),
+    /// `! pipeline`, succeeds if the pipeline fails and the other way around.
+    Not(Box<Expr>),
 }
 
 impl Expr {
@@ -28,8 +38,13 @@ impl Expr {
             Expr::Assign(assign) => Some(PipelineItem::Assigns(assign)),
             Expr::Cmd(cmd) => Some(PipelineItem::Cmd(cmd)),
             Expr::SubShell(sub_shell) => Some(PipelineItem::SubShell(sub_shell)),
+            Expr::Group(group) => Some(PipelineItem::Group(group)),
             Expr::If(if_) => Some(PipelineItem::If(if_)),
+            Expr::For(for_) => Some(PipelineItem::For(for_)),
+            Expr::While(while_) => Some(PipelineItem::While(while_)),
+            Expr::Case(case) => Some(PipelineItem::Case(case)),
             Expr::CondExpr(cond_expr) => Some(PipelineItem::CondExpr(cond_expr)),
+            Expr::Arith(expr) => Some(PipelineItem::Arith(expr)),
             _ => None,
         }
     }
@@ -57,15 +72,86 @@ pub struct Pipeline {
 pub struct Cmd {
     pub assigns: Vec<Assign>,
     pub name_and_args: Vec<Atom>,
-    pub redirect: Option<Redirect>,
-    pub redirect_flags: RedirectFlags,
+    pub redirects: Vec<Redirection>,
 }
 
 #[derive(Debug, Clone)]
 pub struct SubShell {
     pub script: Script,
-    pub redirect: Option<Redirect>,
-    pub redirect_flags: RedirectFlags,
+    pub redirects: Vec<Redirection>,
+}
+
+/// `{ body; }`, which runs in the current shell unlike a subshell.
+#[derive(Debug, Clone)]
+pub struct Group {
+    pub body: Vec<Stmt>,
+    pub redirects: Vec<Redirection>,
+}
+
+/// `name() body` or `function name body`, where the body is a compound command. Redirections
+/// after the body apply to every call.
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub name: Bytes,
+    pub body: Arc<Expr>,
+}
+
+/// `for name in words; do body; done` or `for ((init; cond; step)); do body; done`.
+#[derive(Debug, Clone)]
+pub struct For {
+    pub kind: ForKind,
+    pub body: Vec<Stmt>,
+    /// Redirections after `done`, which apply to the whole loop.
+    pub redirects: Vec<Redirection>,
+}
+
+#[derive(Debug, Clone)]
+pub enum ForKind {
+    /// `for name in words`, without `in` the words are `"$@"`.
+    In {
+        name: Bytes,
+        words: Option<Vec<Atom>>,
+    },
+    /// `for ((init; cond; step))`, each clause is an arithmetic word that is expanded when it is
+    /// evaluated.
+    Arith { init: Atom, cond: Atom, step: Atom },
+}
+
+/// `while cond; do body; done`, or `until cond; do body; done` which loops while `cond` fails.
+#[derive(Debug, Clone)]
+pub struct While {
+    pub cond: Vec<Stmt>,
+    pub body: Vec<Stmt>,
+    pub until: bool,
+    /// Redirections after `done`, which apply to the whole loop.
+    pub redirects: Vec<Redirection>,
+}
+
+/// `case word in pattern | pattern) body ;; ... esac`.
+#[derive(Debug, Clone)]
+pub struct Case {
+    pub word: Atom,
+    pub items: Vec<CaseItem>,
+    /// Redirections after `esac`, which apply to the whole command.
+    pub redirects: Vec<Redirection>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CaseItem {
+    pub patterns: Vec<Atom>,
+    pub body: Vec<Stmt>,
+    pub terminator: CaseTerminator,
+}
+
+/// What happens after the body of a `case` item ran.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CaseTerminator {
+    /// `;;`, or no terminator before `esac`, ends the command.
+    Break,
+    /// `;&` runs the body of the next item without testing its patterns.
+    FallThrough,
+    /// `;;&` tests the patterns of the next items.
+    Continue,
 }
 
 #[derive(Debug, Clone)]
@@ -83,10 +169,103 @@ pub struct If {
     /// - 2n (n is # of elif/then branches)   => n elif/then branches
     /// - 2n + 1                              => n elif/then branches and an else branch
     pub else_parts: Vec<Vec<Stmt>>,
+    /// Redirections after `fi`, which apply to the whole statement.
+    pub redirects: Vec<Redirection>,
 }
 
+/// The contents of a `[[ ... ]]` conditional expression.
 #[derive(Debug, Clone)]
-pub struct CondExpr {}
+pub enum CondExpr {
+    Unary(CondUnaryOp, Atom),
+    Binary(CondBinaryOp, Atom, Atom),
+    /// A lone word, true if it is non-empty.
+    Word(Atom),
+    Not(Box<CondExpr>),
+    And(Box<CondExpr>, Box<CondExpr>),
+    Or(Box<CondExpr>, Box<CondExpr>),
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum CondUnaryOp {
+    /// `-e`
+    Exists,
+    /// `-f`
+    RegularFile,
+    /// `-d`
+    Directory,
+    /// `-x`
+    Executable,
+    /// `-s`
+    NonEmptyFile,
+    /// `-r`
+    Readable,
+    /// `-w`
+    Writable,
+    /// `-z`
+    EmptyString,
+    /// `-n`
+    NonEmptyString,
+}
+
+impl CondUnaryOp {
+    pub fn parse(op: &[u8]) -> Option<Self> {
+        Some(match op {
+            b"-e" => Self::Exists,
+            b"-f" => Self::RegularFile,
+            b"-d" => Self::Directory,
+            b"-x" => Self::Executable,
+            b"-s" => Self::NonEmptyFile,
+            b"-r" => Self::Readable,
+            b"-w" => Self::Writable,
+            b"-z" => Self::EmptyString,
+            b"-n" => Self::NonEmptyString,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum CondBinaryOp {
+    /// `==` or `=`, the right side is a pattern.
+    Match,
+    /// `!=`, the right side is a pattern.
+    NotMatch,
+    /// `=~`, the right side is an extended regular expression.
+    RegexMatch,
+    /// `<`
+    Less,
+    /// `>`
+    Greater,
+    /// `-eq`
+    Eq,
+    /// `-ne`
+    Ne,
+    /// `-lt`
+    Lt,
+    /// `-le`
+    Le,
+    /// `-gt`
+    Gt,
+    /// `-ge`
+    Ge,
+}
+
+impl CondBinaryOp {
+    pub fn parse(op: &[u8]) -> Option<Self> {
+        Some(match op {
+            b"==" | b"=" => Self::Match,
+            b"!=" => Self::NotMatch,
+            b"=~" => Self::RegexMatch,
+            b"-eq" => Self::Eq,
+            b"-ne" => Self::Ne,
+            b"-lt" => Self::Lt,
+            b"-le" => Self::Le,
+            b"-gt" => Self::Gt,
+            b"-ge" => Self::Ge,
+            _ => return None,
+        })
+    }
+}
 
 #[derive(Debug, Clone)]
 pub enum Op {
@@ -99,8 +278,13 @@ pub enum PipelineItem {
     Cmd(Cmd),
     Assigns(Vec<Assign>),
     SubShell(SubShell),
+    Group(Group),
     If(If),
+    For(For),
+    While(While),
+    Case(Case),
     CondExpr(CondExpr),
+    Arith(Atom),
 }
 
 #[derive(Debug, Clone)]
@@ -116,6 +300,10 @@ pub struct RedirectFlags {
     pub stderr: bool,
     pub append: bool,
     pub duplicate_out: bool,
+    /// `<<` and `<<-`, the target is the body of the here-document rather than a file.
+    pub heredoc: bool,
+    /// `<<<`, the target is a word that is fed to stdin, followed by a newline.
+    pub herestring: bool,
 }
 
 impl RedirectFlags {
@@ -126,10 +314,18 @@ impl RedirectFlags {
         }
     }
 
-    pub fn leftleft() -> Self {
+    pub fn heredoc() -> Self {
         Self {
             stdin: true,
-            append: true,
+            heredoc: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn herestring() -> Self {
+        Self {
+            stdin: true,
+            herestring: true,
             ..Default::default()
         }
     }
@@ -166,6 +362,14 @@ impl RedirectFlags {
         }
     }
 
+    pub fn two_right() -> Self {
+        Self {
+            stderr: true,
+            ..Default::default()
+        }
+    }
+
+    /// `2>&1`
     pub fn two_right_and_one() -> Self {
         Self {
             stderr: true,
@@ -174,6 +378,7 @@ impl RedirectFlags {
         }
     }
 
+    /// `1>&2`
     pub fn one_right_and_two() -> Self {
         Self {
             stdout: true,
@@ -181,16 +386,21 @@ impl RedirectFlags {
             ..Default::default()
         }
     }
+}
 
-    pub fn is_empty(&self) -> bool {
-        !(self.append || self.duplicate_out || self.stderr || self.stdin || self.stdout)
-    }
+/// A single redirection, applied in order after the ones before it.
+#[derive(Debug, Clone)]
+pub struct Redirection {
+    pub flags: RedirectFlags,
+    /// `None` for `2>&1` and `1>&2`, which have no target.
+    pub target: Option<Redirect>,
 }
 
 #[derive(Debug, Clone)]
 pub enum Redirect {
     Atom(Atom),
-    PyObject,
+    /// An interpolated Python object, the index into the objects of the template.
+    PyObject(usize),
 }
 
 #[derive(Debug, Clone)]
@@ -201,16 +411,180 @@ pub enum Atom {
 
 #[derive(Debug, Clone)]
 pub enum SimpleAtom {
-    Var(Bytes),
-    VarArgv(u8),
+    Var {
+        name: Bytes,
+        quoted: bool,
+        /// Written as `${name}` rather than `$name`, so brace expansion cannot extend the name.
+        braced: bool,
+    },
+    /// A positional parameter, `$0` is the name of the script.
+    VarArgv {
+        index: usize,
+        quoted: bool,
+    },
     Text(Bytes),
+    /// Text from a quoted string or a Python interpolation, never treated as a pattern.
+    QuotedText(Bytes),
     Asterisk,
     DoubleAsterisk,
     BraceBegin,
     BraceEnd,
     Comma,
-    Tilde,
-    CmdSubst { script: Script, quoted: bool },
+    /// A tilde prefix, holding the login name, `+` or `-` after the `~`.
+    Tilde(Bytes),
+    CmdSubst {
+        script: Script,
+        quoted: bool,
+    },
+    /// `<(script)` if `input`, `>(script)` otherwise. It expands to a `/dev/fd/N` path through
+    /// which the output of the script is read or its input is written.
+    ProcSubst {
+        script: Script,
+        input: bool,
+    },
+    VarExpansion(Box<VarExpansion>),
+    /// An interpolated Python object that is not a string, e.g. a list of arguments.
+    PyObject(usize),
+    /// `$(( expr ))`, the expression is a word that is expanded and then evaluated.
+    Arith {
+        expr: Box<Atom>,
+        quoted: bool,
+    },
+}
+
+/// `${name<op>...}`.
+#[derive(Debug, Clone)]
+pub struct VarExpansion {
+    /// A variable name, a positional parameter or a special parameter.
+    pub name: Bytes,
+    pub op: VarExpansionOp,
+    pub quoted: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum VarExpansionOp {
+    /// `${name:-word}`, with `colon` an empty value counts as unset.
+    Default { word: Atom, colon: bool },
+    /// `${name:=word}`.
+    Assign { word: Atom, colon: bool },
+    /// `${name:?word}`.
+    Error { word: Atom, colon: bool },
+    /// `${name:+word}`.
+    Alternative { word: Atom, colon: bool },
+    /// `${#name}`.
+    Length,
+    /// `${name#pattern}`, or `${name##pattern}` when `longest`.
+    RemovePrefix { pattern: Atom, longest: bool },
+    /// `${name%pattern}`, or `${name%%pattern}` when `longest`.
+    RemoveSuffix { pattern: Atom, longest: bool },
+    /// `${name/pattern/string}`.
+    Replace {
+        pattern: Atom,
+        string: Atom,
+        kind: ReplaceKind,
+    },
+    /// `${name:offset:length}`, where both are arithmetic expressions.
+    Substring { offset: Atom, length: Option<Atom> },
+    /// `${name[index]}`, where the index is an arithmetic expression.
+    Index(Atom),
+    /// `${name[@]}`, or `${name[*]}` which joins the elements like `$*`.
+    Elements { join: bool },
+    /// `${#name[index]}`.
+    IndexLength(Atom),
+    /// `${#name[@]}` or `${#name[*]}`, the number of elements.
+    Count,
+}
+
+impl VarExpansionOp {
+    /// Builds the operation from its operator as lexed, e.g. `:-` or `//`, and its words.
+    pub fn parse(op: &[u8], words: Vec<Atom>) -> Option<Self> {
+        let mut words = words.into_iter();
+        let mut word = || {
+            words
+                .next()
+                .unwrap_or_else(|| Atom::Simple(SimpleAtom::Text(Bytes::new())))
+        };
+        let colon = op.starts_with(b":");
+        Some(match op {
+            b":-" | b"-" => Self::Default {
+                word: word(),
+                colon,
+            },
+            b":=" | b"=" => Self::Assign {
+                word: word(),
+                colon,
+            },
+            b":?" | b"?" => Self::Error {
+                word: word(),
+                colon,
+            },
+            b":+" | b"+" => Self::Alternative {
+                word: word(),
+                colon,
+            },
+            b"#" | b"##" => Self::RemovePrefix {
+                pattern: word(),
+                longest: op.len() == 2,
+            },
+            b"%" | b"%%" => Self::RemoveSuffix {
+                pattern: word(),
+                longest: op.len() == 2,
+            },
+            b"/" | b"//" | b"/#" | b"/%" => Self::Replace {
+                pattern: word(),
+                string: word(),
+                kind: match op {
+                    b"//" => ReplaceKind::All,
+                    b"/#" => ReplaceKind::Prefix,
+                    b"/%" => ReplaceKind::Suffix,
+                    _ => ReplaceKind::First,
+                },
+            },
+            b":" => {
+                let offset = word();
+                let length = words.next();
+                Self::Substring { offset, length }
+            }
+            b"[" => {
+                let subscript = word();
+                // Nothing may follow the `]`.
+                if words.next().is_some_and(
+                    |word| !matches!(word, Atom::Simple(SimpleAtom::Text(text)) if text.is_empty()),
+                ) {
+                    return None;
+                }
+                match subscript {
+                    Atom::Simple(SimpleAtom::Text(text) | SimpleAtom::QuotedText(text))
+                        if matches!(&text[..], b"@" | b"*") =>
+                    {
+                        Self::Elements {
+                            join: &text[..] == b"*",
+                        }
+                    }
+                    Atom::Simple(SimpleAtom::Asterisk) => Self::Elements { join: true },
+                    subscript => Self::Index(subscript),
+                }
+            }
+            b"#[" => match Self::parse(b"[", words.collect())? {
+                Self::Index(subscript) => Self::IndexLength(subscript),
+                _ => Self::Count,
+            },
+            _ => return None,
+        })
+    }
+}
+
+/// Which matches of the pattern `${name/pattern/string}` replaces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplaceKind {
+    /// `/`, the first match.
+    First,
+    /// `//`, every match.
+    All,
+    /// `/#`, a match at the start.
+    Prefix,
+    /// `/%`, a match at the end.
+    Suffix,
 }
 
 #[derive(Debug, Clone)]
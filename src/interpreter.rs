@@ -1,48 +1,107 @@
+mod arith;
+mod brace;
+#[cfg(feature = "coreutils")]
+mod coreutils;
+mod jobs;
+mod options;
+mod pattern;
+mod proc_substs;
+mod pyobject;
+mod run_arith;
 mod run_assigns;
 mod run_async;
 mod run_atom;
 mod run_binary;
+mod run_builtin;
+mod run_case;
 mod run_cmd;
 mod run_cond_expr;
+mod run_for;
+mod run_function;
+mod run_group;
 mod run_if;
+mod run_not;
 mod run_pipeline;
+mod run_proc_subst;
+mod run_redirect;
 mod run_sub_shell;
+mod run_var_expansion;
+mod run_while;
+mod shell;
 
+pub use jobs::*;
+pub use options::*;
+pub use proc_substs::*;
+use run_arith::*;
 use run_assigns::*;
 use run_async::*;
 use run_atom::*;
 use run_binary::*;
+use run_case::*;
 use run_cmd::*;
 use run_cond_expr::*;
+use run_for::*;
+use run_function::*;
+use run_group::*;
 use run_if::*;
+use run_not::*;
 use run_pipeline::*;
 use run_sub_shell::*;
+use run_while::*;
+pub use shell::*;
 
 use std::{
+    fs::File,
+    io::{Read, Write},
     marker::Unpin,
-    os::unix::process::ExitStatusExt,
+    os::{
+        fd::{AsFd, OwnedFd},
+        unix::process::ExitStatusExt,
+    },
     process::{ExitStatus, Stdio},
     sync::Arc,
 };
 
-use tokio::{io, sync::Mutex};
+use futures::future::BoxFuture;
+use tokio::{
+    io,
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::Mutex,
+};
 
 use crate::ast;
 
 pub async fn run_script(
+    shell: &mut Shell,
     script: &ast::Script,
     stdin: Stdin,
     stdout: Stdout,
     stderr: Stdout,
+) -> io::Result<ExitStatus> {
+    let exitstatus = run_stmts(shell, &script.stmts, stdin, stdout, stderr).await?;
+    shell.jobs.finish(shell.job_policy).await?;
+    Ok(exitstatus)
+}
+
+pub async fn run_stmts(
+    shell: &mut Shell,
+    stmts: &[ast::Stmt],
+    stdin: Stdin,
+    stdout: Stdout,
+    stderr: Stdout,
 ) -> io::Result<ExitStatus> {
     let mut exitstatus = ExitStatus::from_raw(0);
-    for stmt in &script.stmts {
-        exitstatus = run_stmt(stmt, stdin.clone(), stdout.clone(), stderr.clone()).await?;
+    for stmt in stmts {
+        exitstatus = run_stmt(shell, stmt, stdin.clone(), stdout.clone(), stderr.clone()).await?;
+        if shell.control.is_some() {
+            break;
+        }
     }
     Ok(exitstatus)
 }
 
 pub async fn run_stmt(
+    shell: &mut Shell,
     stmt: &ast::Stmt,
     stdin: Stdin,
     stdout: Stdout,
@@ -50,55 +109,225 @@ pub async fn run_stmt(
 ) -> io::Result<ExitStatus> {
     let mut exitstatus = ExitStatus::from_raw(0);
     for expr in &stmt.exprs {
-        exitstatus = run_expr(expr, stdin.clone(), stdout.clone(), stderr.clone()).await?;
+        exitstatus =
+            run_boxed_expr(shell, expr, stdin.clone(), stdout.clone(), stderr.clone()).await?;
+        if shell.control.is_some() {
+            break;
+        }
     }
     Ok(exitstatus)
 }
 
 pub async fn run_expr(
+    shell: &mut Shell,
     expr: &ast::Expr,
     stdin: Stdin,
     stdout: Stdout,
     stderr: Stdout,
 ) -> io::Result<ExitStatus> {
-    match expr {
-        ast::Expr::Assign(assigns) => run_assigns(assigns).await,
-        ast::Expr::Binary(binary) => run_binary(binary).await,
-        ast::Expr::Pipeline(pipeline) => run_pipeline(pipeline, stdin, stdout, stderr).await,
-        ast::Expr::Cmd(cmd) => run_cmd(cmd, stdin, stdout, stderr).await,
-        ast::Expr::SubShell(sub_shell) => run_sub_shell(sub_shell).await,
-        ast::Expr::If(if_) => run_if(if_).await,
-        ast::Expr::CondExpr(cond_expr) => run_cond_expr(cond_expr).await,
-        ast::Expr::Async(expr) => run_async(expr).await,
+    let err_out = stderr.clone();
+    let proc_substs = shell.proc_substs.mark(&stdin, &stdout, &stderr);
+    let result = match expr {
+        ast::Expr::Assign(assigns) => run_assigns(shell, assigns, &stderr).await,
+        ast::Expr::Binary(binary) => run_binary(shell, binary, stdin, stdout, stderr).await,
+        ast::Expr::Pipeline(pipeline) => run_pipeline(shell, pipeline, stdin, stdout, stderr).await,
+        ast::Expr::Cmd(cmd) => run_cmd(shell, cmd, stdin, stdout, stderr).await,
+        ast::Expr::SubShell(sub_shell) => {
+            run_sub_shell(shell, sub_shell, stdin, stdout, stderr).await
+        }
+        ast::Expr::Group(group) => run_group(shell, group, stdin, stdout, stderr).await,
+        ast::Expr::Function(function) => run_function_def(shell, function),
+        ast::Expr::If(if_) => run_if(shell, if_, stdin, stdout, stderr).await,
+        ast::Expr::For(for_) => run_for(shell, for_, stdin, stdout, stderr).await,
+        ast::Expr::While(while_) => run_while(shell, while_, stdin, stdout, stderr).await,
+        ast::Expr::Case(case) => run_case(shell, case, stdin, stdout, stderr).await,
+        ast::Expr::CondExpr(cond_expr) => run_cond_expr(shell, cond_expr, stderr).await,
+        ast::Expr::Arith(expr) => run_arith(shell, expr).await,
+        ast::Expr::Async(expr) => run_async(shell, expr, stdin, stdout, stderr).await,
+        ast::Expr::Not(expr) => run_not(shell, expr, stdin, stdout, stderr).await,
+    };
+    shell.proc_substs.finish(proc_substs).await;
+    let exitstatus = match result {
+        Ok(exitstatus) => exitstatus,
+        Err(err) => expansion_error(&err_out, err).await?,
+    };
+    shell.last_status = exitstatus;
+    // A command is a pipeline of its own for `$PIPESTATUS`, while lists and compound
+    // commands leave that of the last pipeline they ran.
+    if !matches!(
+        expr,
+        ast::Expr::Binary(_)
+            | ast::Expr::Pipeline(_)
+            | ast::Expr::Group(_)
+            | ast::Expr::If(_)
+            | ast::Expr::For(_)
+            | ast::Expr::While(_)
+            | ast::Expr::Case(_)
+            | ast::Expr::Not(_)
+    ) {
+        shell.pipestatus = vec![exitstatus];
+    }
+    // With `set -e` a failure ends the script, unless it is tested. The commands of `&&` and
+    // `||` lists are checked on their own.
+    if shell.options.errexit
+        && shell.conditions == 0
+        && shell.control.is_none()
+        && !exitstatus.success()
+        && !matches!(expr, ast::Expr::Binary(_) | ast::Expr::Not(_))
+    {
+        shell.control = Some(Control::Exit);
     }
+    Ok(exitstatus)
+}
+
+/// [`run_expr`] for the commands that run other commands. The future is boxed, which lets the
+/// compiler see that it is `Send` without looking into the recursion.
+pub fn run_boxed_expr<'a>(
+    shell: &'a mut Shell,
+    expr: &'a ast::Expr,
+    stdin: Stdin,
+    stdout: Stdout,
+    stderr: Stdout,
+) -> BoxFuture<'a, io::Result<ExitStatus>> {
+    Box::pin(run_expr(shell, expr, stdin, stdout, stderr))
 }
 
 #[derive(Clone)]
 pub enum Stdin {
     Inherit,
     Pipe(Arc<Mutex<dyn io::AsyncRead + Send + Unpin>>),
+    /// A file descriptor that is handed to child processes as is, e.g. the read end of a pipe.
+    Fd(Arc<OwnedFd>),
 }
 
 #[derive(Clone)]
 pub enum Stdout {
     Inherit,
     Pipe(Arc<Mutex<dyn io::AsyncWrite + Send + Unpin>>),
+    /// A file descriptor that is handed to child processes as is, e.g. the write end of a pipe.
+    Fd(Arc<OwnedFd>),
 }
 
-impl From<&Stdin> for Stdio {
-    fn from(stdin: &Stdin) -> Self {
-        match stdin {
+impl TryFrom<&Stdin> for Stdio {
+    type Error = io::Error;
+
+    fn try_from(stdin: &Stdin) -> io::Result<Self> {
+        Ok(match stdin {
             Stdin::Inherit => Stdio::inherit(),
             Stdin::Pipe(_) => Stdio::piped(),
-        }
+            Stdin::Fd(fd) => fd.try_clone()?.into(),
+        })
     }
 }
 
-impl From<&Stdout> for Stdio {
-    fn from(stdout: &Stdout) -> Self {
-        match stdout {
+impl TryFrom<&Stdout> for Stdio {
+    type Error = io::Error;
+
+    fn try_from(stdout: &Stdout) -> io::Result<Self> {
+        Ok(match stdout {
             Stdout::Inherit => Stdio::inherit(),
             Stdout::Pipe(_) => Stdio::piped(),
+            Stdout::Fd(fd) => fd.try_clone()?.into(),
+        })
+    }
+}
+
+impl Stdin {
+    /// Reads a line for the shell itself, such as for `read`, appending it to `line` without its
+    /// newline. Input is read a byte at a time so that whatever follows the newline is left for
+    /// the next command. Returns whether the line ended with a newline rather than the input.
+    pub async fn read_line(&self, line: &mut Vec<u8>) -> io::Result<bool> {
+        let fd = match self {
+            Stdin::Inherit => io::stdin().as_fd().try_clone_to_owned()?,
+            Stdin::Fd(fd) => fd.try_clone()?,
+            Stdin::Pipe(pipe) => {
+                let mut pipe = pipe.lock().await;
+                let mut byte = [0];
+                while pipe.read(&mut byte).await? != 0 {
+                    if byte[0] == b'\n' {
+                        return Ok(true);
+                    }
+                    line.push(byte[0]);
+                }
+                return Ok(false);
+            }
+        };
+        let mut file = File::from(fd);
+        let (result, buf) = tokio::task::spawn_blocking(move || {
+            let mut buf = vec![];
+            let mut byte = [0];
+            loop {
+                match file.read(&mut byte) {
+                    Ok(0) => return (Ok(false), buf),
+                    Ok(_) if byte[0] == b'\n' => return (Ok(true), buf),
+                    Ok(_) => buf.push(byte[0]),
+                    Err(err) => return (Err(err), buf),
+                }
+            }
+        })
+        .await?;
+        line.extend(buf);
+        result
+    }
+
+    /// Reads the next chunk of input for the shell itself, such as for `cat`. It is empty at the
+    /// end of the input.
+    pub async fn read_chunk(&self) -> io::Result<Vec<u8>> {
+        let mut buf = vec![0; 64 * 1024];
+        let fd = match self {
+            Stdin::Inherit => io::stdin().as_fd().try_clone_to_owned()?,
+            Stdin::Fd(fd) => fd.try_clone()?,
+            Stdin::Pipe(pipe) => {
+                let n = pipe.lock().await.read(&mut buf).await?;
+                buf.truncate(n);
+                return Ok(buf);
+            }
+        };
+        let mut file = File::from(fd);
+        tokio::task::spawn_blocking(move || {
+            let n = file.read(&mut buf)?;
+            buf.truncate(n);
+            Ok(buf)
+        })
+        .await?
+    }
+}
+
+impl Stdout {
+    /// Writes output produced by the shell itself, such as that of a builtin.
+    pub async fn write_all(&self, buf: &[u8]) -> io::Result<()> {
+        match self {
+            // Flushing keeps the output in order with that of the commands that follow.
+            Stdout::Inherit => {
+                let mut stdout = io::stdout();
+                stdout.write_all(buf).await?;
+                stdout.flush().await
+            }
+            _ => self.write_all_redirected(buf).await,
+        }
+    }
+
+    /// Like [`Stdout::write_all`], but inheriting the process' stderr, used for error messages.
+    pub async fn write_all_err(&self, buf: &[u8]) -> io::Result<()> {
+        match self {
+            Stdout::Inherit => {
+                let mut stderr = io::stderr();
+                stderr.write_all(buf).await?;
+                stderr.flush().await
+            }
+            _ => self.write_all_redirected(buf).await,
+        }
+    }
+
+    async fn write_all_redirected(&self, buf: &[u8]) -> io::Result<()> {
+        match self {
+            Stdout::Inherit => unreachable!(),
+            Stdout::Pipe(pipe) => pipe.lock().await.write_all(buf).await,
+            Stdout::Fd(fd) => {
+                let mut file = File::from(fd.try_clone()?);
+                let buf = buf.to_vec();
+                tokio::task::spawn_blocking(move || file.write_all(&buf)).await?
+            }
         }
     }
 }
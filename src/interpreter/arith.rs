@@ -0,0 +1,441 @@
+//! Arithmetic on 64-bit signed integers, as in `$(( ... ))` and `(( ... ))`.
+//!
+//! The expression is parsed after its `$var`, `${...}` and `$(...)` expansions have been
+//! performed, so it only consists of numbers, variable names and C operators.
+
+use crate::interpreter::{Control, Shell};
+use bytes::Bytes;
+
+/// Variables can hold expressions, which are evaluated recursively up to this depth.
+const MAX_DEPTH: usize = 1024;
+
+/// Evaluates an expression, the error is a message suitable for `shl: {expr}: {message}`.
+pub fn eval(shell: &mut Shell, expr: &[u8]) -> Result<i64, String> {
+    Evaluator { shell, depth: 0 }.eval_str(expr)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Num(i64),
+    Name(Bytes),
+    Op(&'static str),
+}
+
+/// Operators, longer ones first so that they are matched before their prefixes.
+const OPS: &[&str] = &[
+    "<<=", ">>=", "**", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+=", "-=",
+    "*=", "/=", "%=", "&=", "^=", "|=", "+", "-", "*", "/", "%", "<", ">", "&", "|", "^", "!", "~",
+    "?", ":", "=", ",", "(", ")",
+];
+
+/// Binary operators from the lowest to the highest precedence, `**` binds tighter still.
+const BINARY_OPS: &[&[&str]] = &[
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", ">", "<=", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+const ASSIGN_OPS: &[&str] = &[
+    "=", "+=", "-=", "*=", "/=", "%=", "<<=", ">>=", "&=", "^=", "|=",
+];
+
+fn tokenize(expr: &[u8]) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < expr.len() {
+        let c = expr[i];
+        if c.is_ascii_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < expr.len() && (expr[i].is_ascii_alphanumeric() || b"#@_".contains(&expr[i])) {
+                i += 1;
+            }
+            tokens.push(Token::Num(parse_number(&expr[start..i])?));
+        } else if c.is_ascii_alphabetic() || c == b'_' {
+            let start = i;
+            while i < expr.len() && (expr[i].is_ascii_alphanumeric() || expr[i] == b'_') {
+                i += 1;
+            }
+            tokens.push(Token::Name(Bytes::copy_from_slice(&expr[start..i])));
+        } else if let Some(op) = OPS.iter().find(|op| expr[i..].starts_with(op.as_bytes())) {
+            tokens.push(Token::Op(op));
+            i += op.len();
+        } else {
+            return Err(format!(
+                "syntax error: invalid arithmetic operator (error token is \"{}\")",
+                String::from_utf8_lossy(&expr[i..])
+            ));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Parses a decimal, `0x` hexadecimal, `0` octal or `base#digits` number.
+fn parse_number(word: &[u8]) -> Result<i64, String> {
+    let invalid = || {
+        format!(
+            "value too great for base (error token is \"{}\")",
+            String::from_utf8_lossy(word)
+        )
+    };
+    let (base, digits) = if let Some((base, digits)) = word.split_once(|&c| c == b'#') {
+        let base = std::str::from_utf8(base)
+            .ok()
+            .and_then(|base| base.parse().ok())
+            .filter(|base| (2..=64).contains(base))
+            .ok_or_else(|| {
+                format!(
+                    "invalid arithmetic base (error token is \"{}\")",
+                    String::from_utf8_lossy(word)
+                )
+            })?;
+        (base, digits)
+    } else if let Some(digits) = word
+        .strip_prefix(b"0x")
+        .or_else(|| word.strip_prefix(b"0X"))
+    {
+        (16, digits)
+    } else if word.len() > 1 && word[0] == b'0' {
+        (8, &word[1..])
+    } else {
+        (10, word)
+    };
+    if digits.is_empty() {
+        return Err(invalid());
+    }
+    let mut value: i64 = 0;
+    for &c in digits {
+        let digit = match c {
+            b'0'..=b'9' => c - b'0',
+            // Up to base 36 letters are case insensitive.
+            b'a'..=b'z' if base <= 36 => c - b'a' + 10,
+            b'A'..=b'Z' if base <= 36 => c - b'A' + 10,
+            b'a'..=b'z' => c - b'a' + 10,
+            b'A'..=b'Z' => c - b'A' + 36,
+            b'@' => 62,
+            b'_' => 63,
+            _ => return Err(invalid()),
+        };
+        if i64::from(digit) >= base {
+            return Err(invalid());
+        }
+        value = value.wrapping_mul(base).wrapping_add(digit.into());
+    }
+    Ok(value)
+}
+
+#[derive(Debug)]
+enum Expr {
+    Num(i64),
+    Var(Bytes),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    /// `name op= value`, where `op` is `=` for a plain assignment.
+    Assign(&'static str, Bytes, Box<Expr>),
+    /// `++name`, `name--`, ...
+    IncDec {
+        name: Bytes,
+        delta: i64,
+        prefix: bool,
+    },
+    Ternary(Box<Expr>, Box<Expr>, Box<Expr>),
+    Comma(Box<Expr>, Box<Expr>),
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    current: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.current)
+    }
+
+    fn peek_n(&self, n: usize) -> Option<&Token> {
+        self.tokens.get(self.current + n)
+    }
+
+    fn matches(&mut self, op: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Op(peeked)) if *peeked == op) {
+            self.current += 1;
+            return true;
+        }
+        false
+    }
+
+    fn matches_any(&mut self, ops: &[&'static str]) -> Option<&'static str> {
+        let Some(Token::Op(peeked)) = self.peek() else {
+            return None;
+        };
+        let op = ops.iter().copied().find(|op| op == peeked)?;
+        self.current += 1;
+        Some(op)
+    }
+
+    fn unexpected(&self) -> String {
+        match self.peek() {
+            Some(token) => format!("syntax error in expression (error token is \"{}\")", token),
+            None => "syntax error: operand expected".to_owned(),
+        }
+    }
+
+    fn parse_comma(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_assign()?;
+        while self.matches(",") {
+            let right = self.parse_assign()?;
+            left = Expr::Comma(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_assign(&mut self) -> Result<Expr, String> {
+        if let (Some(Token::Name(name)), Some(Token::Op(op))) = (self.peek(), self.peek_n(1)) {
+            if let Some(op) = ASSIGN_OPS.iter().copied().find(|assign_op| assign_op == op) {
+                let name = name.clone();
+                self.current += 2;
+                let value = self.parse_assign()?;
+                return Ok(Expr::Assign(op, name, Box::new(value)));
+            }
+        }
+        self.parse_ternary()
+    }
+
+    fn parse_ternary(&mut self) -> Result<Expr, String> {
+        let cond = self.parse_binary(0)?;
+        if !self.matches("?") {
+            return Ok(cond);
+        }
+        let then = self.parse_assign()?;
+        if !self.matches(":") {
+            return Err("`:' expected for conditional expression".to_owned());
+        }
+        let else_ = self.parse_assign()?;
+        Ok(Expr::Ternary(
+            Box::new(cond),
+            Box::new(then),
+            Box::new(else_),
+        ))
+    }
+
+    fn parse_binary(&mut self, level: usize) -> Result<Expr, String> {
+        let Some(ops) = BINARY_OPS.get(level) else {
+            return self.parse_power();
+        };
+        let mut left = self.parse_binary(level + 1)?;
+        while let Some(op) = self.matches_any(ops) {
+            let right = self.parse_binary(level + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    /// `**` is right associative.
+    fn parse_power(&mut self) -> Result<Expr, String> {
+        let base = self.parse_unary()?;
+        if self.matches("**") {
+            let exponent = self.parse_power()?;
+            return Ok(Expr::Binary("**", Box::new(base), Box::new(exponent)));
+        }
+        Ok(base)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        if let Some(op) = self.matches_any(&["++", "--"]) {
+            let Some(Token::Name(name)) = self.peek().cloned() else {
+                return Err(self.unexpected());
+            };
+            self.current += 1;
+            let delta = if op == "++" { 1 } else { -1 };
+            return Ok(Expr::IncDec {
+                name,
+                delta,
+                prefix: true,
+            });
+        }
+        if let Some(op) = self.matches_any(&["-", "+", "!", "~"]) {
+            let operand = self.parse_unary()?;
+            return Ok(Expr::Unary(op, Box::new(operand)));
+        }
+        self.parse_postfix()
+    }
+
+    fn parse_postfix(&mut self) -> Result<Expr, String> {
+        match self.peek().cloned() {
+            Some(Token::Num(value)) => {
+                self.current += 1;
+                Ok(Expr::Num(value))
+            }
+            Some(Token::Name(name)) => {
+                self.current += 1;
+                match self.matches_any(&["++", "--"]) {
+                    Some(op) => Ok(Expr::IncDec {
+                        name,
+                        delta: if op == "++" { 1 } else { -1 },
+                        prefix: false,
+                    }),
+                    None => Ok(Expr::Var(name)),
+                }
+            }
+            Some(Token::Op("(")) => {
+                self.current += 1;
+                let expr = self.parse_comma()?;
+                if !self.matches(")") {
+                    return Err("missing `)'".to_owned());
+                }
+                Ok(expr)
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::Num(value) => write!(f, "{value}"),
+            Token::Name(name) => f.write_str(&String::from_utf8_lossy(name)),
+            Token::Op(op) => f.write_str(op),
+        }
+    }
+}
+
+struct Evaluator<'a> {
+    shell: &'a mut Shell,
+    depth: usize,
+}
+
+impl Evaluator<'_> {
+    fn eval_str(&mut self, expr: &[u8]) -> Result<i64, String> {
+        let tokens = tokenize(expr)?;
+        // An empty expression is zero.
+        if tokens.is_empty() {
+            return Ok(0);
+        }
+        let mut parser = Parser { tokens, current: 0 };
+        let expr = parser.parse_comma()?;
+        if parser.peek().is_some() {
+            return Err(parser.unexpected());
+        }
+        self.eval(&expr)
+    }
+
+    /// The value of a variable, which is itself evaluated as an expression.
+    fn var(&mut self, name: &[u8]) -> Result<i64, String> {
+        let Some(value) = self.shell.var(name) else {
+            if self.shell.options.nounset {
+                self.shell.control = Some(Control::Exit);
+                return Err(format!(
+                    "{}: unbound variable",
+                    String::from_utf8_lossy(name)
+                ));
+            }
+            return Ok(0);
+        };
+        if self.depth >= MAX_DEPTH {
+            return Err("expression recursion level exceeded".to_owned());
+        }
+        self.depth += 1;
+        let result = self.eval_str(&value);
+        self.depth -= 1;
+        result
+    }
+
+    fn set_var(&mut self, name: &Bytes, value: i64) {
+        self.shell.set_var(name.clone(), value.to_string().into());
+    }
+
+    fn eval(&mut self, expr: &Expr) -> Result<i64, String> {
+        Ok(match expr {
+            Expr::Num(value) => *value,
+            Expr::Var(name) => self.var(name)?,
+            Expr::Unary(op, operand) => {
+                let operand = self.eval(operand)?;
+                match *op {
+                    "-" => operand.wrapping_neg(),
+                    "+" => operand,
+                    "!" => (operand == 0).into(),
+                    _ => !operand,
+                }
+            }
+            Expr::Binary("&&", left, right) => {
+                (self.eval(left)? != 0 && self.eval(right)? != 0).into()
+            }
+            Expr::Binary("||", left, right) => {
+                (self.eval(left)? != 0 || self.eval(right)? != 0).into()
+            }
+            Expr::Binary(op, left, right) => {
+                let left = self.eval(left)?;
+                let right = self.eval(right)?;
+                binary(op, left, right)?
+            }
+            Expr::Assign(op, name, value) => {
+                let value = self.eval(value)?;
+                let value = match op.strip_suffix('=').filter(|op| !op.is_empty()) {
+                    Some(op) => binary(op, self.var(name)?, value)?,
+                    None => value,
+                };
+                self.set_var(name, value);
+                value
+            }
+            Expr::IncDec {
+                name,
+                delta,
+                prefix,
+            } => {
+                let old = self.var(name)?;
+                let new = old.wrapping_add(*delta);
+                self.set_var(name, new);
+                if *prefix {
+                    new
+                } else {
+                    old
+                }
+            }
+            Expr::Ternary(cond, then, else_) => {
+                if self.eval(cond)? != 0 {
+                    self.eval(then)?
+                } else {
+                    self.eval(else_)?
+                }
+            }
+            Expr::Comma(left, right) => {
+                self.eval(left)?;
+                self.eval(right)?
+            }
+        })
+    }
+}
+
+fn binary(op: &str, left: i64, right: i64) -> Result<i64, String> {
+    Ok(match op {
+        "+" => left.wrapping_add(right),
+        "-" => left.wrapping_sub(right),
+        "*" => left.wrapping_mul(right),
+        "/" | "%" if right == 0 => return Err("division by 0".to_owned()),
+        "/" => left.wrapping_div(right),
+        "%" => left.wrapping_rem(right),
+        "**" if right < 0 => return Err("exponent less than 0".to_owned()),
+        "**" => left.wrapping_pow(right.try_into().unwrap_or(u32::MAX)),
+        "<<" => left.wrapping_shl(right as u32),
+        ">>" => left.wrapping_shr(right as u32),
+        "&" => left & right,
+        "|" => left | right,
+        "^" => left ^ right,
+        "==" => (left == right).into(),
+        "!=" => (left != right).into(),
+        "<" => (left < right).into(),
+        ">" => (left > right).into(),
+        "<=" => (left <= right).into(),
+        ">=" => (left >= right).into(),
+        _ => unreachable!("unknown binary operator {op}"),
+    })
+}
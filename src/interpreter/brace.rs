@@ -0,0 +1,152 @@
+//! Brace expansion, `a{b,c}` and `{1..10}`, which happens before any other expansion.
+
+use crate::{ast::SimpleAtom, interpreter::is_var_name};
+use bytes::Bytes;
+
+/// Expands the braces in a word to the words they stand for, in order. Braces that do not form
+/// a valid expansion are left in place and expand to themselves.
+///
+/// Since this happens before parameter expansion, the text after a `$name` continues the name,
+/// as in `$x{a,b}` which expands to `$xa $xb`.
+pub fn expand(atoms: &[SimpleAtom]) -> Vec<Vec<SimpleAtom>> {
+    expand_from(atoms.to_vec(), 0)
+        .into_iter()
+        .map(join_var_names)
+        .collect()
+}
+
+/// Appends the name characters at the start of the text after a `$name` to the name.
+fn join_var_names(word: Vec<SimpleAtom>) -> Vec<SimpleAtom> {
+    let mut joined: Vec<SimpleAtom> = vec![];
+    for atom in word {
+        if let (
+            Some(SimpleAtom::Var {
+                name,
+                quoted: false,
+                braced: false,
+            }),
+            SimpleAtom::Text(text),
+        ) = (joined.last_mut(), &atom)
+        {
+            let len = text
+                .iter()
+                .take_while(|c| c.is_ascii_alphanumeric() || **c == b'_')
+                .count();
+            if is_var_name(name) && len > 0 {
+                *name = [&name[..], &text[..len]].concat().into();
+                if len < text.len() {
+                    joined.push(SimpleAtom::Text(text.slice(len..)));
+                }
+                continue;
+            }
+        }
+        joined.push(atom);
+    }
+    joined
+}
+
+fn expand_from(atoms: Vec<SimpleAtom>, start: usize) -> Vec<Vec<SimpleAtom>> {
+    for open in start..atoms.len() {
+        if !matches!(atoms[open], SimpleAtom::BraceBegin) {
+            continue;
+        }
+        let Some((len, alternatives)) = alternatives(&atoms[open..]) else {
+            continue;
+        };
+        let mut words = vec![];
+        for alternative in alternatives {
+            let word = [&atoms[..open], &alternative, &atoms[open + len..]].concat();
+            // The alternative itself may contain more braces.
+            words.extend(expand_from(word, open));
+        }
+        return words;
+    }
+    vec![atoms]
+}
+
+/// Parses the brace expression at the start of `atoms`, returning its length and alternatives.
+fn alternatives(atoms: &[SimpleAtom]) -> Option<(usize, Vec<Vec<SimpleAtom>>)> {
+    let mut depth = 0;
+    let mut commas = vec![];
+    let mut close = None;
+    for (i, atom) in atoms.iter().enumerate() {
+        match atom {
+            SimpleAtom::BraceBegin => depth += 1,
+            SimpleAtom::BraceEnd => {
+                depth -= 1;
+                if depth == 0 {
+                    close = Some(i);
+                    break;
+                }
+            }
+            SimpleAtom::Comma if depth == 1 => commas.push(i),
+            _ => {}
+        }
+    }
+    let close = close?;
+    if commas.is_empty() {
+        let [SimpleAtom::Text(text)] = &atoms[1..close] else {
+            return None;
+        };
+        let words = range(text)?;
+        let alternatives = words.into_iter().map(|word| vec![SimpleAtom::Text(word)]);
+        return Some((close + 1, alternatives.collect()));
+    }
+    let mut alternatives = vec![];
+    let mut begin = 1;
+    for &end in commas.iter().chain([&close]) {
+        alternatives.push(atoms[begin..end].to_vec());
+        begin = end + 1;
+    }
+    Some((close + 1, alternatives))
+}
+
+/// Expands `start..end[..step]`, where both ends are integers or single letters. Integers are
+/// zero padded to the same width if either end has a leading zero.
+fn range(text: &[u8]) -> Option<Vec<Bytes>> {
+    let text = str::from_utf8(text).ok()?;
+    let (start, end, step) = match text.split("..").collect::<Vec<_>>()[..] {
+        [start, end] => (start, end, 1),
+        [start, end, step] => (start, end, step.parse::<i64>().ok()?.abs().max(1)),
+        _ => return None,
+    };
+    if let (Ok(start_int), Ok(end_int)) = (start.parse::<i64>(), end.parse::<i64>()) {
+        let width = if has_leading_zero(start) || has_leading_zero(end) {
+            start.len().max(end.len())
+        } else {
+            0
+        };
+        return Some(
+            steps(start_int, end_int, step)
+                .map(|n| format!("{n:0width$}").into())
+                .collect(),
+        );
+    }
+    let ([start], [end]) = (start.as_bytes(), end.as_bytes()) else {
+        return None;
+    };
+    if !start.is_ascii_alphabetic() || !end.is_ascii_alphabetic() {
+        return None;
+    }
+    Some(
+        steps(*start as i64, *end as i64, step)
+            .map(|c| Bytes::copy_from_slice(&[c as u8]))
+            .collect(),
+    )
+}
+
+fn steps(start: i64, end: i64, step: i64) -> impl Iterator<Item = i64> {
+    let step = if start <= end { step } else { -step };
+    std::iter::successors(Some(start), move |n| n.checked_add(step)).take_while(move |n| {
+        if step > 0 {
+            *n <= end
+        } else {
+            *n >= end
+        }
+    })
+}
+
+fn has_leading_zero(int: &str) -> bool {
+    let digits = int.trim_start_matches('-');
+    digits.len() > 1 && digits.starts_with('0')
+}
@@ -0,0 +1,1312 @@
+use crate::interpreter::{Shell, Stdin, Stdout};
+use bytes::Bytes;
+use std::{
+    ffi::{CString, OsStr},
+    fmt::Display,
+    os::unix::{ffi::OsStrExt, fs::PermissionsExt},
+    path::{Path, PathBuf},
+    str,
+};
+use tokio::{
+    fs,
+    io::{self, AsyncReadExt},
+};
+
+/// The size in which the utilities read their input and write their output.
+const CHUNK: usize = 64 * 1024;
+
+/// Common utilities that the shell runs in-process rather than from `$PATH`, with the
+/// `coreutils` feature.
+#[derive(Debug, Copy, Clone)]
+pub enum Coreutil {
+    Echo,
+    Cat,
+    Ls,
+    Mkdir,
+    Rm,
+    Mv,
+    Cp,
+    Touch,
+    Which,
+    Basename,
+    Dirname,
+    Seq,
+    Yes,
+    Head,
+    Tail,
+    Wc,
+}
+
+impl Coreutil {
+    pub fn parse(name: &[u8]) -> Option<Self> {
+        Some(match name {
+            b"echo" => Self::Echo,
+            b"cat" => Self::Cat,
+            b"ls" => Self::Ls,
+            b"mkdir" => Self::Mkdir,
+            b"rm" => Self::Rm,
+            b"mv" => Self::Mv,
+            b"cp" => Self::Cp,
+            b"touch" => Self::Touch,
+            b"which" => Self::Which,
+            b"basename" => Self::Basename,
+            b"dirname" => Self::Dirname,
+            b"seq" => Self::Seq,
+            b"yes" => Self::Yes,
+            b"head" => Self::Head,
+            b"tail" => Self::Tail,
+            b"wc" => Self::Wc,
+            _ => return None,
+        })
+    }
+}
+
+pub async fn run_coreutil(
+    shell: &Shell,
+    util: Coreutil,
+    args: &[Bytes],
+    stdin: &Stdin,
+    stdout: &Stdout,
+    stderr: &Stdout,
+) -> io::Result<i32> {
+    let mut out = Output::new(stdout);
+    let result = match util {
+        Coreutil::Echo => run_echo(args, &mut out).await,
+        Coreutil::Cat => run_cat(shell, args, stdin, &mut out, stderr).await,
+        Coreutil::Ls => run_ls(shell, args, &mut out, stderr).await,
+        Coreutil::Mkdir => run_mkdir(shell, args, stderr).await,
+        Coreutil::Rm => run_rm(shell, args, stderr).await,
+        Coreutil::Mv => run_mv(shell, args, stderr).await,
+        Coreutil::Cp => run_cp(shell, args, stderr).await,
+        Coreutil::Touch => run_touch(shell, args, stderr).await,
+        Coreutil::Which => run_which(shell, args, &mut out, stderr).await,
+        Coreutil::Basename => run_basename(args, &mut out, stderr).await,
+        Coreutil::Dirname => run_dirname(args, &mut out, stderr).await,
+        Coreutil::Seq => run_seq(args, &mut out, stderr).await,
+        Coreutil::Yes => run_yes(args, &mut out).await,
+        Coreutil::Head => run_head(shell, args, stdin, &mut out, stderr).await,
+        Coreutil::Tail => run_tail(shell, args, stdin, &mut out, stderr).await,
+        Coreutil::Wc => run_wc(shell, args, stdin, &mut out, stderr).await,
+    };
+    let result = match result {
+        Ok(code) => out.flush().await.map(|()| code),
+        Err(err) => Err(err),
+    };
+    match result {
+        // The reader went away, the status is that of a process killed by SIGPIPE.
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(128 + libc::SIGPIPE),
+        result => result,
+    }
+}
+
+/// Buffers the output of a utility, so that it is not written a line at a time.
+struct Output<'a> {
+    stdout: &'a Stdout,
+    buf: Vec<u8>,
+}
+
+impl<'a> Output<'a> {
+    fn new(stdout: &'a Stdout) -> Self {
+        Self {
+            stdout,
+            buf: vec![],
+        }
+    }
+
+    async fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() >= CHUNK {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            self.stdout.write_all(&self.buf).await?;
+            self.buf.clear();
+        }
+        Ok(())
+    }
+
+    /// Reports an error after the output so far, to keep the two in order.
+    async fn error(
+        &mut self,
+        stderr: &Stdout,
+        util: &str,
+        message: impl Display,
+    ) -> io::Result<()> {
+        self.flush().await?;
+        fail(stderr, util, message).await
+    }
+}
+
+async fn fail(stderr: &Stdout, util: &str, message: impl Display) -> io::Result<()> {
+    stderr
+        .write_all_err(format!("shl: {util}: {message}\n").as_bytes())
+        .await
+}
+
+/// The message of an error without the ` (os error N)` that is appended to it.
+fn describe(err: &io::Error) -> String {
+    let message = err.to_string();
+    match message.find(" (os error") {
+        Some(i) => message[..i].to_owned(),
+        None => message,
+    }
+}
+
+fn lossy(arg: &[u8]) -> String {
+    String::from_utf8_lossy(arg).into_owned()
+}
+
+fn resolve(shell: &Shell, arg: &[u8]) -> PathBuf {
+    shell.cwd.join(OsStr::from_bytes(arg))
+}
+
+/// The options given before the operands, in order. Short options may be combined, as in `-rf`,
+/// and those in `with_value` take the rest of the word or the next word as their value.
+struct Opts<'a> {
+    flags: Vec<(u8, Bytes)>,
+    operands: &'a [Bytes],
+}
+
+fn parse_opts<'a>(args: &'a [Bytes], flags: &[u8], with_value: &[u8]) -> Result<Opts<'a>, String> {
+    let mut opts = vec![];
+    let mut i = 0;
+    while let Some(arg) = args.get(i) {
+        // A lone `-` is stdin and `-1` a negative number, both are operands.
+        if arg.len() < 2 || arg[0] != b'-' || (arg[1].is_ascii_digit() && !flags.contains(&arg[1]))
+        {
+            break;
+        }
+        i += 1;
+        if &arg[..] == b"--" {
+            break;
+        }
+        for (j, &flag) in arg.iter().enumerate().skip(1) {
+            if with_value.contains(&flag) {
+                let value = if j + 1 < arg.len() {
+                    arg.slice(j + 1..)
+                } else {
+                    let Some(value) = args.get(i) else {
+                        return Err(format!("option requires an argument -- '{}'", flag as char));
+                    };
+                    i += 1;
+                    value.clone()
+                };
+                opts.push((flag, value));
+                break;
+            }
+            if !flags.contains(&flag) {
+                return Err(format!("invalid option -- '{}'", flag as char));
+            }
+            opts.push((flag, Bytes::new()));
+        }
+    }
+    Ok(Opts {
+        flags: opts,
+        operands: &args[i..],
+    })
+}
+
+impl Opts<'_> {
+    fn has(&self, flag: u8) -> bool {
+        self.flags.iter().any(|(f, _)| *f == flag)
+    }
+}
+
+/// A file or stdin, read a chunk at a time.
+enum Input<'a> {
+    Stdin(&'a Stdin),
+    File(fs::File),
+}
+
+impl Input<'_> {
+    async fn open<'a>(shell: &Shell, stdin: &'a Stdin, operand: &[u8]) -> io::Result<Input<'a>> {
+        if operand == b"-" {
+            return Ok(Input::Stdin(stdin));
+        }
+        let path = resolve(shell, operand);
+        if fs::metadata(&path).await?.is_dir() {
+            return Err(io::Error::from_raw_os_error(libc::EISDIR));
+        }
+        Ok(Input::File(fs::File::open(path).await?))
+    }
+
+    /// The next chunk of input, which is empty at the end.
+    async fn read(&mut self) -> io::Result<Vec<u8>> {
+        match self {
+            Input::Stdin(stdin) => stdin.read_chunk().await,
+            Input::File(file) => {
+                let mut buf = vec![0; CHUNK];
+                let n = file.read(&mut buf).await?;
+                buf.truncate(n);
+                Ok(buf)
+            }
+        }
+    }
+
+    async fn read_to_end(&mut self) -> io::Result<Vec<u8>> {
+        let mut content = vec![];
+        loop {
+            let chunk = self.read().await?;
+            if chunk.is_empty() {
+                return Ok(content);
+            }
+            content.extend(chunk);
+        }
+    }
+}
+
+/// The operands of a utility that reads files, stdin if there are none.
+fn input_operands(operands: &[Bytes]) -> Vec<Bytes> {
+    if operands.is_empty() {
+        vec![Bytes::from_static(b"-")]
+    } else {
+        operands.to_vec()
+    }
+}
+
+/// `echo [-neE] [arg...]`, where `-n` leaves out the newline and `-e` interprets escapes.
+async fn run_echo(args: &[Bytes], out: &mut Output<'_>) -> io::Result<i32> {
+    let mut newline = true;
+    let mut escapes = false;
+    let mut args = args;
+    while let [first, rest @ ..] = args {
+        if first.len() < 2 || first[0] != b'-' || !first[1..].iter().all(|c| b"neE".contains(c)) {
+            break;
+        }
+        for &flag in &first[1..] {
+            match flag {
+                b'n' => newline = false,
+                b'e' => escapes = true,
+                _ => escapes = false,
+            }
+        }
+        args = rest;
+    }
+    let mut line = vec![];
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            line.push(b' ');
+        }
+        if !escapes {
+            line.extend_from_slice(arg);
+        } else if !unescape(arg, &mut line) {
+            // `\c` ends the output.
+            out.write(&line).await?;
+            return Ok(0);
+        }
+    }
+    if newline {
+        line.push(b'\n');
+    }
+    out.write(&line).await?;
+    Ok(0)
+}
+
+/// Appends `arg` with the escapes of `echo -e` interpreted, returns false at a `\c`.
+fn unescape(arg: &[u8], out: &mut Vec<u8>) -> bool {
+    let mut i = 0;
+    while i < arg.len() {
+        let c = arg[i];
+        i += 1;
+        if c != b'\\' || i == arg.len() {
+            out.push(c);
+            continue;
+        }
+        let escape = arg[i];
+        i += 1;
+        let digits = |i: usize, max: usize, radix: u32| {
+            let end = arg[i..]
+                .iter()
+                .take(max)
+                .take_while(|c| (**c as char).is_digit(radix))
+                .count();
+            let value = str::from_utf8(&arg[i..i + end])
+                .ok()
+                .and_then(|digits| u32::from_str_radix(digits, radix).ok())
+                .unwrap_or(0);
+            (value as u8, end)
+        };
+        match escape {
+            b'a' => out.push(0x07),
+            b'b' => out.push(0x08),
+            b'c' => return false,
+            b'e' | b'E' => out.push(0x1b),
+            b'f' => out.push(0x0c),
+            b'n' => out.push(b'\n'),
+            b'r' => out.push(b'\r'),
+            b't' => out.push(b'\t'),
+            b'v' => out.push(0x0b),
+            b'\\' => out.push(b'\\'),
+            b'0' => {
+                let (value, len) = digits(i, 3, 8);
+                out.push(value);
+                i += len;
+            }
+            b'x' => match digits(i, 2, 16) {
+                (_, 0) => out.extend_from_slice(b"\\x"),
+                (value, len) => {
+                    out.push(value);
+                    i += len;
+                }
+            },
+            escape => out.extend_from_slice(&[b'\\', escape]),
+        }
+    }
+    true
+}
+
+/// `cat [-n] [file...]`, where `-n` numbers the lines.
+async fn run_cat(
+    shell: &Shell,
+    args: &[Bytes],
+    stdin: &Stdin,
+    out: &mut Output<'_>,
+    stderr: &Stdout,
+) -> io::Result<i32> {
+    let opts = match parse_opts(args, b"nu", b"") {
+        Ok(opts) => opts,
+        Err(message) => {
+            fail(stderr, "cat", message).await?;
+            return Ok(1);
+        }
+    };
+    let number = opts.has(b'n');
+    let mut line_number = 0;
+    let mut at_line_start = true;
+    let mut code = 0;
+    for operand in input_operands(opts.operands) {
+        let mut input = match Input::open(shell, stdin, &operand).await {
+            Ok(input) => input,
+            Err(err) => {
+                let message = format!("{}: {}", lossy(&operand), describe(&err));
+                out.error(stderr, "cat", message).await?;
+                code = 1;
+                continue;
+            }
+        };
+        loop {
+            let chunk = input.read().await?;
+            if chunk.is_empty() {
+                break;
+            }
+            if !number {
+                out.write(&chunk).await?;
+                continue;
+            }
+            let mut numbered = vec![];
+            for &c in &chunk {
+                if at_line_start {
+                    line_number += 1;
+                    numbered.extend_from_slice(format!("{line_number:6}\t").as_bytes());
+                }
+                numbered.push(c);
+                at_line_start = c == b'\n';
+            }
+            out.write(&numbered).await?;
+        }
+    }
+    Ok(code)
+}
+
+/// `ls [-aAd1] [file...]`, one name per line in byte order, files before directories.
+async fn run_ls(
+    shell: &Shell,
+    args: &[Bytes],
+    out: &mut Output<'_>,
+    stderr: &Stdout,
+) -> io::Result<i32> {
+    let opts = match parse_opts(args, b"aAd1", b"") {
+        Ok(opts) => opts,
+        Err(message) => {
+            fail(stderr, "ls", message).await?;
+            return Ok(2);
+        }
+    };
+    let (all, almost_all, directory) = (opts.has(b'a'), opts.has(b'A'), opts.has(b'd'));
+    let operands = if opts.operands.is_empty() {
+        vec![Bytes::from_static(b".")]
+    } else {
+        opts.operands.to_vec()
+    };
+    let mut code = 0;
+    let mut files = vec![];
+    let mut dirs = vec![];
+    for operand in &operands {
+        match fs::metadata(resolve(shell, operand)).await {
+            Ok(metadata) if metadata.is_dir() && !directory => dirs.push(operand.clone()),
+            Ok(_) => files.push(operand.clone()),
+            // A dangling symlink is still listed.
+            Err(_) if fs::symlink_metadata(resolve(shell, operand)).await.is_ok() => {
+                files.push(operand.clone())
+            }
+            Err(err) => {
+                let message = format!("cannot access '{}': {}", lossy(operand), describe(&err));
+                out.error(stderr, "ls", message).await?;
+                code = 2;
+            }
+        }
+    }
+    files.sort();
+    dirs.sort();
+    for file in &files {
+        out.write(&[&file[..], b"\n"].concat()).await?;
+    }
+    for (i, dir) in dirs.iter().enumerate() {
+        let mut names = vec![];
+        if all {
+            names.extend([b".".to_vec(), b"..".to_vec()]);
+        }
+        let mut entries = match fs::read_dir(resolve(shell, dir)).await {
+            Ok(entries) => entries,
+            Err(err) => {
+                let message = format!("cannot open directory '{}': {}", lossy(dir), describe(&err));
+                out.error(stderr, "ls", message).await?;
+                code = 2;
+                continue;
+            }
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().as_bytes().to_vec();
+            if all || almost_all || !name.starts_with(b".") {
+                names.push(name);
+            }
+        }
+        names.sort();
+        if i > 0 || !files.is_empty() {
+            out.write(b"\n").await?;
+        }
+        if operands.len() > 1 {
+            out.write(&[&dir[..], b":\n"].concat()).await?;
+        }
+        for name in names {
+            out.write(&[&name[..], b"\n"].concat()).await?;
+        }
+    }
+    Ok(code)
+}
+
+/// `mkdir [-p] dir...`, where `-p` also creates the parents and accepts existing directories.
+async fn run_mkdir(shell: &Shell, args: &[Bytes], stderr: &Stdout) -> io::Result<i32> {
+    let opts = match parse_opts(args, b"p", b"") {
+        Ok(opts) if opts.operands.is_empty() => Err("missing operand".to_owned()),
+        result => result,
+    };
+    let opts = match opts {
+        Ok(opts) => opts,
+        Err(message) => {
+            fail(stderr, "mkdir", message).await?;
+            return Ok(1);
+        }
+    };
+    let mut code = 0;
+    for operand in opts.operands {
+        let path = resolve(shell, operand);
+        let result = if opts.has(b'p') {
+            fs::create_dir_all(path).await
+        } else {
+            fs::create_dir(path).await
+        };
+        if let Err(err) = result {
+            let message = format!(
+                "cannot create directory '{}': {}",
+                lossy(operand),
+                describe(&err)
+            );
+            fail(stderr, "mkdir", message).await?;
+            code = 1;
+        }
+    }
+    Ok(code)
+}
+
+/// `rm [-rRf] file...`, where `-r` removes directories with their contents and `-f` ignores
+/// files that do not exist.
+async fn run_rm(shell: &Shell, args: &[Bytes], stderr: &Stdout) -> io::Result<i32> {
+    let opts = match parse_opts(args, b"rRf", b"") {
+        Ok(opts) if opts.operands.is_empty() && !opts.has(b'f') => {
+            Err("missing operand".to_owned())
+        }
+        result => result,
+    };
+    let opts = match opts {
+        Ok(opts) => opts,
+        Err(message) => {
+            fail(stderr, "rm", message).await?;
+            return Ok(1);
+        }
+    };
+    let recursive = opts.has(b'r') || opts.has(b'R');
+    let force = opts.has(b'f');
+    let mut code = 0;
+    for operand in opts.operands {
+        let path = resolve(shell, operand);
+        let name = lossy(operand);
+        let last = operand.rsplit(|&c| c == b'/').find(|part| !part.is_empty());
+        let result = match fs::symlink_metadata(&path).await {
+            _ if recursive && last.is_none() => {
+                let message = format!("it is dangerous to operate recursively on '{name}'");
+                fail(stderr, "rm", message).await?;
+                code = 1;
+                continue;
+            }
+            _ if matches!(last, Some(b"." | b"..")) => {
+                let message =
+                    format!("refusing to remove '.' or '..' directory: skipping '{name}'");
+                fail(stderr, "rm", message).await?;
+                code = 1;
+                continue;
+            }
+            Err(err) if force && err.kind() == io::ErrorKind::NotFound => continue,
+            Ok(metadata) if metadata.is_dir() && !recursive => {
+                Err(io::Error::from_raw_os_error(libc::EISDIR))
+            }
+            Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(&path).await,
+            Ok(_) => fs::remove_file(&path).await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            let message = format!("cannot remove '{name}': {}", describe(&err));
+            fail(stderr, "rm", message).await?;
+            code = 1;
+        }
+    }
+    Ok(code)
+}
+
+/// Splits the operands of `mv` and `cp` into sources and a destination, which is a directory
+/// that they go into when it exists or when there are several sources.
+async fn sources_and_dest<'a>(
+    shell: &Shell,
+    util: &str,
+    operands: &'a [Bytes],
+    stderr: &Stdout,
+) -> io::Result<Option<Vec<(&'a Bytes, PathBuf, PathBuf)>>> {
+    let [sources @ .., dest] = operands else {
+        fail(stderr, util, "missing file operand").await?;
+        return Ok(None);
+    };
+    if sources.is_empty() {
+        let message = format!("missing destination file operand after '{}'", lossy(dest));
+        fail(stderr, util, message).await?;
+        return Ok(None);
+    }
+    let dest_path = resolve(shell, dest);
+    let into_dir = fs::metadata(&dest_path)
+        .await
+        .is_ok_and(|metadata| metadata.is_dir());
+    if sources.len() > 1 && !into_dir {
+        let message = format!("target '{}' is not a directory", lossy(dest));
+        fail(stderr, util, message).await?;
+        return Ok(None);
+    }
+    let mut pairs = vec![];
+    for source in sources {
+        let from = resolve(shell, source);
+        let to = match from.file_name() {
+            Some(name) if into_dir => dest_path.join(name),
+            _ => dest_path.clone(),
+        };
+        pairs.push((source, from, to));
+    }
+    Ok(Some(pairs))
+}
+
+/// `mv source... dest`, which copies and removes when a rename would cross file systems.
+async fn run_mv(shell: &Shell, args: &[Bytes], stderr: &Stdout) -> io::Result<i32> {
+    let opts = match parse_opts(args, b"f", b"") {
+        Ok(opts) => opts,
+        Err(message) => {
+            fail(stderr, "mv", message).await?;
+            return Ok(1);
+        }
+    };
+    let Some(pairs) = sources_and_dest(shell, "mv", opts.operands, stderr).await? else {
+        return Ok(1);
+    };
+    let mut code = 0;
+    for (source, from, to) in pairs {
+        let result = match fs::rename(&from, &to).await {
+            Err(err) if err.raw_os_error() == Some(libc::EXDEV) => {
+                move_across(from.clone(), to.clone()).await
+            }
+            result => result,
+        };
+        if let Err(err) = result {
+            let message = format!(
+                "cannot move '{}' to '{}': {}",
+                lossy(source),
+                to.display(),
+                describe(&err)
+            );
+            fail(stderr, "mv", message).await?;
+            code = 1;
+        }
+    }
+    Ok(code)
+}
+
+async fn move_across(from: PathBuf, to: PathBuf) -> io::Result<()> {
+    tokio::task::spawn_blocking(move || {
+        copy_tree(&from, &to)?;
+        if std::fs::symlink_metadata(&from)?.is_dir() {
+            std::fs::remove_dir_all(&from)
+        } else {
+            std::fs::remove_file(&from)
+        }
+    })
+    .await?
+}
+
+/// Copies a file, symlink or directory with its contents, keeping permissions.
+fn copy_tree(from: &Path, to: &Path) -> io::Result<()> {
+    let metadata = std::fs::symlink_metadata(from)?;
+    if metadata.is_symlink() {
+        std::os::unix::fs::symlink(std::fs::read_link(from)?, to)
+    } else if metadata.is_dir() {
+        match std::fs::create_dir(to) {
+            Err(err) if err.kind() != io::ErrorKind::AlreadyExists => return Err(err),
+            _ => {}
+        }
+        for entry in std::fs::read_dir(from)? {
+            let entry = entry?;
+            copy_tree(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        std::fs::set_permissions(to, metadata.permissions())
+    } else {
+        std::fs::copy(from, to).map(|_| ())
+    }
+}
+
+/// `cp [-rR] source... dest`, where `-r` copies directories with their contents.
+async fn run_cp(shell: &Shell, args: &[Bytes], stderr: &Stdout) -> io::Result<i32> {
+    let opts = match parse_opts(args, b"rRf", b"") {
+        Ok(opts) => opts,
+        Err(message) => {
+            fail(stderr, "cp", message).await?;
+            return Ok(1);
+        }
+    };
+    let recursive = opts.has(b'r') || opts.has(b'R');
+    let Some(pairs) = sources_and_dest(shell, "cp", opts.operands, stderr).await? else {
+        return Ok(1);
+    };
+    let mut code = 0;
+    for (source, from, to) in pairs {
+        let result = match fs::metadata(&from).await {
+            Ok(metadata) if metadata.is_dir() && !recursive => {
+                let message = format!("-r not specified; omitting directory '{}'", lossy(source));
+                fail(stderr, "cp", message).await?;
+                code = 1;
+                continue;
+            }
+            Ok(metadata) if metadata.is_dir() => {
+                if to.starts_with(&from) {
+                    let message =
+                        format!("cannot copy a directory, '{}', into itself", lossy(source));
+                    fail(stderr, "cp", message).await?;
+                    code = 1;
+                    continue;
+                }
+                tokio::task::spawn_blocking(move || copy_tree(&from, &to)).await?
+            }
+            Ok(_) => fs::copy(&from, &to).await.map(|_| ()),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            let message = format!("cannot copy '{}': {}", lossy(source), describe(&err));
+            fail(stderr, "cp", message).await?;
+            code = 1;
+        }
+    }
+    Ok(code)
+}
+
+/// `touch [-c] file...`, sets the times of files to now, creating them unless `-c` is given.
+async fn run_touch(shell: &Shell, args: &[Bytes], stderr: &Stdout) -> io::Result<i32> {
+    let opts = match parse_opts(args, b"c", b"") {
+        Ok(opts) if opts.operands.is_empty() => Err("missing file operand".to_owned()),
+        result => result,
+    };
+    let opts = match opts {
+        Ok(opts) => opts,
+        Err(message) => {
+            fail(stderr, "touch", message).await?;
+            return Ok(1);
+        }
+    };
+    let mut code = 0;
+    for operand in opts.operands {
+        let path = resolve(shell, operand);
+        let result = match fs::metadata(&path).await {
+            Err(err) if err.kind() == io::ErrorKind::NotFound && opts.has(b'c') => continue,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .await
+                .map(|_| ()),
+            _ => touch(&path),
+        };
+        if let Err(err) = result {
+            let message = format!("cannot touch '{}': {}", lossy(operand), describe(&err));
+            fail(stderr, "touch", message).await?;
+            code = 1;
+        }
+    }
+    Ok(code)
+}
+
+fn touch(path: &Path) -> io::Result<()> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    // Null times are the current time.
+    if unsafe { libc::utimensat(libc::AT_FDCWD, path.as_ptr(), std::ptr::null(), 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// `which [-a] name...`, prints where a command is found in `$PATH`, or with `-a` all of the
+/// places.
+async fn run_which(
+    shell: &Shell,
+    args: &[Bytes],
+    out: &mut Output<'_>,
+    stderr: &Stdout,
+) -> io::Result<i32> {
+    let opts = match parse_opts(args, b"a", b"") {
+        Ok(opts) => opts,
+        Err(message) => {
+            fail(stderr, "which", message).await?;
+            return Ok(2);
+        }
+    };
+    let path_var = shell.var(b"PATH").unwrap_or_default();
+    let mut code = 0;
+    for name in opts.operands {
+        let candidates: Vec<Vec<u8>> = if name.contains(&b'/') {
+            vec![name.to_vec()]
+        } else {
+            path_var
+                .split(|&c| c == b':')
+                .map(|dir| match dir {
+                    b"" => name.to_vec(),
+                    dir => [dir, b"/", name].concat(),
+                })
+                .collect()
+        };
+        let mut found = false;
+        for candidate in candidates {
+            let executable = fs::metadata(resolve(shell, &candidate))
+                .await
+                .is_ok_and(|metadata| {
+                    metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+                });
+            if executable {
+                out.write(&[&candidate[..], b"\n"].concat()).await?;
+                found = true;
+                if !opts.has(b'a') {
+                    break;
+                }
+            }
+        }
+        if !found {
+            code = 1;
+        }
+    }
+    Ok(code)
+}
+
+/// `basename name [suffix]` or `basename [-a] [-s suffix] name...`, the last component of a path
+/// without the suffix.
+async fn run_basename(args: &[Bytes], out: &mut Output<'_>, stderr: &Stdout) -> io::Result<i32> {
+    let opts = match parse_opts(args, b"a", b"s") {
+        Ok(opts) if opts.operands.is_empty() => Err("missing operand".to_owned()),
+        result => result,
+    };
+    let opts = match opts {
+        Ok(opts) => opts,
+        Err(message) => {
+            fail(stderr, "basename", message).await?;
+            return Ok(1);
+        }
+    };
+    let suffix = opts.flags.iter().rev().find(|(flag, _)| *flag == b's');
+    let (names, suffix) = match (suffix, opts.operands) {
+        (Some((_, suffix)), names) => (names, &suffix[..]),
+        (None, names) if opts.has(b'a') => (names, &b""[..]),
+        (None, [_]) => (opts.operands, &b""[..]),
+        (None, [name, suffix]) => (std::slice::from_ref(name), &suffix[..]),
+        (None, [_, _, extra, ..]) => {
+            fail(
+                stderr,
+                "basename",
+                format!("extra operand '{}'", lossy(extra)),
+            )
+            .await?;
+            return Ok(1);
+        }
+        (None, []) => unreachable!(),
+    };
+    for name in names {
+        let trimmed = trim_trailing_slashes(name);
+        let base = match trimmed.iter().rposition(|&c| c == b'/') {
+            _ if trimmed.is_empty() && !name.is_empty() => b"/",
+            Some(i) => &trimmed[i + 1..],
+            None => trimmed,
+        };
+        let base = match base.strip_suffix(suffix) {
+            Some(stripped) if !stripped.is_empty() => stripped,
+            _ => base,
+        };
+        out.write(&[base, b"\n"].concat()).await?;
+    }
+    Ok(0)
+}
+
+/// `dirname name...`, a path without its last component.
+async fn run_dirname(args: &[Bytes], out: &mut Output<'_>, stderr: &Stdout) -> io::Result<i32> {
+    let opts = match parse_opts(args, b"", b"") {
+        Ok(opts) if opts.operands.is_empty() => Err("missing operand".to_owned()),
+        result => result,
+    };
+    let opts = match opts {
+        Ok(opts) => opts,
+        Err(message) => {
+            fail(stderr, "dirname", message).await?;
+            return Ok(1);
+        }
+    };
+    for name in opts.operands {
+        let trimmed = trim_trailing_slashes(name);
+        let dir = match trimmed.iter().rposition(|&c| c == b'/') {
+            _ if trimmed.is_empty() && !name.is_empty() => b"/",
+            Some(i) => match trim_trailing_slashes(&trimmed[..i]) {
+                b"" => b"/",
+                dir => dir,
+            },
+            None => b".",
+        };
+        out.write(&[dir, b"\n"].concat()).await?;
+    }
+    Ok(0)
+}
+
+fn trim_trailing_slashes(name: &[u8]) -> &[u8] {
+    let end = name.iter().rposition(|&c| c != b'/').map_or(0, |i| i + 1);
+    &name[..end]
+}
+
+/// `seq [-w] [-s sep] [first [increment]] last`, where `-w` pads the numbers with zeros to the
+/// same width.
+async fn run_seq(args: &[Bytes], out: &mut Output<'_>, stderr: &Stdout) -> io::Result<i32> {
+    let opts = match parse_opts(args, b"w", b"s") {
+        Ok(opts) => opts,
+        Err(message) => {
+            fail(stderr, "seq", message).await?;
+            return Ok(1);
+        }
+    };
+    let separator = match opts.flags.iter().rev().find(|(flag, _)| *flag == b's') {
+        Some((_, separator)) => separator.clone(),
+        None => Bytes::from_static(b"\n"),
+    };
+    let (first, increment, last) = match opts.operands {
+        [last] => (&b"1"[..], &b"1"[..], &last[..]),
+        [first, last] => (&first[..], &b"1"[..], &last[..]),
+        [first, increment, last] => (&first[..], &increment[..], &last[..]),
+        [] => {
+            fail(stderr, "seq", "missing operand").await?;
+            return Ok(1);
+        }
+        [_, _, _, extra, ..] => {
+            fail(stderr, "seq", format!("extra operand '{}'", lossy(extra))).await?;
+            return Ok(1);
+        }
+    };
+    let mut numbers = [0.0; 3];
+    let mut precision = 0;
+    for (i, arg) in [first, increment, last].into_iter().enumerate() {
+        let arg = lossy(arg);
+        let Some(number) = arg.trim().parse::<f64>().ok().filter(|n| n.is_finite()) else {
+            fail(
+                stderr,
+                "seq",
+                format!("invalid floating point argument: '{arg}'"),
+            )
+            .await?;
+            return Ok(1);
+        };
+        numbers[i] = number;
+        // The precision is that of the first number and the increment.
+        if i < 2 {
+            let decimals = arg
+                .split_once('.')
+                .map_or(0, |(_, decimals)| decimals.len());
+            precision = precision.max(decimals);
+        }
+    }
+    let [first, increment, last] = numbers;
+    if increment == 0.0 {
+        let increment = lossy(opts.operands[1].as_ref());
+        fail(
+            stderr,
+            "seq",
+            format!("invalid Zero increment value: '{increment}'"),
+        )
+        .await?;
+        return Ok(1);
+    }
+    let width = if opts.has(b'w') {
+        let first = format!("{first:.precision$}").len();
+        first.max(format!("{last:.precision$}").len())
+    } else {
+        0
+    };
+    let mut i = 0u64;
+    loop {
+        // Multiplying rather than adding keeps the error from growing.
+        let number = first + i as f64 * increment;
+        if (increment > 0.0 && number > last) || (increment < 0.0 && number < last) {
+            break;
+        }
+        if i > 0 {
+            out.write(&separator).await?;
+        }
+        let formatted = if number < 0.0 {
+            format!(
+                "-{:0>width$.precision$}",
+                -number,
+                width = width.saturating_sub(1)
+            )
+        } else {
+            format!("{number:0>width$.precision$}")
+        };
+        out.write(formatted.as_bytes()).await?;
+        i += 1;
+    }
+    if i > 0 {
+        out.write(b"\n").await?;
+    }
+    Ok(0)
+}
+
+/// `yes [string...]`, prints the string, `y` by default, until the output is closed.
+async fn run_yes(args: &[Bytes], out: &mut Output<'_>) -> io::Result<i32> {
+    let mut line = args.join(&b' ');
+    if args.is_empty() {
+        line.push(b'y');
+    }
+    line.push(b'\n');
+    let lines = line.repeat(CHUNK / line.len() + 1);
+    loop {
+        out.write(&lines).await?;
+    }
+}
+
+/// How much of an input `head` or `tail` prints, a number of lines or bytes. For `tail` a count
+/// given with `+` is where to start instead.
+struct Count {
+    bytes: bool,
+    n: u64,
+    from_start: bool,
+}
+
+/// Parses the options of `head` and `tail`, `-n N`, `-c N` and the obsolete `-N`.
+fn parse_count(args: &[Bytes]) -> Result<(Count, Opts<'_>), String> {
+    let mut count = Count {
+        bytes: false,
+        n: 10,
+        from_start: false,
+    };
+    let (obsolete, args) = match args {
+        [first, rest @ ..]
+            if first.len() > 1 && first[0] == b'-' && first[1..].iter().all(u8::is_ascii_digit) =>
+        {
+            (Some(first.slice(1..)), rest)
+        }
+        args => (None, args),
+    };
+    let opts = parse_opts(args, b"qv", b"nc")?;
+    let values = opts.flags.iter().filter(|(flag, _)| b"nc".contains(flag));
+    for (flag, value) in obsolete.map(|value| (b'n', value)).iter().chain(values) {
+        let value = lossy(value);
+        let (from_start, digits) = match value.strip_prefix('+') {
+            Some(digits) => (true, digits),
+            None => (false, value.strip_prefix('-').unwrap_or(&value)),
+        };
+        let kind = if *flag == b'c' { "bytes" } else { "lines" };
+        let Ok(n) = digits.parse() else {
+            return Err(format!("invalid number of {kind}: '{value}'"));
+        };
+        count = Count {
+            bytes: *flag == b'c',
+            n,
+            from_start,
+        };
+    }
+    Ok((count, opts))
+}
+
+/// `head [-n lines | -c bytes] [file...]`, prints the start of each input.
+async fn run_head(
+    shell: &Shell,
+    args: &[Bytes],
+    stdin: &Stdin,
+    out: &mut Output<'_>,
+    stderr: &Stdout,
+) -> io::Result<i32> {
+    let (count, opts) = match parse_count(args) {
+        Ok(parsed) => parsed,
+        Err(message) => {
+            fail(stderr, "head", message).await?;
+            return Ok(1);
+        }
+    };
+    let operands = input_operands(opts.operands);
+    let headers = (operands.len() > 1 || opts.has(b'v')) && !opts.has(b'q');
+    let mut code = 0;
+    for (i, operand) in operands.iter().enumerate() {
+        let mut input = match Input::open(shell, stdin, operand).await {
+            Ok(input) => input,
+            Err(err) => {
+                let message = format!(
+                    "cannot open '{}' for reading: {}",
+                    lossy(operand),
+                    describe(&err)
+                );
+                out.error(stderr, "head", message).await?;
+                code = 1;
+                continue;
+            }
+        };
+        if headers {
+            write_header(out, i, operand).await?;
+        }
+        let mut left = count.n;
+        while left > 0 {
+            let chunk = input.read().await?;
+            if chunk.is_empty() {
+                break;
+            }
+            let end = if count.bytes {
+                chunk.len().min(left as usize)
+            } else {
+                let mut end = chunk.len();
+                for (j, _) in chunk.iter().enumerate().filter(|(_, &c)| c == b'\n') {
+                    left -= 1;
+                    if left == 0 {
+                        end = j + 1;
+                        break;
+                    }
+                }
+                end
+            };
+            if count.bytes {
+                left -= end as u64;
+            }
+            out.write(&chunk[..end]).await?;
+        }
+    }
+    Ok(code)
+}
+
+/// `tail [-n [+]lines | -c [+]bytes] [file...]`, prints the end of each input, or with `+`
+/// everything from the given line or byte on.
+async fn run_tail(
+    shell: &Shell,
+    args: &[Bytes],
+    stdin: &Stdin,
+    out: &mut Output<'_>,
+    stderr: &Stdout,
+) -> io::Result<i32> {
+    let (count, opts) = match parse_count(args) {
+        Ok(parsed) => parsed,
+        Err(message) => {
+            fail(stderr, "tail", message).await?;
+            return Ok(1);
+        }
+    };
+    let operands = input_operands(opts.operands);
+    let headers = (operands.len() > 1 || opts.has(b'v')) && !opts.has(b'q');
+    let mut code = 0;
+    for (i, operand) in operands.iter().enumerate() {
+        let content = match Input::open(shell, stdin, operand).await {
+            Ok(mut input) => input.read_to_end().await,
+            Err(err) => Err(err),
+        };
+        let content = match content {
+            Ok(content) => content,
+            Err(err) => {
+                let message = format!(
+                    "cannot open '{}' for reading: {}",
+                    lossy(operand),
+                    describe(&err)
+                );
+                out.error(stderr, "tail", message).await?;
+                code = 1;
+                continue;
+            }
+        };
+        if headers {
+            write_header(out, i, operand).await?;
+        }
+        let n = count.n as usize;
+        let start = match count {
+            Count {
+                bytes: true,
+                from_start: true,
+                ..
+            } => n.saturating_sub(1).min(content.len()),
+            Count { bytes: true, .. } => content.len().saturating_sub(n),
+            Count {
+                from_start: true, ..
+            } if n <= 1 => 0,
+            Count {
+                from_start: true, ..
+            } => content
+                .iter()
+                .enumerate()
+                .filter(|(_, &c)| c == b'\n')
+                .nth(n - 2)
+                .map_or(content.len(), |(j, _)| j + 1),
+            Count { .. } => {
+                // A last line without a newline still counts as a line.
+                let search = match content.last() {
+                    Some(b'\n') => &content[..content.len() - 1],
+                    _ => &content[..],
+                };
+                match n {
+                    0 => content.len(),
+                    n => search
+                        .iter()
+                        .enumerate()
+                        .rev()
+                        .filter(|(_, &c)| c == b'\n')
+                        .nth(n - 1)
+                        .map_or(0, |(j, _)| j + 1),
+                }
+            }
+        };
+        out.write(&content[start..]).await?;
+    }
+    Ok(code)
+}
+
+async fn write_header(out: &mut Output<'_>, i: usize, operand: &[u8]) -> io::Result<()> {
+    let name = if operand == b"-" {
+        &b"standard input"[..]
+    } else {
+        operand
+    };
+    let separator = if i > 0 { &b"\n"[..] } else { b"" };
+    out.write(&[separator, b"==> ", name, b" <==\n"].concat())
+        .await
+}
+
+/// `wc [-lwmc] [file...]`, counts the lines, words, characters and bytes of each input.
+async fn run_wc(
+    shell: &Shell,
+    args: &[Bytes],
+    stdin: &Stdin,
+    out: &mut Output<'_>,
+    stderr: &Stdout,
+) -> io::Result<i32> {
+    let opts = match parse_opts(args, b"lwmc", b"") {
+        Ok(opts) => opts,
+        Err(message) => {
+            fail(stderr, "wc", message).await?;
+            return Ok(1);
+        }
+    };
+    // The counts are always printed in this order.
+    let mut shown: Vec<usize> = b"lwmc"
+        .iter()
+        .enumerate()
+        .filter(|(_, flag)| opts.has(**flag))
+        .map(|(i, _)| i)
+        .collect();
+    if shown.is_empty() {
+        shown = vec![0, 1, 3];
+    }
+    let named = !opts.operands.is_empty();
+    let operands = input_operands(opts.operands);
+    let mut results = vec![];
+    let mut code = 0;
+    for operand in &operands {
+        let counts = match Input::open(shell, stdin, operand).await {
+            Ok(mut input) => count_input(&mut input).await,
+            Err(err) => Err(err),
+        };
+        match counts {
+            Ok(counts) => results.push((operand.clone(), counts)),
+            Err(err) => {
+                let message = format!("{}: {}", lossy(operand), describe(&err));
+                out.error(stderr, "wc", message).await?;
+                code = 1;
+            }
+        }
+    }
+    let mut total = [0u64; 4];
+    for (_, counts) in &results {
+        for (total, count) in total.iter_mut().zip(counts) {
+            *total += count;
+        }
+    }
+    // Like GNU wc, the width fits the total size, and stdin is assumed to be large.
+    let width = if shown.len() == 1 && operands.len() == 1 {
+        0
+    } else if operands.iter().any(|operand| &operand[..] == b"-") {
+        7
+    } else {
+        total[3].to_string().len()
+    };
+    if operands.len() > 1 {
+        results.push((Bytes::from_static(b"total"), total));
+    }
+    for (operand, counts) in results {
+        let mut line = shown
+            .iter()
+            .map(|&i| format!("{:>width$}", counts[i]))
+            .collect::<Vec<_>>()
+            .join(" ")
+            .into_bytes();
+        if named {
+            line.push(b' ');
+            line.extend_from_slice(&operand);
+        }
+        line.push(b'\n');
+        out.write(&line).await?;
+    }
+    Ok(code)
+}
+
+/// The lines, words, characters and bytes of an input.
+async fn count_input(input: &mut Input<'_>) -> io::Result<[u64; 4]> {
+    let mut counts = [0u64; 4];
+    let mut in_word = false;
+    loop {
+        let chunk = input.read().await?;
+        if chunk.is_empty() {
+            return Ok(counts);
+        }
+        for &c in &chunk {
+            if c == b'\n' {
+                counts[0] += 1;
+            }
+            let space = c.is_ascii_whitespace() || c == 0x0b;
+            if !space && !in_word {
+                counts[1] += 1;
+            }
+            in_word = !space;
+            // UTF-8 continuation bytes are part of the character before them.
+            if c & 0xc0 != 0x80 {
+                counts[2] += 1;
+            }
+        }
+        counts[3] += chunk.len() as u64;
+    }
+}
@@ -0,0 +1,84 @@
+use std::process::ExitStatus;
+use tokio::{io, task::JoinHandle};
+
+/// Linux never hands out pids above `2^22`, so ids of jobs that are not a single process start
+/// there and cannot be mistaken for a real pid.
+const FIRST_VIRTUAL_PID: u32 = 1 << 22;
+
+/// What happens to background jobs that are still running when a script finishes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum JobPolicy {
+    /// Wait for them to finish.
+    #[default]
+    Wait,
+    /// Kill them.
+    Kill,
+}
+
+/// The background jobs started with `&`.
+///
+/// A subshell cannot wait for the jobs of its parent, so a clone of the table is empty.
+#[derive(Debug, Default)]
+pub struct Jobs {
+    jobs: Vec<Job>,
+    next_virtual_pid: u32,
+}
+
+#[derive(Debug)]
+struct Job {
+    pid: u32,
+    handle: JoinHandle<io::Result<ExitStatus>>,
+}
+
+impl Clone for Jobs {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl Jobs {
+    pub fn push(&mut self, pid: u32, handle: JoinHandle<io::Result<ExitStatus>>) {
+        self.jobs.push(Job { pid, handle });
+    }
+
+    /// Returns an id for a job that is not backed by a single process, e.g. a pipeline.
+    pub fn virtual_pid(&mut self) -> u32 {
+        self.next_virtual_pid += 1;
+        FIRST_VIRTUAL_PID + self.next_virtual_pid
+    }
+
+    /// Waits for the job with the given pid, returns `None` if there is no such job.
+    pub async fn wait(&mut self, pid: u32) -> Option<io::Result<ExitStatus>> {
+        let index = self.jobs.iter().position(|job| job.pid == pid)?;
+        Some(join(self.jobs.remove(index)).await)
+    }
+
+    pub async fn wait_all(&mut self) -> io::Result<()> {
+        for job in self.jobs.drain(..) {
+            join(job).await?;
+        }
+        Ok(())
+    }
+
+    /// Aborts all jobs. Child processes are killed when the task owning them is dropped.
+    pub fn kill_all(&mut self) {
+        for job in self.jobs.drain(..) {
+            job.handle.abort();
+        }
+    }
+
+    /// Disposes of the jobs that are still running when a script finishes.
+    pub async fn finish(&mut self, policy: JobPolicy) -> io::Result<()> {
+        match policy {
+            JobPolicy::Wait => self.wait_all().await,
+            JobPolicy::Kill => {
+                self.kill_all();
+                Ok(())
+            }
+        }
+    }
+}
+
+async fn join(job: Job) -> io::Result<ExitStatus> {
+    job.handle.await.map_err(io::Error::other)?
+}
@@ -0,0 +1,71 @@
+/// The options that are turned on and off with `set` and `shopt`.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// `set -e`, the script ends when a command fails, unless its status is tested.
+    pub errexit: bool,
+    /// `set -f`, words are not globbed.
+    pub noglob: bool,
+    /// `set -u`, expanding a variable that is not set is an error.
+    pub nounset: bool,
+    /// `set -o pipefail`, a pipeline fails with the status of its last stage that failed.
+    pub pipefail: bool,
+    /// `set -x`, commands are printed to stderr after `$PS4` before they run.
+    pub xtrace: bool,
+}
+
+/// The names of the options in `set -o name`, with the flag of `set -f` and the like.
+const OPTIONS: [(&str, Option<u8>); 5] = [
+    ("errexit", Some(b'e')),
+    ("noglob", Some(b'f')),
+    ("nounset", Some(b'u')),
+    ("pipefail", None),
+    ("xtrace", Some(b'x')),
+];
+
+impl Options {
+    /// The names of all options, in order.
+    pub fn names() -> impl Iterator<Item = &'static str> {
+        OPTIONS.iter().map(|(name, _)| *name)
+    }
+
+    /// The name of the option with a flag, such as `errexit` for `e`.
+    pub fn flag_name(flag: u8) -> Option<&'static str> {
+        OPTIONS
+            .iter()
+            .find(|(_, option_flag)| *option_flag == Some(flag))
+            .map(|(name, _)| *name)
+    }
+
+    pub fn get(&self, name: &[u8]) -> Option<bool> {
+        Some(match name {
+            b"errexit" => self.errexit,
+            b"noglob" => self.noglob,
+            b"nounset" => self.nounset,
+            b"pipefail" => self.pipefail,
+            b"xtrace" => self.xtrace,
+            _ => return None,
+        })
+    }
+
+    /// Turns an option on or off, returns false if there is no such option.
+    pub fn set(&mut self, name: &[u8], on: bool) -> bool {
+        match self.option_mut(name) {
+            Some(option) => {
+                *option = on;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn option_mut(&mut self, name: &[u8]) -> Option<&mut bool> {
+        Some(match name {
+            b"errexit" => &mut self.errexit,
+            b"noglob" => &mut self.noglob,
+            b"nounset" => &mut self.nounset,
+            b"pipefail" => &mut self.pipefail,
+            b"xtrace" => &mut self.xtrace,
+            _ => return None,
+        })
+    }
+}
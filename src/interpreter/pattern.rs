@@ -0,0 +1,269 @@
+//! Shell pattern matching (`*`, `?` and `[...]`) as used by `[[ == ]]`, `case` and globbing.
+//!
+//! Patterns are byte strings in which a backslash escapes the next byte, quoted parts of a word
+//! are escaped with [`escape`] before they are spliced into a pattern.
+
+use bytes::BytesMut;
+use std::{
+    ffi::OsStr,
+    fs,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+pub fn escape(text: &[u8], out: &mut BytesMut) {
+    for &c in text {
+        if matches!(c, b'*' | b'?' | b'[' | b']' | b'\\') {
+            out.extend_from_slice(b"\\");
+        }
+        out.extend_from_slice(&[c]);
+    }
+}
+
+/// Whether the pattern contains any unescaped special characters.
+pub fn is_pattern(pattern: &[u8]) -> bool {
+    let mut i = 0;
+    while i < pattern.len() {
+        match pattern[i] {
+            b'\\' => i += 1,
+            b'*' | b'?' => return true,
+            b'[' if bracket_end(pattern, i).is_some() => return true,
+            _ => {}
+        }
+        i += 1;
+    }
+    false
+}
+
+pub fn unescape(pattern: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(pattern.len());
+    let mut chars = pattern.iter();
+    while let Some(&c) = chars.next() {
+        if c == b'\\' {
+            if let Some(&c) = chars.next() {
+                out.push(c);
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Expands a pattern to the sorted list of paths matching it, relative to `cwd` unless the
+/// pattern is absolute.
+///
+/// A `**` component matches any number of directories. Hidden files only match a component
+/// that starts with a literal `.`.
+pub fn glob(cwd: &Path, pattern: &[u8]) -> Vec<Vec<u8>> {
+    let mut components: Vec<&[u8]> = pattern.split(|&c| c == b'/').collect();
+    let (dir, prefix) = if pattern.starts_with(b"/") {
+        components.remove(0);
+        (PathBuf::from("/"), b"/".to_vec())
+    } else {
+        (cwd.to_path_buf(), vec![])
+    };
+    let mut paths = vec![];
+    glob_in(&dir, &prefix, &components, &mut paths);
+    paths.sort();
+    paths.dedup();
+    paths
+}
+
+fn glob_in(dir: &Path, prefix: &[u8], components: &[&[u8]], paths: &mut Vec<Vec<u8>>) {
+    let Some((&component, rest)) = components.split_first() else {
+        return;
+    };
+    if component == b"**" {
+        // Zero directories, a trailing `**` matches everything below.
+        glob_in(
+            dir,
+            prefix,
+            if rest.is_empty() { &[b"*"] } else { rest },
+            paths,
+        );
+        for (name, path) in read_dir(dir, false) {
+            if path.is_dir() && !path.is_symlink() {
+                glob_in(&path, &join(prefix, &name, true), components, paths);
+            }
+        }
+        return;
+    }
+    let names = if is_pattern(component) {
+        read_dir(dir, component.starts_with(b"."))
+            .into_iter()
+            .filter(|(name, _)| matches(component, name))
+            .collect()
+    } else {
+        let name = unescape(component);
+        let path = dir.join(OsStr::from_bytes(&name));
+        if fs::symlink_metadata(&path).is_err() {
+            return;
+        }
+        vec![(name, path)]
+    };
+    for (name, path) in names {
+        if rest.is_empty() {
+            paths.push(join(prefix, &name, false));
+        } else if path.is_dir() {
+            glob_in(&path, &join(prefix, &name, true), rest, paths);
+        }
+    }
+}
+
+fn read_dir(dir: &Path, hidden: bool) -> Vec<(Vec<u8>, PathBuf)> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return vec![];
+    };
+    entries
+        .flatten()
+        .map(|entry| (entry.file_name().as_bytes().to_vec(), entry.path()))
+        .filter(|(name, _)| hidden || !name.starts_with(b"."))
+        .collect()
+}
+
+fn join(prefix: &[u8], name: &[u8], dir: bool) -> Vec<u8> {
+    let mut path = [prefix, name].concat();
+    if dir {
+        path.push(b'/');
+    }
+    path
+}
+
+pub fn matches(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Position in the pattern after the last `*`, and the text position it was tried at.
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() {
+            match pattern[p] {
+                b'*' => {
+                    while p < pattern.len() && pattern[p] == b'*' {
+                        p += 1;
+                    }
+                    backtrack = Some((p, t));
+                    continue;
+                }
+                b'?' => {
+                    p += 1;
+                    t += 1;
+                    continue;
+                }
+                b'[' => {
+                    if let Some(end) = bracket_end(pattern, p) {
+                        if match_bracket(&pattern[p + 1..end], text[t]) {
+                            p = end + 1;
+                            t += 1;
+                            continue;
+                        }
+                    } else if text[t] == b'[' {
+                        p += 1;
+                        t += 1;
+                        continue;
+                    }
+                }
+                b'\\' if p + 1 < pattern.len() => {
+                    if pattern[p + 1] == text[t] {
+                        p += 2;
+                        t += 1;
+                        continue;
+                    }
+                }
+                c => {
+                    if c == text[t] {
+                        p += 1;
+                        t += 1;
+                        continue;
+                    }
+                }
+            }
+        }
+        let Some((star_p, star_t)) = backtrack else {
+            return false;
+        };
+        p = star_p;
+        t = star_t + 1;
+        backtrack = Some((star_p, t));
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Returns the index of the `]` closing the bracket expression that starts at `start`.
+fn bracket_end(pattern: &[u8], start: usize) -> Option<usize> {
+    let mut i = start + 1;
+    if matches!(pattern.get(i), Some(b'!' | b'^')) {
+        i += 1;
+    }
+    // A `]` right after the opening bracket is taken literally.
+    if pattern.get(i) == Some(&b']') {
+        i += 1;
+    }
+    while i < pattern.len() {
+        match pattern[i] {
+            b'\\' => i += 1,
+            b'[' if pattern.get(i + 1) == Some(&b':') => {
+                if let Some(len) = pattern[i + 2..].windows(2).position(|w| w == b":]") {
+                    i += len + 3;
+                }
+            }
+            b']' => return Some(i),
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+fn match_bracket(set: &[u8], c: u8) -> bool {
+    let (negate, set) = match set {
+        [b'!' | b'^', rest @ ..] => (true, rest),
+        _ => (false, set),
+    };
+    let mut i = 0;
+    let mut matched = false;
+    while i < set.len() {
+        if set[i] == b'[' && set.get(i + 1) == Some(&b':') {
+            if let Some(len) = set[i + 2..].windows(2).position(|w| w == b":]") {
+                matched |= match_class(&set[i + 2..i + 2 + len], c);
+                i += len + 4;
+                continue;
+            }
+        }
+        let mut lo = set[i];
+        if lo == b'\\' && i + 1 < set.len() {
+            i += 1;
+            lo = set[i];
+        }
+        if set.get(i + 1) == Some(&b'-') && i + 2 < set.len() {
+            let mut hi = set[i + 2];
+            i += 2;
+            if hi == b'\\' && i + 1 < set.len() {
+                i += 1;
+                hi = set[i];
+            }
+            matched |= lo <= c && c <= hi;
+        } else {
+            matched |= lo == c;
+        }
+        i += 1;
+    }
+    matched != negate
+}
+
+fn match_class(class: &[u8], c: u8) -> bool {
+    match class {
+        b"alnum" => c.is_ascii_alphanumeric(),
+        b"alpha" => c.is_ascii_alphabetic(),
+        b"blank" => c == b' ' || c == b'\t',
+        b"cntrl" => c.is_ascii_control(),
+        b"digit" => c.is_ascii_digit(),
+        b"graph" => c.is_ascii_graphic(),
+        b"lower" => c.is_ascii_lowercase(),
+        b"print" => c.is_ascii_graphic() || c == b' ',
+        b"punct" => c.is_ascii_punctuation(),
+        b"space" => c.is_ascii_whitespace() || c == b'\x0b',
+        b"upper" => c.is_ascii_uppercase(),
+        b"xdigit" => c.is_ascii_hexdigit(),
+        _ => false,
+    }
+}
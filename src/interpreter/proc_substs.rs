@@ -0,0 +1,96 @@
+use crate::interpreter::{Stdin, Stdout};
+use std::{
+    fmt,
+    os::fd::{AsRawFd, OwnedFd, RawFd},
+};
+use tokio::task::JoinHandle;
+
+/// The process substitutions, `<(...)` and `>(...)`, of the commands that are running.
+///
+/// A subshell cannot end the process substitutions of its parent, so a clone of the table is
+/// empty.
+#[derive(Default)]
+pub struct ProcSubsts {
+    substs: Vec<ProcSubst>,
+    /// The standard input, output and error of the command being expanded, which its
+    /// substitutions share.
+    stdio: Option<(Stdin, Stdout, Stdout)>,
+}
+
+/// The state of [`ProcSubsts`] before a command, which is restored once it is done.
+pub struct ProcSubstsMark {
+    len: usize,
+    stdio: Option<(Stdin, Stdout, Stdout)>,
+}
+
+#[derive(Debug)]
+struct ProcSubst {
+    /// The end of the pipe that the command using the substitution opens as `/dev/fd/N`.
+    fd: OwnedFd,
+    handle: JoinHandle<()>,
+}
+
+impl Clone for ProcSubsts {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl fmt::Debug for ProcSubsts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProcSubsts")
+            .field("substs", &self.substs)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ProcSubsts {
+    /// Adds a running substitution, returns the file descriptor it is reached through.
+    pub fn push(&mut self, fd: OwnedFd, handle: JoinHandle<()>) -> RawFd {
+        let raw = fd.as_raw_fd();
+        self.substs.push(ProcSubst { fd, handle });
+        raw
+    }
+
+    /// Marks the substitutions that were started so far, see [`ProcSubsts::finish`]. The ones
+    /// started until then run with the standard streams of the command.
+    pub fn mark(&mut self, stdin: &Stdin, stdout: &Stdout, stderr: &Stdout) -> ProcSubstsMark {
+        let stdio = (stdin.clone(), stdout.clone(), stderr.clone());
+        ProcSubstsMark {
+            len: self.substs.len(),
+            stdio: self.stdio.replace(stdio),
+        }
+    }
+
+    /// The standard input, output and error of the command being expanded.
+    pub fn stdio(&self) -> (Stdin, Stdout, Stdout) {
+        self.stdio
+            .clone()
+            .unwrap_or((Stdin::Inherit, Stdout::Inherit, Stdout::Inherit))
+    }
+
+    /// The file descriptors that external commands inherit.
+    pub fn fds(&self) -> Vec<RawFd> {
+        self.substs
+            .iter()
+            .map(|subst| subst.fd.as_raw_fd())
+            .collect()
+    }
+
+    /// Closes the substitutions started since `mark` and waits for them to finish, once the
+    /// command that uses them is done.
+    pub async fn finish(&mut self, mark: ProcSubstsMark) {
+        self.stdio = mark.stdio;
+        // Dropping the file descriptors first lets every substitution see EOF or a broken pipe.
+        let handles: Vec<_> = self
+            .substs
+            .split_off(mark.len)
+            .into_iter()
+            .map(|subst| subst.handle)
+            .collect();
+        for handle in handles {
+            // Like that of a subshell, the status of a substitution is not seen by anyone.
+            let _ = handle.await;
+        }
+    }
+}
@@ -0,0 +1,177 @@
+//! Interpolated Python objects that are not strings, used as arguments or redirections.
+
+use crate::interpreter::{Stdin, Stdout};
+use bytes::Bytes;
+use pyo3::{
+    exceptions::PyTypeError,
+    prelude::*,
+    types::{PyByteArray, PyBytes, PyList, PyMemoryView, PyString, PyTuple},
+};
+use std::{
+    io::Cursor,
+    os::fd::{BorrowedFd, RawFd},
+    path::PathBuf,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tokio::{io, sync::Mutex};
+
+/// The arguments that `{obj}` expands to: one for every item of a list or tuple, and one for
+/// any other object. Path-like objects become their path, other objects are converted with
+/// `str()`.
+pub fn pyobject_args(pyobject: &Py<PyAny>) -> PyResult<Vec<Bytes>> {
+    Python::with_gil(|py| {
+        let pyobject = pyobject.bind(py);
+        if pyobject.is_instance_of::<PyList>() || pyobject.is_instance_of::<PyTuple>() {
+            return pyobject
+                .try_iter()?
+                .map(|item| pyobject_arg(&item?))
+                .collect();
+        }
+        Ok(vec![pyobject_arg(pyobject)?])
+    })
+}
+
+fn pyobject_arg(pyobject: &Bound<PyAny>) -> PyResult<Bytes> {
+    if let Ok(bytes) = pyobject.downcast::<PyBytes>() {
+        return Ok(Bytes::copy_from_slice(bytes.as_bytes()));
+    }
+    let text = if pyobject.hasattr("__fspath__")? {
+        let path = pyobject.call_method0("__fspath__")?;
+        if let Ok(bytes) = path.downcast::<PyBytes>() {
+            return Ok(Bytes::copy_from_slice(bytes.as_bytes()));
+        }
+        path.str()?
+    } else {
+        pyobject.str()?
+    };
+    Ok(Bytes::copy_from_slice(text.to_str()?.as_bytes()))
+}
+
+/// The word of `<<< {obj}`: the contents of a `bytes`, `bytearray` or `memoryview`, and `str()`
+/// of any other object.
+pub fn pyobject_herestring(pyobject: &Py<PyAny>) -> PyResult<Bytes> {
+    Python::with_gil(|py| {
+        let pyobject = pyobject.bind(py);
+        if let Ok(bytes) = pyobject.downcast::<PyBytes>() {
+            return Ok(Bytes::copy_from_slice(bytes.as_bytes()));
+        }
+        if let Ok(bytearray) = pyobject.downcast::<PyByteArray>() {
+            return Ok(bytearray.to_vec().into());
+        }
+        if pyobject.is_instance_of::<PyMemoryView>() {
+            let bytes = pyobject.call_method0("tobytes")?;
+            return Ok(Bytes::copy_from_slice(
+                bytes.downcast::<PyBytes>()?.as_bytes(),
+            ));
+        }
+        Ok(Bytes::copy_from_slice(pyobject.str()?.to_str()?.as_bytes()))
+    })
+}
+
+pub enum PyRedirect {
+    /// A path-like object, opened like a file name would be.
+    Path(PathBuf),
+    Stdin(Stdin),
+    Stdout(Stdout),
+}
+
+/// Resolves the target of `< {obj}` or `> {obj}`.
+///
+/// `bytes` and `str` are input, a `bytearray` is input or captures output, path-like objects
+/// are opened, file objects backed by a file descriptor are used directly and other file-like
+/// objects such as `io.BytesIO` are read or written through their methods. A `bytearray` is
+/// cleared first unless the output is appended with `>>`.
+pub fn pyobject_redirect(pyobject: &Py<PyAny>, input: bool, append: bool) -> PyResult<PyRedirect> {
+    Python::with_gil(|py| {
+        let pyobject = pyobject.bind(py);
+        if let Ok(bytes) = pyobject.downcast::<PyBytes>() {
+            if input {
+                return Ok(input_data(bytes.as_bytes().to_vec()));
+            }
+        } else if let Ok(text) = pyobject.downcast::<PyString>() {
+            if input {
+                return Ok(input_data(text.to_str()?.as_bytes().to_vec()));
+            }
+            return Ok(PyRedirect::Path(text.extract()?));
+        } else if let Ok(bytearray) = pyobject.downcast::<PyByteArray>() {
+            if input {
+                return Ok(input_data(bytearray.to_vec()));
+            }
+            if !append {
+                pyobject.call_method0("clear")?;
+            }
+            return Ok(output_writer(pyobject, "extend"));
+        } else if pyobject.hasattr("__fspath__")? {
+            return Ok(PyRedirect::Path(pyobject.extract()?));
+        } else if let Ok(fileno) = pyobject
+            .call_method0("fileno")
+            .and_then(|fileno| fileno.extract::<RawFd>())
+        {
+            if !input {
+                // Anything Python has buffered must come before the output of the command.
+                pyobject.call_method0("flush")?;
+            }
+            // SAFETY: the file object keeps the file descriptor open while it is duplicated.
+            let fd = unsafe { BorrowedFd::borrow_raw(fileno) }.try_clone_to_owned()?;
+            return Ok(if input {
+                PyRedirect::Stdin(Stdin::Fd(Arc::new(fd)))
+            } else {
+                PyRedirect::Stdout(Stdout::Fd(Arc::new(fd)))
+            });
+        } else if input && pyobject.hasattr("read")? {
+            let data = pyobject.call_method0("read")?;
+            return Ok(input_data(
+                data.downcast_into::<PyBytes>()?.as_bytes().to_vec(),
+            ));
+        } else if !input && pyobject.hasattr("write")? {
+            return Ok(output_writer(pyobject, "write"));
+        }
+        Err(PyTypeError::new_err(format!(
+            "cannot redirect {} {}",
+            if input { "from" } else { "to" },
+            pyobject.get_type().name()?
+        )))
+    })
+}
+
+fn input_data(data: Vec<u8>) -> PyRedirect {
+    PyRedirect::Stdin(Stdin::Pipe(Arc::new(Mutex::new(Cursor::new(data)))))
+}
+
+fn output_writer(pyobject: &Bound<PyAny>, method: &'static str) -> PyRedirect {
+    let writer = PyWriter {
+        pyobject: pyobject.clone().unbind(),
+        method,
+    };
+    PyRedirect::Stdout(Stdout::Pipe(Arc::new(Mutex::new(writer))))
+}
+
+/// Passes everything written to it to a method of a Python object, e.g. `bytearray.extend`.
+struct PyWriter {
+    pyobject: Py<PyAny>,
+    method: &'static str,
+}
+
+impl io::AsyncWrite for PyWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let result = Python::with_gil(|py| {
+            let bytes = PyBytes::new(py, buf);
+            self.pyobject.call_method1(py, self.method, (bytes,))
+        });
+        Poll::Ready(result.map(|_| buf.len()).map_err(io::Error::from))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
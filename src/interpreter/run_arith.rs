@@ -0,0 +1,30 @@
+use crate::{
+    ast,
+    interpreter::{
+        arith,
+        run_atom::{run_atom_to_bytes, ExpansionError},
+        Shell,
+    },
+};
+use std::{os::unix::process::ExitStatusExt, process::ExitStatus};
+use tokio::io;
+
+/// `(( expr ))`, succeeds if the expression is not zero.
+pub async fn run_arith(shell: &mut Shell, expr: &ast::Atom) -> io::Result<ExitStatus> {
+    let value = eval_arith(shell, expr).await?;
+    Ok(ExitStatus::from_raw(i32::from(value == 0) << 8))
+}
+
+/// Expands the word of an arithmetic expression and evaluates it.
+pub async fn eval_arith(shell: &mut Shell, expr: &ast::Atom) -> io::Result<i64> {
+    let text = Box::pin(run_atom_to_bytes(shell, expr)).await?;
+    eval_arith_text(shell, &text)
+}
+
+/// Evaluates an arithmetic expression that has already been expanded.
+pub fn eval_arith_text(shell: &mut Shell, text: &[u8]) -> io::Result<i64> {
+    arith::eval(shell, text).map_err(|message| {
+        let text = String::from_utf8_lossy(text.trim_ascii());
+        io::Error::other(ExpansionError(format!("{text}: {message}")))
+    })
+}
@@ -1,7 +1,26 @@
-use crate::ast;
-use std::process::ExitStatus;
+use crate::{
+    ast,
+    interpreter::{run_atom::run_atom_to_bytes, run_cmd::xtrace, Shell, Stdout},
+};
+use std::{os::unix::process::ExitStatusExt, process::ExitStatus};
 use tokio::io;
 
-pub async fn run_assigns(assigns: &[ast::Assign]) -> io::Result<ExitStatus> {
-    todo!()
+pub async fn run_assigns(
+    shell: &mut Shell,
+    assigns: &[ast::Assign],
+    stderr: &Stdout,
+) -> io::Result<ExitStatus> {
+    // Like in bash, the status is that of the last command substitution, or 0 without one.
+    shell.cmd_subst_status = None;
+    for assign in assigns {
+        let value = run_atom_to_bytes(shell, &assign.value).await?;
+        if shell.options.xtrace {
+            xtrace(shell, &[(assign.label.clone(), value.clone())], &[], stderr).await?;
+        }
+        shell.set_var(assign.label.clone(), value);
+    }
+    Ok(shell
+        .cmd_subst_status
+        .take()
+        .unwrap_or(ExitStatus::from_raw(0)))
 }
@@ -1,7 +1,90 @@
-use crate::ast;
-use std::process::ExitStatus;
+use crate::{
+    ast,
+    interpreter::{
+        run_boxed_expr,
+        run_builtin::{run_builtin, Builtin},
+        run_cmd::{expand_cmd, spawn_cmd, spawn_error},
+        run_function::run_function,
+        run_redirect::{redirect_error, run_redirects},
+        Shell, Stdin, Stdout,
+    },
+};
+use std::{os::unix::process::ExitStatusExt, process::ExitStatus};
 use tokio::io;
 
-pub async fn run_async(expr: &ast::Expr) -> io::Result<ExitStatus> {
-    todo!()
+/// Starts `expr &` as a background job, which runs on a copy of the shell state.
+///
+/// `$!` is the pid of the job if it is a single external command, a virtual pid otherwise.
+pub async fn run_async(
+    shell: &mut Shell,
+    expr: &ast::Expr,
+    stdin: Stdin,
+    stdout: Stdout,
+    stderr: Stdout,
+) -> io::Result<ExitStatus> {
+    let mut bg_shell = shell.clone();
+    let (pid, handle) = match expr {
+        ast::Expr::Cmd(cmd) => {
+            let expanded = expand_cmd(shell, cmd).await?;
+            let (stdin, stdout, stderr) =
+                match run_redirects(shell, &cmd.redirects, stdin, stdout, stderr.clone()).await {
+                    Ok(stdio) => stdio,
+                    Err(err) => return redirect_error(&stderr, err).await,
+                };
+            let name = expanded.args.first().cloned().unwrap_or_default();
+            // Like in the foreground, a function or builtin sees the assignments before it. The
+            // copy of the shell is dropped with the job, so they need not be undone.
+            bg_shell.set_temp_vars(&expanded.assigns);
+            if let Some(body) = shell.functions.get(&name).cloned() {
+                let handle = tokio::spawn(async move {
+                    run_function(
+                        &mut bg_shell,
+                        &name,
+                        body,
+                        &expanded.args[1..],
+                        stdin,
+                        stdout,
+                        stderr,
+                    )
+                    .await
+                });
+                (shell.jobs.virtual_pid(), handle)
+            } else if let Some(builtin) = Builtin::parse(&name) {
+                let handle = tokio::spawn(async move {
+                    run_builtin(
+                        &mut bg_shell,
+                        builtin,
+                        &expanded.args[1..],
+                        stdin,
+                        stdout,
+                        stderr,
+                    )
+                    .await
+                });
+                (shell.jobs.virtual_pid(), handle)
+            } else {
+                match spawn_cmd(shell, &expanded, stdin, stdout, stderr.clone()) {
+                    Ok(spawned) => {
+                        let pid = spawned.pid().unwrap_or_default();
+                        (pid, tokio::spawn(spawned.wait()))
+                    }
+                    Err(err) => {
+                        let exitstatus = spawn_error(&stderr, &name, err).await?;
+                        let handle = tokio::spawn(async move { Ok(exitstatus) });
+                        (shell.jobs.virtual_pid(), handle)
+                    }
+                }
+            }
+        }
+        _ => {
+            let expr = expr.clone();
+            let handle = tokio::spawn(async move {
+                run_boxed_expr(&mut bg_shell, &expr, stdin, stdout, stderr).await
+            });
+            (shell.jobs.virtual_pid(), handle)
+        }
+    };
+    shell.jobs.push(pid, handle);
+    shell.last_bg_pid = Some(pid);
+    Ok(ExitStatus::from_raw(0))
 }
@@ -1,64 +1,490 @@
 use crate::{
     ast,
-    interpreter::{run_script, Stdin, Stdout},
+    interpreter::{
+        brace, pattern,
+        pyobject::pyobject_args,
+        run_arith::eval_arith,
+        run_proc_subst::run_proc_subst,
+        run_script,
+        run_var_expansion::{expands_to_args, run_var_expansion},
+        Control, Shell, Stdin, Stdout,
+    },
     stringpool::StringPool,
 };
-use std::sync::Arc;
+use bytes::{Bytes, BytesMut};
+use std::{
+    ffi::{CStr, CString},
+    fmt,
+    os::unix::{ffi::OsStrExt, process::ExitStatusExt},
+    process::ExitStatus,
+    sync::Arc,
+};
 use tokio::{io, sync::Mutex};
 
-pub async fn run_atom(atom: &ast::Atom, out: &mut StringPool) -> io::Result<()> {
+/// An expansion that failed, e.g. `${name:?}` of an unset variable. It fails the command with
+/// status 1 instead of aborting the script.
+#[derive(Debug)]
+pub struct ExpansionError(pub String);
+
+impl fmt::Display for ExpansionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ExpansionError {}
+
+/// Reports an [`ExpansionError`] and fails with status 1, other errors are passed on.
+pub async fn expansion_error(stderr: &Stdout, err: io::Error) -> io::Result<ExitStatus> {
+    let err = err.downcast::<ExpansionError>()?;
+    stderr
+        .write_all_err(format!("shl: {err}\n").as_bytes())
+        .await?;
+    Ok(ExitStatus::from_raw(1 << 8))
+}
+
+/// The error of `set -u` for a variable that is not set, which like in bash also ends the
+/// script.
+pub fn unbound_error(shell: &mut Shell, name: &[u8]) -> io::Error {
+    shell.control = Some(Control::Exit);
+    let name = String::from_utf8_lossy(name);
+    io::Error::other(ExpansionError(format!("{name}: unbound variable")))
+}
+
+/// Expands an atom to fields, after brace expansion they are split on `$IFS` and then globbed.
+pub async fn run_atom(shell: &mut Shell, atom: &ast::Atom, out: &mut StringPool) -> io::Result<()> {
+    let mut patterns = StringPool::new();
+    let mut fields = Fields::new(&mut patterns, Some(shell.ifs()), Quoting::Pattern);
+    match atom {
+        ast::Atom::CompoundAtom(compound_atom) if compound_atom.brace_expansion_hint => {
+            for word in brace::expand(&compound_atom.atoms) {
+                for simple_atom in &word {
+                    run_simple_atom(shell, simple_atom, &mut fields).await?;
+                }
+                fields.delimit();
+            }
+        }
+        _ => {
+            run_atom_into(shell, atom, &mut fields).await?;
+            fields.delimit();
+        }
+    }
+    for pattern in patterns.get_strings() {
+        if !shell.options.noglob && pattern::is_pattern(pattern) {
+            let (cwd, pattern) = (shell.cwd.clone(), pattern.clone());
+            let paths = tokio::task::spawn_blocking(move || pattern::glob(&cwd, &pattern)).await?;
+            if !paths.is_empty() {
+                paths.into_iter().for_each(|path| out.push(path.into()));
+                continue;
+            }
+        }
+        // A pattern without matches is left as is.
+        out.push(pattern::unescape(pattern).into());
+    }
+    Ok(())
+}
+
+/// Expands an atom to a single string without field splitting, as done for assignment values.
+pub async fn run_atom_to_bytes(shell: &mut Shell, atom: &ast::Atom) -> io::Result<Bytes> {
+    run_atom_to_single(shell, atom, Quoting::None).await
+}
+
+/// Expands an atom to a pattern in which only the unquoted parts are special, see [`pattern`].
+pub async fn run_atom_to_pattern(shell: &mut Shell, atom: &ast::Atom) -> io::Result<Bytes> {
+    run_atom_to_single(shell, atom, Quoting::Pattern).await
+}
+
+/// Expands an atom to a regex in which only the unquoted parts are special, as done for `=~`.
+pub async fn run_atom_to_regex(shell: &mut Shell, atom: &ast::Atom) -> io::Result<Bytes> {
+    run_atom_to_single(shell, atom, Quoting::Regex).await
+}
+
+async fn run_atom_to_single(
+    shell: &mut Shell,
+    atom: &ast::Atom,
+    quoting: Quoting,
+) -> io::Result<Bytes> {
+    let mut out = StringPool::new();
+    let mut fields = Fields::new(&mut out, None, quoting);
+    run_atom_into(shell, atom, &mut fields).await?;
+    fields.delimit();
+    Ok(out.get_strings().first().cloned().unwrap_or_default())
+}
+
+fn escape_regex(text: &[u8], out: &mut BytesMut) {
+    for &c in text {
+        if b"\\.+*?()|[]{}^$#&-~".contains(&c) {
+            out.extend_from_slice(b"\\");
+        }
+        out.extend_from_slice(&[c]);
+    }
+}
+
+async fn run_atom_into(
+    shell: &mut Shell,
+    atom: &ast::Atom,
+    fields: &mut Fields<'_>,
+) -> io::Result<()> {
     match atom {
-        ast::Atom::Simple(simple_atom) => run_simple_atom(simple_atom, out).await,
-        ast::Atom::CompoundAtom(compound_atom) => run_compound_atom(compound_atom, out).await,
+        ast::Atom::Simple(simple_atom) => run_simple_atom(shell, simple_atom, fields).await,
+        ast::Atom::CompoundAtom(compound_atom) => {
+            run_compound_atom(shell, compound_atom, fields).await
+        }
     }
 }
 
-async fn run_simple_atom(simple_atom: &ast::SimpleAtom, out: &mut StringPool) -> io::Result<()> {
+async fn run_simple_atom(
+    shell: &mut Shell,
+    simple_atom: &ast::SimpleAtom,
+    fields: &mut Fields<'_>,
+) -> io::Result<()> {
     match simple_atom {
-        ast::SimpleAtom::Var(var) => todo!(),
-        ast::SimpleAtom::VarArgv(_) => todo!(),
+        ast::SimpleAtom::Var { name, quoted, .. } => {
+            match (&name[..], *quoted) {
+                // `"$@"` expands to one field per argument.
+                (b"@", true) => {
+                    for (i, arg) in shell.args.iter().enumerate() {
+                        if i > 0 {
+                            fields.delimit_arg();
+                        }
+                        fields.push_quoted(arg);
+                    }
+                }
+                (b"@" | b"*", false) => {
+                    for (i, arg) in shell.args.iter().enumerate() {
+                        if i > 0 {
+                            fields.delimit_arg();
+                        }
+                        fields.push_unquoted(arg);
+                    }
+                }
+                // `"$*"` joins the arguments with the first character of `$IFS`.
+                (b"*", true) => {
+                    let ifs = shell.ifs();
+                    let separator = ifs.first().map(std::slice::from_ref).unwrap_or_default();
+                    fields.push_quoted(&shell.args.join(separator));
+                }
+                (name, quoted) => {
+                    let value = match shell.var(name) {
+                        Some(value) => value,
+                        None if shell.options.nounset => return Err(unbound_error(shell, name)),
+                        None => Bytes::new(),
+                    };
+                    if quoted {
+                        fields.push_quoted(&value);
+                    } else {
+                        fields.push_unquoted(&value);
+                    }
+                }
+            }
+            Ok(())
+        }
+        ast::SimpleAtom::VarArgv { index, quoted } => {
+            let value = match shell.arg(*index) {
+                Some(value) => value,
+                None if shell.options.nounset => {
+                    return Err(unbound_error(shell, index.to_string().as_bytes()))
+                }
+                None => Bytes::new(),
+            };
+            if *quoted {
+                fields.push_quoted(&value);
+            } else {
+                fields.push_unquoted(&value);
+            }
+            Ok(())
+        }
         ast::SimpleAtom::Text(text) => {
-            out.push(text.clone());
+            fields.push_text(text);
+            Ok(())
+        }
+        ast::SimpleAtom::QuotedText(text) => {
+            fields.push_quoted(text);
+            Ok(())
+        }
+        ast::SimpleAtom::Asterisk => {
+            fields.push_text(b"*");
+            Ok(())
+        }
+        ast::SimpleAtom::DoubleAsterisk => {
+            fields.push_text(b"**");
+            Ok(())
+        }
+        // Braces that are left after brace expansion are literal.
+        ast::SimpleAtom::BraceBegin => {
+            fields.push_text(b"{");
+            Ok(())
+        }
+        ast::SimpleAtom::BraceEnd => {
+            fields.push_text(b"}");
             Ok(())
         }
-        ast::SimpleAtom::Asterisk => todo!(),
-        ast::SimpleAtom::DoubleAsterisk => todo!(),
-        ast::SimpleAtom::BraceBegin => todo!(),
-        ast::SimpleAtom::BraceEnd => todo!(),
-        ast::SimpleAtom::Comma => todo!(),
-        ast::SimpleAtom::Tilde => todo!(),
-        ast::SimpleAtom::CmdSubst { script, quoted } => run_cmd_subst(script, *quoted, out).await,
+        ast::SimpleAtom::Comma => {
+            fields.push_text(b",");
+            Ok(())
+        }
+        ast::SimpleAtom::Tilde(name) => {
+            match tilde_expansion(shell, name) {
+                Some(path) => fields.push_quoted(&path),
+                // Unknown users and an unset `$OLDPWD` leave the prefix as is.
+                None => fields.push_quoted(&[b"~", &name[..]].concat()),
+            }
+            Ok(())
+        }
+        ast::SimpleAtom::CmdSubst { script, quoted } => {
+            run_cmd_subst(shell, script, *quoted, fields).await
+        }
+        ast::SimpleAtom::ProcSubst { script, input } => {
+            fields.push_quoted(&run_proc_subst(shell, script, *input)?);
+            Ok(())
+        }
+        ast::SimpleAtom::PyObject(index) => {
+            let pyobject = &shell.pyobjects[*index];
+            // Like `"$@"`, every item of a list is a field of its own.
+            for (i, arg) in pyobject_args(pyobject)?.iter().enumerate() {
+                if i > 0 {
+                    fields.delimit_arg();
+                }
+                fields.push_quoted(arg);
+            }
+            Ok(())
+        }
+        ast::SimpleAtom::Arith { expr, quoted } => {
+            let value = eval_arith(shell, expr).await?.to_string();
+            if *quoted {
+                fields.push_quoted(value.as_bytes());
+            } else {
+                fields.push_unquoted(value.as_bytes());
+            }
+            Ok(())
+        }
+        // Like `"$@"`, `"${name[@]}"` expands to one field per element.
+        ast::SimpleAtom::VarExpansion(expansion)
+            if expansion.quoted
+                && matches!(expansion.op, ast::VarExpansionOp::Elements { join: false }) =>
+        {
+            for (i, element) in shell.array(&expansion.name).iter().enumerate() {
+                if i > 0 {
+                    fields.delimit_arg();
+                }
+                fields.push_quoted(element);
+            }
+            Ok(())
+        }
+        // So does `"${@:-word}"` when there are arguments.
+        ast::SimpleAtom::VarExpansion(expansion)
+            if expansion.quoted && expands_to_args(shell, expansion) =>
+        {
+            for (i, arg) in shell.args.iter().enumerate() {
+                if i > 0 {
+                    fields.delimit_arg();
+                }
+                fields.push_quoted(arg);
+            }
+            Ok(())
+        }
+        ast::SimpleAtom::VarExpansion(expansion) => {
+            let value = run_var_expansion(shell, expansion).await?;
+            if expansion.quoted {
+                fields.push_quoted(&value);
+            } else {
+                fields.push_unquoted(&value);
+            }
+            Ok(())
+        }
+    }
+}
+
+fn tilde_expansion(shell: &Shell, name: &[u8]) -> Option<Bytes> {
+    match name {
+        b"" => shell.var(b"HOME").or_else(|| home_dir(None)),
+        b"+" => Some(Bytes::copy_from_slice(shell.cwd.as_os_str().as_bytes())),
+        b"-" => shell.var(b"OLDPWD"),
+        user => home_dir(Some(user)),
+    }
+}
+
+/// Looks up the home directory of a user, or of the current user, in the passwd database.
+fn home_dir(user: Option<&[u8]>) -> Option<Bytes> {
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0; 16384];
+    let mut result = std::ptr::null_mut();
+    let status = match user {
+        Some(user) => {
+            let user = CString::new(user).ok()?;
+            unsafe {
+                libc::getpwnam_r(
+                    user.as_ptr(),
+                    &mut passwd,
+                    buf.as_mut_ptr(),
+                    buf.len(),
+                    &mut result,
+                )
+            }
+        }
+        None => unsafe {
+            libc::getpwuid_r(
+                libc::getuid(),
+                &mut passwd,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        },
+    };
+    if status != 0 || result.is_null() {
+        return None;
     }
+    let home = unsafe { CStr::from_ptr(passwd.pw_dir) };
+    Some(Bytes::copy_from_slice(home.to_bytes()))
 }
 
 async fn run_compound_atom(
+    shell: &mut Shell,
     compound_atom: &ast::CompoundAtom,
-    out: &mut StringPool,
+    fields: &mut Fields<'_>,
 ) -> io::Result<()> {
-    todo!()
+    for atom in &compound_atom.atoms {
+        run_simple_atom(shell, atom, fields).await?;
+    }
+    Ok(())
 }
 
-async fn run_cmd_subst(script: &ast::Script, quoted: bool, out: &mut StringPool) -> io::Result<()> {
+async fn run_cmd_subst(
+    shell: &mut Shell,
+    script: &ast::Script,
+    quoted: bool,
+    fields: &mut Fields<'_>,
+) -> io::Result<()> {
     let stdout = Arc::new(Mutex::new(vec![]));
-    Box::pin(run_script(
+    // Command substitutions run in a subshell, changes to the state are discarded. Like in bash,
+    // `set -e` does not carry over into them.
+    let mut subshell = shell.clone();
+    subshell.options.errexit = false;
+    let exitstatus = Box::pin(run_script(
+        &mut subshell,
         script,
         Stdin::Inherit,
         Stdout::Pipe(stdout.clone()),
         Stdout::Inherit,
     ))
     .await?;
+    shell.cmd_subst_status = Some(exitstatus);
     let stdout = stdout.lock().await;
-    let stdout = stdout.as_slice();
+    let mut stdout = stdout.as_slice();
+    while let [rest @ .., b'\n'] = stdout {
+        stdout = rest;
+    }
     if quoted {
-        out.push_str(stdout);
+        fields.push_quoted(stdout);
     } else {
-        word_splitting(stdout, out);
+        fields.push_unquoted(stdout);
     }
     Ok(())
 }
 
-fn word_splitting(text: &[u8], out: &mut StringPool) {
-    for word in text.split(|b| *b == b' ' || *b == b'\n' || *b == b'\t') {
-        out.push_str(word);
+/// How quoted text is kept literal in a word that is matched against.
+#[derive(Clone, Copy)]
+enum Quoting {
+    /// The word is used as is.
+    None,
+    /// The word is a pattern, see [`pattern`].
+    Pattern,
+    /// The word is a regex.
+    Regex,
+}
+
+/// Collects the fields that a word expands to.
+struct Fields<'a> {
+    out: &'a mut StringPool,
+    /// Field separators, `None` disables field splitting.
+    ifs: Option<Bytes>,
+    quoting: Quoting,
+    current: BytesMut,
+    in_field: bool,
+}
+
+impl<'a> Fields<'a> {
+    fn new(out: &'a mut StringPool, ifs: Option<Bytes>, quoting: Quoting) -> Self {
+        Self {
+            out,
+            ifs,
+            quoting,
+            current: BytesMut::new(),
+            in_field: false,
+        }
+    }
+
+    /// Appends unquoted text from the script, which is special in a pattern or regex.
+    fn push_text(&mut self, text: &[u8]) {
+        match self.quoting {
+            // The lexer turns escaped pattern characters into quoted text, so only a backslash
+            // needs escaping.
+            Quoting::Pattern => {
+                for &c in text {
+                    if c == b'\\' {
+                        self.current.extend_from_slice(b"\\");
+                    }
+                    self.current.extend_from_slice(&[c]);
+                }
+            }
+            Quoting::None | Quoting::Regex => self.current.extend_from_slice(text),
+        }
+        self.in_field = true;
+    }
+
+    fn push_quoted(&mut self, text: &[u8]) {
+        match self.quoting {
+            Quoting::None => self.current.extend_from_slice(text),
+            Quoting::Pattern => pattern::escape(text, &mut self.current),
+            Quoting::Regex => escape_regex(text, &mut self.current),
+        }
+        self.in_field = true;
+    }
+
+    /// Appends the result of an unquoted expansion, splitting it on `$IFS`.
+    fn push_unquoted(&mut self, text: &[u8]) {
+        let Some(ifs) = self.ifs.clone() else {
+            self.push_text(text);
+            return;
+        };
+        let mut after_separator = !self.in_field;
+        for &c in text {
+            if !ifs.contains(&c) {
+                // Glob characters in the value are special, but a backslash is not an escape.
+                if c == b'\\' && matches!(self.quoting, Quoting::Pattern) {
+                    self.current.extend_from_slice(b"\\");
+                }
+                self.current.extend_from_slice(&[c]);
+                self.in_field = true;
+                after_separator = false;
+            } else if c.is_ascii_whitespace() {
+                self.delimit();
+            } else {
+                // Non-whitespace separators delimit a field even if it is empty.
+                if after_separator {
+                    self.in_field = true;
+                }
+                self.delimit();
+                after_separator = true;
+            }
+        }
+    }
+
+    /// Separates the arguments of `$@`, which are joined with spaces when there is no field
+    /// splitting.
+    fn delimit_arg(&mut self) {
+        if self.ifs.is_some() {
+            self.delimit();
+        } else {
+            self.push_quoted(b" ");
+        }
+    }
+
+    fn delimit(&mut self) {
+        if self.in_field {
+            self.out.push(self.current.split().freeze());
+            self.in_field = false;
+        }
     }
 }
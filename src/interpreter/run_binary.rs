@@ -1,7 +1,37 @@
-use crate::ast;
+use crate::{
+    ast,
+    interpreter::{run_boxed_expr, Shell, Stdin, Stdout},
+};
 use std::process::ExitStatus;
 use tokio::io;
 
-pub async fn run_binary(binary: &ast::Binary) -> io::Result<ExitStatus> {
-    todo!()
+pub async fn run_binary(
+    shell: &mut Shell,
+    binary: &ast::Binary,
+    stdin: Stdin,
+    stdout: Stdout,
+    stderr: Stdout,
+) -> io::Result<ExitStatus> {
+    shell.conditions += 1;
+    let left = run_boxed_expr(
+        shell,
+        &binary.left,
+        stdin.clone(),
+        stdout.clone(),
+        stderr.clone(),
+    )
+    .await;
+    shell.conditions -= 1;
+    let left = left?;
+    if shell.control.is_some() {
+        return Ok(left);
+    }
+    let run_right = match binary.op {
+        ast::Op::And => left.success(),
+        ast::Op::Or => !left.success(),
+    };
+    if !run_right {
+        return Ok(left);
+    }
+    run_boxed_expr(shell, &binary.right, stdin, stdout, stderr).await
 }
@@ -0,0 +1,733 @@
+#[cfg(feature = "coreutils")]
+use crate::interpreter::coreutils::{run_coreutil, Coreutil};
+use crate::interpreter::{exit_code, is_var_name, Control, Options, Shell, Stdin, Stdout};
+use bytes::Bytes;
+use std::{
+    ffi::OsStr,
+    os::unix::{ffi::OsStrExt, process::ExitStatusExt},
+    path::{Component, Path, PathBuf},
+    process::ExitStatus,
+};
+use tokio::io;
+
+/// Commands that are implemented by the shell itself, they take precedence over `$PATH`.
+#[derive(Debug, Copy, Clone)]
+pub enum Builtin {
+    Wait,
+    Shift,
+    Set,
+    Shopt,
+    Break,
+    Continue,
+    Read,
+    Return,
+    Local,
+    Cd,
+    Pwd,
+    Export,
+    Unset,
+    Exit,
+    True,
+    False,
+    #[cfg(feature = "coreutils")]
+    Coreutil(Coreutil),
+}
+
+impl Builtin {
+    pub fn parse(name: &[u8]) -> Option<Self> {
+        Some(match name {
+            b"wait" => Self::Wait,
+            b"shift" => Self::Shift,
+            b"set" => Self::Set,
+            b"shopt" => Self::Shopt,
+            b"break" => Self::Break,
+            b"continue" => Self::Continue,
+            b"read" => Self::Read,
+            b"return" => Self::Return,
+            b"local" => Self::Local,
+            b"cd" => Self::Cd,
+            b"pwd" => Self::Pwd,
+            b"export" => Self::Export,
+            b"unset" => Self::Unset,
+            b"exit" => Self::Exit,
+            b"true" | b":" => Self::True,
+            b"false" => Self::False,
+            #[cfg(feature = "coreutils")]
+            name => Self::Coreutil(Coreutil::parse(name)?),
+            #[cfg(not(feature = "coreutils"))]
+            _ => return None,
+        })
+    }
+}
+
+pub async fn run_builtin(
+    shell: &mut Shell,
+    builtin: Builtin,
+    args: &[Bytes],
+    stdin: Stdin,
+    stdout: Stdout,
+    stderr: Stdout,
+) -> io::Result<ExitStatus> {
+    let code = match builtin {
+        Builtin::Wait => run_wait(shell, args, &stderr).await?,
+        Builtin::Shift => run_shift(shell, args, &stderr).await?,
+        Builtin::Set => run_set(shell, args, &stdout, &stderr).await?,
+        Builtin::Shopt => run_shopt(shell, args, &stdout, &stderr).await?,
+        Builtin::Break => run_loop_control(shell, "break", args, &stderr).await?,
+        Builtin::Continue => run_loop_control(shell, "continue", args, &stderr).await?,
+        Builtin::Read => run_read(shell, args, &stdin, &stderr).await?,
+        Builtin::Return => run_return(shell, args, &stderr).await?,
+        Builtin::Local => run_local(shell, args, &stderr).await?,
+        Builtin::Cd => run_cd(shell, args, &stdout, &stderr).await?,
+        Builtin::Pwd => run_pwd(shell, args, &stdout, &stderr).await?,
+        Builtin::Export => run_export(shell, args, &stdout, &stderr).await?,
+        Builtin::Unset => run_unset(shell, args, &stderr).await?,
+        Builtin::Exit => run_exit(shell, args, &stderr).await?,
+        Builtin::True => 0,
+        Builtin::False => 1,
+        #[cfg(feature = "coreutils")]
+        Builtin::Coreutil(util) => {
+            run_coreutil(shell, util, args, &stdin, &stdout, &stderr).await?
+        }
+    };
+    Ok(ExitStatus::from_raw(code << 8))
+}
+
+/// `wait [pid...]`, without pids it waits for all jobs and succeeds, otherwise its status is
+/// that of the last pid.
+async fn run_wait(shell: &mut Shell, args: &[Bytes], stderr: &Stdout) -> io::Result<i32> {
+    if args.is_empty() {
+        shell.jobs.wait_all().await?;
+        return Ok(0);
+    }
+    let mut code = 0;
+    for arg in args {
+        let arg = String::from_utf8_lossy(arg);
+        let Ok(pid) = arg.parse() else {
+            stderr
+                .write_all_err(format!("shl: wait: `{arg}': not a pid\n").as_bytes())
+                .await?;
+            code = 2;
+            continue;
+        };
+        code = match shell.jobs.wait(pid).await {
+            Some(exitstatus) => exit_code(exitstatus?),
+            None => {
+                stderr
+                    .write_all_err(
+                        format!("shl: wait: pid {pid} is not a child of this shell\n").as_bytes(),
+                    )
+                    .await?;
+                127
+            }
+        };
+    }
+    Ok(code)
+}
+
+/// `shift [n]`, fails without shifting if there are fewer than `n` positional parameters.
+async fn run_shift(shell: &mut Shell, args: &[Bytes], stderr: &Stdout) -> io::Result<i32> {
+    let n = match args {
+        [] => 1,
+        [n] => {
+            let n = String::from_utf8_lossy(n);
+            let Ok(n) = n.parse() else {
+                stderr
+                    .write_all_err(
+                        format!("shl: shift: {n}: numeric argument required\n").as_bytes(),
+                    )
+                    .await?;
+                return Ok(2);
+            };
+            n
+        }
+        _ => {
+            stderr
+                .write_all_err(b"shl: shift: too many arguments\n")
+                .await?;
+            return Ok(2);
+        }
+    };
+    if n > shell.args.len() {
+        return Ok(1);
+    }
+    shell.args.drain(..n);
+    Ok(0)
+}
+
+/// `set [-efux] [-o name] [--] [arg...]`, turns options on, or off with `+`, and replaces the
+/// positional parameters with the args. Without a name, `-o` and `+o` list the options.
+async fn run_set(
+    shell: &mut Shell,
+    args: &[Bytes],
+    stdout: &Stdout,
+    stderr: &Stdout,
+) -> io::Result<i32> {
+    let mut args = args;
+    let mut replace_args = false;
+    while let [first, rest @ ..] = args {
+        let (on, flags) = match first.split_first() {
+            Some((b'-', flags)) => (true, flags),
+            Some((b'+', flags)) => (false, flags),
+            _ => break,
+        };
+        args = rest;
+        // `--` ends the options, as does a lone `-` or `+` which keeps the arguments if none
+        // follow.
+        if flags.is_empty() || flags == b"-" {
+            replace_args = flags == b"-" || !rest.is_empty();
+            break;
+        }
+        for &flag in flags {
+            if flag == b'o' {
+                let [name, rest @ ..] = args else {
+                    stdout.write_all(&list_options(&shell.options, !on)).await?;
+                    continue;
+                };
+                args = rest;
+                if !shell.options.set(name, on) {
+                    let name = String::from_utf8_lossy(name);
+                    stderr
+                        .write_all_err(
+                            format!("shl: set: {name}: invalid option name\n").as_bytes(),
+                        )
+                        .await?;
+                    return Ok(2);
+                }
+                continue;
+            }
+            let Some(name) = Options::flag_name(flag) else {
+                let sign = if on { '-' } else { '+' };
+                let flag = flag as char;
+                stderr
+                    .write_all_err(format!("shl: set: {sign}{flag}: invalid option\n").as_bytes())
+                    .await?;
+                return Ok(2);
+            };
+            shell.options.set(name.as_bytes(), on);
+        }
+    }
+    if replace_args || !args.is_empty() {
+        shell.args = args.to_vec();
+    }
+    Ok(0)
+}
+
+/// `shopt [-pqsu] [-o] [name...]`, turns options on with `-s` and off with `-u`, otherwise lists
+/// them and fails if any of the names is off. There are no options but those of `set -o`, so
+/// `-o` is implied.
+async fn run_shopt(
+    shell: &mut Shell,
+    args: &[Bytes],
+    stdout: &Stdout,
+    stderr: &Stdout,
+) -> io::Result<i32> {
+    let mut set = None;
+    let mut print = false;
+    let mut quiet = false;
+    let mut args = args;
+    while let [first, rest @ ..] = args {
+        if first.len() < 2 || first[0] != b'-' {
+            break;
+        }
+        args = rest;
+        if &first[..] == b"--" {
+            break;
+        }
+        for &flag in &first[1..] {
+            match flag {
+                b's' => set = Some(true),
+                b'u' => set = Some(false),
+                b'p' => print = true,
+                b'q' => quiet = true,
+                b'o' => {}
+                flag => {
+                    let flag = flag as char;
+                    stderr
+                        .write_all_err(format!("shl: shopt: -{flag}: invalid option\n").as_bytes())
+                        .await?;
+                    return Ok(2);
+                }
+            }
+        }
+    }
+    let mut code = 0;
+    if let (Some(on), false) = (set, args.is_empty()) {
+        for name in args {
+            if !shell.options.set(name, on) {
+                let name = String::from_utf8_lossy(name);
+                stderr
+                    .write_all_err(
+                        format!("shl: shopt: {name}: invalid shell option name\n").as_bytes(),
+                    )
+                    .await?;
+                code = 1;
+            }
+        }
+        return Ok(code);
+    }
+    let names: Vec<_> = if args.is_empty() {
+        Options::names().map(|name| name.as_bytes()).collect()
+    } else {
+        args.iter().map(|name| &name[..]).collect()
+    };
+    let mut out = vec![];
+    for name in names {
+        let Some(on) = shell.options.get(name) else {
+            let name = String::from_utf8_lossy(name);
+            stderr
+                .write_all_err(
+                    format!("shl: shopt: {name}: invalid shell option name\n").as_bytes(),
+                )
+                .await?;
+            code = 1;
+            continue;
+        };
+        // `shopt -s` and `shopt -u` without names list the options that are on or off.
+        if set.is_some_and(|set| set != on) {
+            continue;
+        }
+        if !on && !args.is_empty() {
+            code = 1;
+        }
+        out.extend(format_option(name, on, print));
+    }
+    if !quiet {
+        stdout.write_all(&out).await?;
+    }
+    Ok(code)
+}
+
+/// Lists the options like `set -o`, or like `set +o` as commands that restore them.
+fn list_options(options: &Options, as_commands: bool) -> Vec<u8> {
+    Options::names()
+        .flat_map(|name| {
+            let on = options.get(name.as_bytes()).unwrap_or_default();
+            format_option(name.as_bytes(), on, as_commands)
+        })
+        .collect()
+}
+
+fn format_option(name: &[u8], on: bool, as_command: bool) -> Vec<u8> {
+    let name = String::from_utf8_lossy(name);
+    let line = match (as_command, on) {
+        (true, true) => format!("set -o {name}\n"),
+        (true, false) => format!("set +o {name}\n"),
+        (false, true) => format!("{name:<15}\ton\n"),
+        (false, false) => format!("{name:<15}\toff\n"),
+    };
+    line.into_bytes()
+}
+
+/// `break [n]` and `continue [n]`, leave a signal for the `n`th enclosing loop. A level beyond
+/// the outermost loop refers to it.
+async fn run_loop_control(
+    shell: &mut Shell,
+    name: &str,
+    args: &[Bytes],
+    stderr: &Stdout,
+) -> io::Result<i32> {
+    let n = match args {
+        [] => 1,
+        [n] => {
+            let n = String::from_utf8_lossy(n);
+            match n.parse() {
+                Ok(0) => {
+                    stderr
+                        .write_all_err(
+                            format!("shl: {name}: {n}: loop count out of range\n").as_bytes(),
+                        )
+                        .await?;
+                    return Ok(1);
+                }
+                Ok(n) => n,
+                Err(_) => {
+                    stderr
+                        .write_all_err(
+                            format!("shl: {name}: {n}: numeric argument required\n").as_bytes(),
+                        )
+                        .await?;
+                    return Ok(2);
+                }
+            }
+        }
+        _ => {
+            stderr
+                .write_all_err(format!("shl: {name}: too many arguments\n").as_bytes())
+                .await?;
+            return Ok(2);
+        }
+    };
+    if shell.loop_depth == 0 {
+        stderr
+            .write_all_err(
+                format!("shl: {name}: only meaningful in a `for', `while', or `until' loop\n")
+                    .as_bytes(),
+            )
+            .await?;
+        return Ok(0);
+    }
+    let n = n.min(shell.loop_depth);
+    shell.control = Some(match name {
+        "break" => Control::Break(n),
+        _ => Control::Continue(n),
+    });
+    Ok(0)
+}
+
+/// `read [-r] [name...]`, reads a line and splits it on `$IFS` into the variables, the last of
+/// which gets the rest of the line. Without names the whole line is stored in `$REPLY`. Unless
+/// `-r` is given, a backslash escapes the next character and a trailing one continues the line.
+async fn run_read(
+    shell: &mut Shell,
+    args: &[Bytes],
+    stdin: &Stdin,
+    stderr: &Stdout,
+) -> io::Result<i32> {
+    let (raw, names) = match args {
+        [first, rest @ ..] if first.as_ref() == b"-r" => (true, rest),
+        [first, ..] if first.starts_with(b"-") && first.len() > 1 => {
+            let option = String::from_utf8_lossy(first);
+            stderr
+                .write_all_err(format!("shl: read: {option}: invalid option\n").as_bytes())
+                .await?;
+            return Ok(2);
+        }
+        names => (false, names),
+    };
+    // The line along with whether each byte was escaped, escaped bytes never split it.
+    let mut line: Vec<(u8, bool)> = vec![];
+    let mut code = 0;
+    loop {
+        let mut part = vec![];
+        if !stdin.read_line(&mut part).await? {
+            // The end of input still assigns what was read, but fails.
+            code = 1;
+        }
+        let mut bytes = part.into_iter();
+        let mut continued = false;
+        while let Some(byte) = bytes.next() {
+            if raw || byte != b'\\' {
+                line.push((byte, false));
+                continue;
+            }
+            match bytes.next() {
+                Some(byte) => line.push((byte, true)),
+                None => continued = true,
+            }
+        }
+        if !continued || code != 0 {
+            break;
+        }
+    }
+    let [names @ .., last] = names else {
+        let reply = line.iter().map(|&(byte, _)| byte).collect::<Vec<_>>();
+        shell.set_var(Bytes::from_static(b"REPLY"), reply.into());
+        return Ok(code);
+    };
+    let ifs = shell.ifs();
+    let is_ifs = |&(byte, escaped): &(u8, bool)| !escaped && ifs.contains(&byte);
+    let is_ifs_space = |c: &(u8, bool)| is_ifs(c) && c.0.is_ascii_whitespace();
+    let mut rest = &line[..];
+    while let [first, tail @ ..] = rest {
+        if !is_ifs_space(first) {
+            break;
+        }
+        rest = tail;
+    }
+    for name in names {
+        let end = rest.iter().position(is_ifs).unwrap_or(rest.len());
+        let value = rest[..end]
+            .iter()
+            .map(|&(byte, _)| byte)
+            .collect::<Vec<_>>();
+        shell.set_var(name.clone(), value.into());
+        rest = &rest[end..];
+        // A field ends at whitespace from `$IFS` and at most one other `$IFS` character.
+        let mut seen_delimiter = false;
+        while let [first, tail @ ..] = rest {
+            match first {
+                c if is_ifs_space(c) => {}
+                c if is_ifs(c) && !seen_delimiter => seen_delimiter = true,
+                _ => break,
+            }
+            rest = tail;
+        }
+    }
+    while let [init @ .., last] = rest {
+        if !is_ifs_space(last) {
+            break;
+        }
+        rest = init;
+    }
+    let value = rest.iter().map(|&(byte, _)| byte).collect::<Vec<_>>();
+    shell.set_var(last.clone(), value.into());
+    Ok(code)
+}
+
+/// `return [n]`, ends the running function call with status `n`, or that of the last command.
+async fn run_return(shell: &mut Shell, args: &[Bytes], stderr: &Stdout) -> io::Result<i32> {
+    if shell.call_depth() == 0 {
+        stderr
+            .write_all_err(b"shl: return: can only `return' from a function\n")
+            .await?;
+        return Ok(1);
+    }
+    let code = match args {
+        [] => exit_code(shell.last_status),
+        [n] => {
+            let n = String::from_utf8_lossy(n);
+            let Ok(n) = n.parse::<i64>() else {
+                stderr
+                    .write_all_err(
+                        format!("shl: return: {n}: numeric argument required\n").as_bytes(),
+                    )
+                    .await?;
+                return Ok(2);
+            };
+            // Like an exit status, only the low 8 bits are kept.
+            (n & 0xff) as i32
+        }
+        _ => {
+            stderr
+                .write_all_err(b"shl: return: too many arguments\n")
+                .await?;
+            return Ok(2);
+        }
+    };
+    shell.control = Some(Control::Return);
+    Ok(code)
+}
+
+/// `local name[=value]...`, makes variables local to the running function call.
+async fn run_local(shell: &mut Shell, args: &[Bytes], stderr: &Stdout) -> io::Result<i32> {
+    let mut code = 0;
+    for arg in args {
+        let (name, value) = split_assignment(arg);
+        if !is_var_name(&name) {
+            let arg = String::from_utf8_lossy(arg);
+            stderr
+                .write_all_err(format!("shl: local: `{arg}': not a valid identifier\n").as_bytes())
+                .await?;
+            code = 1;
+            continue;
+        }
+        if !shell.set_local_var(name, value) {
+            stderr
+                .write_all_err(b"shl: local: can only be used in a function\n")
+                .await?;
+            return Ok(1);
+        }
+    }
+    Ok(code)
+}
+
+/// Splits `name=value`, a word without `=` is just a name.
+fn split_assignment(arg: &Bytes) -> (Bytes, Option<Bytes>) {
+    match arg.iter().position(|&c| c == b'=') {
+        Some(i) => (arg.slice(..i), Some(arg.slice(i + 1..))),
+        None => (arg.clone(), None),
+    }
+}
+
+/// `cd [dir]`, changes the logical working directory, to `$HOME` without a dir and to
+/// `$OLDPWD` for `-`. A `..` removes the last component of the path rather than following
+/// symlinks.
+async fn run_cd(
+    shell: &mut Shell,
+    args: &[Bytes],
+    stdout: &Stdout,
+    stderr: &Stdout,
+) -> io::Result<i32> {
+    let dir = match args {
+        [] => shell.var(b"HOME").ok_or("HOME"),
+        [dir] if &dir[..] == b"-" => shell.var(b"OLDPWD").ok_or("OLDPWD"),
+        [dir] => Ok(dir.clone()),
+        _ => {
+            stderr
+                .write_all_err(b"shl: cd: too many arguments\n")
+                .await?;
+            return Ok(1);
+        }
+    };
+    let dir = match dir {
+        Ok(dir) => dir,
+        Err(var) => {
+            stderr
+                .write_all_err(format!("shl: cd: {var} not set\n").as_bytes())
+                .await?;
+            return Ok(1);
+        }
+    };
+    let path = normalize_path(&shell.cwd.join(OsStr::from_bytes(&dir)));
+    let error = match tokio::fs::metadata(&path).await {
+        Ok(metadata) if metadata.is_dir() => None,
+        Ok(_) => Some("Not a directory".to_owned()),
+        Err(err) => Some(err.to_string()),
+    };
+    if let Some(error) = error {
+        let dir = String::from_utf8_lossy(&dir);
+        stderr
+            .write_all_err(format!("shl: cd: {dir}: {error}\n").as_bytes())
+            .await?;
+        return Ok(1);
+    }
+    let new = Bytes::copy_from_slice(path.as_os_str().as_bytes());
+    let old = std::mem::replace(&mut shell.cwd, path);
+    shell.set_var(
+        Bytes::from_static(b"OLDPWD"),
+        Bytes::copy_from_slice(old.as_os_str().as_bytes()),
+    );
+    shell.set_var(Bytes::from_static(b"PWD"), new.clone());
+    // `cd -` prints where it went.
+    if matches!(args, [dir] if &dir[..] == b"-") {
+        stdout.write_all(&[&new[..], b"\n"].concat()).await?;
+    }
+    Ok(0)
+}
+
+/// Resolves `.` and `..` in an absolute path without looking at the file system.
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// `pwd [-L | -P]`, prints the logical working directory, or with `-P` the one with all symlinks
+/// resolved.
+async fn run_pwd(
+    shell: &mut Shell,
+    args: &[Bytes],
+    stdout: &Stdout,
+    stderr: &Stdout,
+) -> io::Result<i32> {
+    let physical = match args.last().map(|arg| &arg[..]) {
+        None | Some(b"-L") => false,
+        Some(b"-P") => true,
+        Some(arg) => {
+            let arg = String::from_utf8_lossy(arg);
+            stderr
+                .write_all_err(format!("shl: pwd: {arg}: invalid option\n").as_bytes())
+                .await?;
+            return Ok(2);
+        }
+    };
+    let path = if physical {
+        tokio::fs::canonicalize(&shell.cwd).await?
+    } else {
+        shell.cwd.clone()
+    };
+    stdout
+        .write_all(&[path.as_os_str().as_bytes(), b"\n"].concat())
+        .await?;
+    Ok(0)
+}
+
+/// `export [-n] [name[=value]...]`, marks variables to be passed to commands, or with `-n` not
+/// to. Without names it lists the exported variables.
+async fn run_export(
+    shell: &mut Shell,
+    args: &[Bytes],
+    stdout: &Stdout,
+    stderr: &Stdout,
+) -> io::Result<i32> {
+    let (unexport, args) = match args {
+        [first, rest @ ..] if &first[..] == b"-n" => (true, rest),
+        [first, rest @ ..] if &first[..] == b"-p" => (false, rest),
+        args => (false, args),
+    };
+    if args.is_empty() {
+        let mut vars: Vec<_> = shell.exported_vars().collect();
+        vars.sort();
+        let mut out = vec![];
+        for (name, value) in vars {
+            out.extend_from_slice(b"declare -x ");
+            out.extend_from_slice(name.as_bytes());
+            out.extend_from_slice(b"=\"");
+            for &c in value.as_bytes() {
+                if b"\"\\$`".contains(&c) {
+                    out.push(b'\\');
+                }
+                out.push(c);
+            }
+            out.extend_from_slice(b"\"\n");
+        }
+        stdout.write_all(&out).await?;
+        return Ok(0);
+    }
+    let mut code = 0;
+    for arg in args {
+        let (name, value) = split_assignment(arg);
+        if !is_var_name(&name) {
+            let arg = String::from_utf8_lossy(arg);
+            stderr
+                .write_all_err(format!("shl: export: `{arg}': not a valid identifier\n").as_bytes())
+                .await?;
+            code = 1;
+        } else if unexport {
+            shell.unexport_var(&name);
+        } else {
+            shell.export_var(name, value);
+        }
+    }
+    Ok(code)
+}
+
+/// `unset [-v | -f] name...`, unsets variables, or with `-f` functions.
+async fn run_unset(shell: &mut Shell, args: &[Bytes], stderr: &Stdout) -> io::Result<i32> {
+    let (functions, args) = match args {
+        [first, rest @ ..] if &first[..] == b"-f" => (true, rest),
+        [first, rest @ ..] if &first[..] == b"-v" => (false, rest),
+        args => (false, args),
+    };
+    let mut code = 0;
+    for name in args {
+        if functions {
+            shell.functions.remove(name);
+        } else if is_var_name(name) {
+            shell.unset_var(name);
+        } else {
+            let name = String::from_utf8_lossy(name);
+            stderr
+                .write_all_err(format!("shl: unset: `{name}': not a valid identifier\n").as_bytes())
+                .await?;
+            code = 1;
+        }
+    }
+    Ok(code)
+}
+
+/// `exit [n]`, ends the script with status `n`, or that of the last command.
+async fn run_exit(shell: &mut Shell, args: &[Bytes], stderr: &Stdout) -> io::Result<i32> {
+    let code = match args {
+        [] => exit_code(shell.last_status),
+        [n] => match String::from_utf8_lossy(n).parse::<i64>() {
+            Ok(n) => (n & 0xff) as i32,
+            Err(_) => {
+                let n = String::from_utf8_lossy(n);
+                stderr
+                    .write_all_err(
+                        format!("shl: exit: {n}: numeric argument required\n").as_bytes(),
+                    )
+                    .await?;
+                2
+            }
+        },
+        _ => {
+            stderr
+                .write_all_err(b"shl: exit: too many arguments\n")
+                .await?;
+            return Ok(1);
+        }
+    };
+    shell.control = Some(Control::Exit);
+    Ok(code)
+}
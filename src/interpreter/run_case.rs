@@ -0,0 +1,62 @@
+use crate::{
+    ast,
+    interpreter::{
+        pattern,
+        run_atom::{run_atom_to_bytes, run_atom_to_pattern},
+        run_redirect::{redirect_error, run_redirects},
+        run_stmts, Shell, Stdin, Stdout,
+    },
+};
+use std::{os::unix::process::ExitStatusExt, process::ExitStatus};
+use tokio::io;
+
+pub async fn run_case(
+    shell: &mut Shell,
+    case: &ast::Case,
+    stdin: Stdin,
+    stdout: Stdout,
+    stderr: Stdout,
+) -> io::Result<ExitStatus> {
+    let (stdin, stdout, stderr) =
+        match run_redirects(shell, &case.redirects, stdin, stdout, stderr.clone()).await {
+            Ok(stdio) => stdio,
+            Err(err) => return redirect_error(&stderr, err).await,
+        };
+    let word = run_atom_to_bytes(shell, &case.word).await?;
+    let mut exitstatus = ExitStatus::from_raw(0);
+    // After `;&` the next body runs whether or not its patterns match.
+    let mut fall_through = false;
+    for item in &case.items {
+        if !fall_through && !matches_any(shell, &item.patterns, &word).await? {
+            continue;
+        }
+        exitstatus = Box::pin(run_stmts(
+            shell,
+            &item.body,
+            stdin.clone(),
+            stdout.clone(),
+            stderr.clone(),
+        ))
+        .await?;
+        if shell.control.is_some() {
+            break;
+        }
+        match item.terminator {
+            ast::CaseTerminator::Break => break,
+            ast::CaseTerminator::FallThrough => fall_through = true,
+            ast::CaseTerminator::Continue => fall_through = false,
+        }
+    }
+    Ok(exitstatus)
+}
+
+/// Whether any of the patterns of an item matches, they are expanded lazily from left to right.
+async fn matches_any(shell: &mut Shell, patterns: &[ast::Atom], word: &[u8]) -> io::Result<bool> {
+    for pattern in patterns {
+        let pattern = run_atom_to_pattern(shell, pattern).await?;
+        if pattern::matches(&pattern, word) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
@@ -1,79 +1,272 @@
 use crate::{
     ast,
-    interpreter::{run_atom::run_atom, Stdin, Stdout},
+    interpreter::{
+        is_var_name,
+        run_atom::{run_atom, run_atom_to_bytes},
+        run_builtin::{run_builtin, Builtin},
+        run_function::run_function,
+        run_redirect::{redirect_error, run_redirects},
+        Shell, Stdin, Stdout,
+    },
     stringpool::StringPool,
 };
-use futures::future::{join_all, BoxFuture};
-use std::{future::Future, ops::DerefMut, process::ExitStatus};
+use bytes::Bytes;
+use futures::future::{join, join_all, BoxFuture};
+use std::{
+    ffi::OsStr,
+    future::Future,
+    os::unix::{ffi::OsStrExt, process::ExitStatusExt},
+    process::{ExitStatus, Stdio},
+    sync::Arc,
+};
 use tokio::{
-    io::{self, AsyncWriteExt},
-    process::Command,
+    io::{self, AsyncReadExt, AsyncWriteExt},
+    process::{Child, Command},
+    sync::Mutex,
 };
 
 pub fn run_cmd<'a, 'b>(
+    shell: &'a mut Shell,
     cmd: &'b ast::Cmd,
     stdin: Stdin,
     stdout: Stdout,
     stderr: Stdout,
 ) -> impl Future<Output = io::Result<ExitStatus>> + Send + use<'a, 'b> {
     async move {
-        let mut args = StringPool::new();
-        for arg in &cmd.name_and_args {
-            run_atom(arg, &mut args).await?;
+        let expanded = expand_cmd(shell, cmd).await?;
+        if shell.options.xtrace {
+            xtrace(shell, &expanded.assigns, &expanded.args, &stderr).await?;
         }
-        let mut child = Command::new(str::from_utf8(&args.get_strings()[0]).unwrap())
-            .args(
-                args.get_strings()[1..]
-                    .iter()
-                    .map(|s| str::from_utf8(s).unwrap()),
-            )
-            .stdin(&stdin)
-            .stdout(&stdout)
-            .stderr(&stderr)
-            .spawn()
-            .unwrap();
-        let mut stdin = if let Stdin::Pipe(stdin) = &stdin {
-            Some(stdin.lock().await)
-        } else {
-            None
-        };
-        let mut stdout = if let Stdout::Pipe(stdout) = &stdout {
-            Some(stdout.lock().await)
-        } else {
-            None
+        let (stdin, stdout, stderr) =
+            match run_redirects(shell, &cmd.redirects, stdin, stdout, stderr.clone()).await {
+                Ok(stdio) => stdio,
+                Err(err) => return redirect_error(&stderr, err).await,
+            };
+        let [name, args @ ..] = &expanded.args[..] else {
+            return Ok(ExitStatus::from_raw(0));
         };
-        let mut stderr = if let Stdout::Pipe(stderr) = &stderr {
-            Some(stderr.lock().await)
+        if let Some(body) = shell.functions.get(name).cloned() {
+            let saved = shell.set_temp_vars(&expanded.assigns);
+            let result = run_function(shell, name, body, args, stdin, stdout, stderr).await;
+            shell.restore_vars(saved);
+            return result;
+        }
+        if let Some(builtin) = Builtin::parse(name) {
+            let saved = shell.set_temp_vars(&expanded.assigns);
+            let result = run_builtin(shell, builtin, args, stdin, stdout, stderr).await;
+            shell.restore_vars(saved);
+            return result;
+        }
+        match spawn_cmd(shell, &expanded, stdin, stdout, stderr.clone()) {
+            Ok(spawned) => spawned.wait().await,
+            Err(err) => spawn_error(&stderr, name, err).await,
+        }
+    }
+}
+
+/// Reports a command that could not be started, it fails with 127 if it was not found and 126
+/// if it could not be executed. Other errors are passed on.
+pub async fn spawn_error(stderr: &Stdout, name: &[u8], err: io::Error) -> io::Result<ExitStatus> {
+    let name = String::from_utf8_lossy(name);
+    let (message, code) = match err.kind() {
+        io::ErrorKind::NotFound => ("command not found", 127),
+        io::ErrorKind::PermissionDenied => ("Permission denied", 126),
+        _ => return Err(err),
+    };
+    stderr
+        .write_all_err(format!("shl: {name}: {message}\n").as_bytes())
+        .await?;
+    Ok(ExitStatus::from_raw(code << 8))
+}
+
+/// With `set -x`, prints a command to stderr after `$PS4` before it runs. Words are quoted where
+/// they would not read back the same.
+pub async fn xtrace(
+    shell: &Shell,
+    assigns: &[(Bytes, Bytes)],
+    args: &[Bytes],
+    stderr: &Stdout,
+) -> io::Result<()> {
+    let mut line = shell
+        .var(b"PS4")
+        .unwrap_or_else(|| Bytes::from_static(b"+ "))
+        .to_vec();
+    let assigns = assigns
+        .iter()
+        .map(|(name, value)| [&name[..], b"=", &quote(value)].concat());
+    for (i, word) in assigns.chain(args.iter().map(|arg| quote(arg))).enumerate() {
+        if i > 0 {
+            line.push(b' ');
+        }
+        line.extend(word);
+    }
+    line.push(b'\n');
+    stderr.write_all_err(&line).await
+}
+
+fn quote(word: &[u8]) -> Vec<u8> {
+    let plain = !word.is_empty()
+        && word
+            .iter()
+            .all(|c| c.is_ascii_alphanumeric() || b"_@%+=:,./-".contains(c));
+    if plain {
+        return word.to_vec();
+    }
+    let mut quoted = vec![b'\''];
+    for &c in word {
+        if c == b'\'' {
+            quoted.extend_from_slice(b"'\\''");
         } else {
-            None
-        };
-        let bump = bumpalo::Bump::new();
-        let mut futures: Vec<BoxFuture<io::Result<u64>>> = vec![];
-        if let Some(stdin) = &mut stdin {
-            let child_stdin = bump.alloc(child.stdin.take().unwrap());
-            let stdin = stdin.deref_mut();
-            futures.push(Box::pin(io::copy(stdin, child_stdin)));
+            quoted.push(c);
         }
-        if let Some(stdout) = &mut stdout {
-            let child_stdout = bump.alloc(child.stdout.take().unwrap());
-            let stdout = stdout.deref_mut();
-            futures.push(Box::pin(io::copy(child_stdout, stdout)));
+    }
+    quoted.push(b'\'');
+    quoted
+}
+
+/// A command after word expansion.
+pub struct ExpandedCmd {
+    pub args: Vec<Bytes>,
+    pub assigns: Vec<(Bytes, Bytes)>,
+}
+
+pub async fn expand_cmd(shell: &mut Shell, cmd: &ast::Cmd) -> io::Result<ExpandedCmd> {
+    let mut args = StringPool::new();
+    for arg in &cmd.name_and_args {
+        // Declaration builtins take `name=value` words as assignments, which are not split.
+        let declaration = matches!(args.get_strings().first(), Some(name) if matches!(&name[..], b"local" | b"export"));
+        if declaration && is_assignment_word(arg) {
+            args.push(run_atom_to_bytes(shell, arg).await?);
+        } else {
+            run_atom(shell, arg, &mut args).await?;
         }
-        if let Some(stderr) = &mut stderr {
-            let child_stderr = bump.alloc(child.stderr.take().unwrap());
-            let stderr = stderr.deref_mut();
-            futures.push(Box::pin(io::copy(child_stderr, stderr)));
+    }
+    let mut assigns = vec![];
+    for assign in &cmd.assigns {
+        let value = run_atom_to_bytes(shell, &assign.value).await?;
+        assigns.push((assign.label.clone(), value));
+    }
+    Ok(ExpandedCmd {
+        args: args.get_strings().to_vec(),
+        assigns,
+    })
+}
+
+fn is_assignment_word(arg: &ast::Atom) -> bool {
+    let first = match arg {
+        ast::Atom::Simple(atom) => Some(atom),
+        ast::Atom::CompoundAtom(atom) => atom.atoms.first(),
+    };
+    let Some(ast::SimpleAtom::Text(text)) = first else {
+        return false;
+    };
+    text.iter()
+        .position(|&c| c == b'=')
+        .is_some_and(|eq| is_var_name(&text[..eq]))
+}
+
+/// A running external command, along with the tasks that feed its in-memory stdio.
+pub struct SpawnedCmd {
+    child: Child,
+    copies: Vec<BoxFuture<'static, io::Result<()>>>,
+}
+
+pub fn spawn_cmd(
+    shell: &Shell,
+    cmd: &ExpandedCmd,
+    stdin: Stdin,
+    stdout: Stdout,
+    stderr: Stdout,
+) -> io::Result<SpawnedCmd> {
+    let name = OsStr::from_bytes(&cmd.args[0]);
+    // A relative path such as `./run.sh` is relative to the logical working directory.
+    let program = if cmd.args[0].contains(&b'/') {
+        shell.cwd.join(name).into_os_string()
+    } else {
+        name.to_owned()
+    };
+    let mut command = Command::new(program);
+    command
+        .args(cmd.args[1..].iter().map(|arg| OsStr::from_bytes(arg)))
+        .current_dir(&shell.cwd)
+        .env_clear()
+        .envs(shell.exported_vars())
+        .envs(
+            cmd.assigns
+                .iter()
+                .map(|(label, value)| (OsStr::from_bytes(label), OsStr::from_bytes(value))),
+        )
+        .stdin(Stdio::try_from(&stdin)?)
+        .stdout(Stdio::try_from(&stdout)?)
+        .stderr(Stdio::try_from(&stderr)?)
+        // Killed background jobs are dropped rather than waited for.
+        .kill_on_drop(true);
+    let fds = shell.proc_substs.fds();
+    if !fds.is_empty() {
+        // SAFETY: only async-signal-safe calls are made between fork and exec.
+        unsafe {
+            command.pre_exec(move || {
+                // Process substitutions are reached through `/dev/fd/N`, so the child keeps
+                // their file descriptors open.
+                for &fd in &fds {
+                    if libc::fcntl(fd, libc::F_SETFD, 0) == -1 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
         }
-        for result in join_all(futures).await {
+    }
+    let mut child = command.spawn()?;
+    let mut copies: Vec<BoxFuture<'static, io::Result<()>>> = vec![];
+    if let Stdin::Pipe(stdin) = stdin {
+        let mut child_stdin = child.stdin.take().unwrap();
+        copies.push(Box::pin(async move {
+            let mut stdin = stdin.lock().await;
+            match io::copy(&mut *stdin, &mut child_stdin).await {
+                // The child exited without reading all of its input.
+                Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+                // Dropping `child_stdin` closes the pipe so the child sees EOF.
+                result => result.map(|_| ()),
+            }
+        }));
+    }
+    if let Stdout::Pipe(stdout) = stdout {
+        let child_stdout = child.stdout.take().unwrap();
+        copies.push(Box::pin(copy_to_pipe(child_stdout, stdout)));
+    }
+    if let Stdout::Pipe(stderr) = stderr {
+        let child_stderr = child.stderr.take().unwrap();
+        copies.push(Box::pin(copy_to_pipe(child_stderr, stderr)));
+    }
+    Ok(SpawnedCmd { child, copies })
+}
+
+impl SpawnedCmd {
+    pub fn pid(&self) -> Option<u32> {
+        self.child.id()
+    }
+
+    pub async fn wait(mut self) -> io::Result<ExitStatus> {
+        let (results, success) = join(join_all(self.copies), self.child.wait()).await;
+        for result in results {
             result?;
         }
-        let success = child.wait().await?;
-        if let Some(mut stdout) = stdout {
-            stdout.shutdown().await?;
-        }
-        if let Some(mut stderr) = stderr {
-            stderr.shutdown().await?;
+        success
+    }
+}
+
+/// Copies `reader` into `pipe` chunk by chunk, so that stdout and stderr may share a pipe.
+async fn copy_to_pipe(
+    mut reader: impl io::AsyncRead + Unpin,
+    pipe: Arc<Mutex<dyn io::AsyncWrite + Send + Unpin>>,
+) -> io::Result<()> {
+    let mut buf = vec![0; 8192];
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(());
         }
-        Ok(success)
+        pipe.lock().await.write_all(&buf[..n]).await?;
     }
 }
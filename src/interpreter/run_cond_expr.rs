@@ -1,7 +1,119 @@
-use crate::ast;
-use std::process::ExitStatus;
+use crate::{
+    ast,
+    interpreter::{
+        pattern,
+        run_atom::{run_atom_to_bytes, run_atom_to_pattern, run_atom_to_regex},
+        Shell, Stdout,
+    },
+};
+use regex::bytes::Regex;
+use std::{
+    ffi::{CString, OsStr},
+    os::unix::{ffi::OsStrExt, process::ExitStatusExt},
+    path::Path,
+    process::ExitStatus,
+};
 use tokio::io;
 
-pub async fn run_cond_expr(cond_expr: &ast::CondExpr) -> io::Result<ExitStatus> {
-    todo!()
+/// Evaluates `[[ ... ]]`, the status is 0 if the expression is true, 1 if it is false and 2 if it
+/// is invalid, e.g. for a malformed integer or regex.
+pub async fn run_cond_expr(
+    shell: &mut Shell,
+    cond_expr: &ast::CondExpr,
+    stderr: Stdout,
+) -> io::Result<ExitStatus> {
+    let code = match eval_cond_expr(shell, cond_expr).await? {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(message) => {
+            stderr
+                .write_all_err(format!("shl: [[: {message}\n").as_bytes())
+                .await?;
+            2
+        }
+    };
+    Ok(ExitStatus::from_raw(code << 8))
+}
+
+async fn eval_cond_expr(
+    shell: &mut Shell,
+    cond_expr: &ast::CondExpr,
+) -> io::Result<Result<bool, String>> {
+    Ok(match cond_expr {
+        ast::CondExpr::Unary(op, atom) => {
+            let arg = run_atom_to_bytes(shell, atom).await?;
+            Ok(eval_unary(shell, *op, &arg))
+        }
+        ast::CondExpr::Binary(op, left, right) => {
+            let left = run_atom_to_bytes(shell, left).await?;
+            let right = match op {
+                ast::CondBinaryOp::Match | ast::CondBinaryOp::NotMatch => {
+                    run_atom_to_pattern(shell, right).await?
+                }
+                ast::CondBinaryOp::RegexMatch => run_atom_to_regex(shell, right).await?,
+                _ => run_atom_to_bytes(shell, right).await?,
+            };
+            eval_binary(*op, &left, &right)
+        }
+        ast::CondExpr::Word(atom) => Ok(!run_atom_to_bytes(shell, atom).await?.is_empty()),
+        ast::CondExpr::Not(cond_expr) => Box::pin(eval_cond_expr(shell, cond_expr))
+            .await?
+            .map(|b| !b),
+        ast::CondExpr::And(left, right) => match Box::pin(eval_cond_expr(shell, left)).await? {
+            Ok(true) => Box::pin(eval_cond_expr(shell, right)).await?,
+            result => result,
+        },
+        ast::CondExpr::Or(left, right) => match Box::pin(eval_cond_expr(shell, left)).await? {
+            Ok(false) => Box::pin(eval_cond_expr(shell, right)).await?,
+            result => result,
+        },
+    })
+}
+
+fn eval_unary(shell: &Shell, op: ast::CondUnaryOp, arg: &[u8]) -> bool {
+    let path = shell.cwd.join(Path::new(OsStr::from_bytes(arg)));
+    match op {
+        ast::CondUnaryOp::Exists => path.exists(),
+        ast::CondUnaryOp::RegularFile => path.is_file(),
+        ast::CondUnaryOp::Directory => path.is_dir(),
+        ast::CondUnaryOp::NonEmptyFile => path.metadata().is_ok_and(|m| m.len() > 0),
+        ast::CondUnaryOp::Readable => access(&path, libc::R_OK),
+        ast::CondUnaryOp::Writable => access(&path, libc::W_OK),
+        ast::CondUnaryOp::Executable => access(&path, libc::X_OK),
+        ast::CondUnaryOp::EmptyString => arg.is_empty(),
+        ast::CondUnaryOp::NonEmptyString => !arg.is_empty(),
+    }
+}
+
+fn access(path: &Path, mode: libc::c_int) -> bool {
+    let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    unsafe { libc::access(path.as_ptr(), mode) == 0 }
+}
+
+fn eval_binary(op: ast::CondBinaryOp, left: &[u8], right: &[u8]) -> Result<bool, String> {
+    Ok(match op {
+        ast::CondBinaryOp::Match => pattern::matches(right, left),
+        ast::CondBinaryOp::NotMatch => !pattern::matches(right, left),
+        ast::CondBinaryOp::RegexMatch => {
+            let right = String::from_utf8_lossy(right);
+            let regex = Regex::new(&right).map_err(|_| format!("invalid regex: {right}"))?;
+            regex.is_match(left)
+        }
+        ast::CondBinaryOp::Less => left < right,
+        ast::CondBinaryOp::Greater => left > right,
+        ast::CondBinaryOp::Eq => parse_int(left)? == parse_int(right)?,
+        ast::CondBinaryOp::Ne => parse_int(left)? != parse_int(right)?,
+        ast::CondBinaryOp::Lt => parse_int(left)? < parse_int(right)?,
+        ast::CondBinaryOp::Le => parse_int(left)? <= parse_int(right)?,
+        ast::CondBinaryOp::Gt => parse_int(left)? > parse_int(right)?,
+        ast::CondBinaryOp::Ge => parse_int(left)? >= parse_int(right)?,
+    })
+}
+
+fn parse_int(text: &[u8]) -> Result<i64, String> {
+    let text = str::from_utf8(text).unwrap_or_default().trim();
+    text.parse()
+        .map_err(|_| format!("{text}: integer expression expected"))
 }
@@ -0,0 +1,99 @@
+use crate::{
+    ast,
+    interpreter::{
+        run_arith::eval_arith_text,
+        run_atom::{run_atom, run_atom_to_bytes},
+        run_redirect::{redirect_error, run_redirects},
+        run_stmts, Shell, Stdin, Stdout,
+    },
+    stringpool::StringPool,
+};
+use std::{os::unix::process::ExitStatusExt, process::ExitStatus};
+use tokio::io;
+
+pub async fn run_for(
+    shell: &mut Shell,
+    for_: &ast::For,
+    stdin: Stdin,
+    stdout: Stdout,
+    stderr: Stdout,
+) -> io::Result<ExitStatus> {
+    let (stdin, stdout, stderr) =
+        match run_redirects(shell, &for_.redirects, stdin, stdout, stderr.clone()).await {
+            Ok(stdio) => stdio,
+            Err(err) => return redirect_error(&stderr, err).await,
+        };
+    shell.loop_depth += 1;
+    let result = run_loop(shell, for_, stdin, stdout, stderr).await;
+    shell.loop_depth -= 1;
+    result
+}
+
+async fn run_loop(
+    shell: &mut Shell,
+    for_: &ast::For,
+    stdin: Stdin,
+    stdout: Stdout,
+    stderr: Stdout,
+) -> io::Result<ExitStatus> {
+    let mut exitstatus = ExitStatus::from_raw(0);
+    match &for_.kind {
+        ast::ForKind::In { name, words } => {
+            let values = match words {
+                Some(words) => {
+                    let mut values = StringPool::new();
+                    for word in words {
+                        run_atom(shell, word, &mut values).await?;
+                    }
+                    values.get_strings().to_vec()
+                }
+                None => shell.args.clone(),
+            };
+            for value in values {
+                shell.set_var(name.clone(), value);
+                exitstatus = Box::pin(run_stmts(
+                    shell,
+                    &for_.body,
+                    stdin.clone(),
+                    stdout.clone(),
+                    stderr.clone(),
+                ))
+                .await?;
+                if shell.loop_ends() {
+                    break;
+                }
+            }
+        }
+        ast::ForKind::Arith { init, cond, step } => {
+            arith_clause(shell, init).await?;
+            while arith_clause(shell, cond)
+                .await?
+                .is_none_or(|cond| cond != 0)
+            {
+                exitstatus = Box::pin(run_stmts(
+                    shell,
+                    &for_.body,
+                    stdin.clone(),
+                    stdout.clone(),
+                    stderr.clone(),
+                ))
+                .await?;
+                if shell.loop_ends() {
+                    break;
+                }
+                arith_clause(shell, step).await?;
+            }
+        }
+    }
+    Ok(exitstatus)
+}
+
+/// Evaluates one of the clauses of `for ((init; cond; step))`, which is expanded again every
+/// time. An empty clause is `None`.
+async fn arith_clause(shell: &mut Shell, clause: &ast::Atom) -> io::Result<Option<i64>> {
+    let text = run_atom_to_bytes(shell, clause).await?;
+    if text.trim_ascii().is_empty() {
+        return Ok(None);
+    }
+    eval_arith_text(shell, &text).map(Some)
+}
@@ -0,0 +1,69 @@
+use crate::{
+    ast,
+    interpreter::{run_boxed_expr, Control, Shell, Stdin, Stdout},
+};
+use bytes::Bytes;
+use std::{
+    future::Future,
+    mem,
+    os::unix::process::ExitStatusExt,
+    pin::Pin,
+    process::ExitStatus,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tokio::io;
+
+pub fn run_function_def(shell: &mut Shell, function: &ast::Function) -> io::Result<ExitStatus> {
+    shell
+        .functions
+        .insert(function.name.clone(), function.body.clone());
+    Ok(ExitStatus::from_raw(0))
+}
+
+/// Calls a function with `args` as its positional parameters, its status is that of `return` or
+/// of the last command it ran.
+pub async fn run_function(
+    shell: &mut Shell,
+    name: &[u8],
+    body: Arc<ast::Expr>,
+    args: &[Bytes],
+    stdin: Stdin,
+    stdout: Stdout,
+    stderr: Stdout,
+) -> io::Result<ExitStatus> {
+    if shell.call_depth() >= shell.max_call_depth {
+        let name = String::from_utf8_lossy(name);
+        let message = format!(
+            "shl: {name}: maximum function nesting level exceeded ({})\n",
+            shell.max_call_depth
+        );
+        stderr.write_all_err(message.as_bytes()).await?;
+        return Ok(ExitStatus::from_raw(1 << 8));
+    }
+    let outer_args = mem::replace(&mut shell.args, args.to_vec());
+    // `break` and `continue` never reach the loops around a call.
+    let outer_loop_depth = mem::take(&mut shell.loop_depth);
+    shell.push_call();
+    let result = GrowStack(run_boxed_expr(shell, &body, stdin, stdout, stderr)).await;
+    shell.pop_call();
+    shell.loop_depth = outer_loop_depth;
+    shell.args = outer_args;
+    if shell.control == Some(Control::Return) {
+        shell.control = None;
+    }
+    result
+}
+
+/// Polls a function call on a stack that grows on demand. The futures of nested calls are
+/// polled on top of each other, so recursion would otherwise overflow the stack long before
+/// reaching [`Shell::max_call_depth`].
+struct GrowStack<F: ?Sized>(Pin<Box<F>>);
+
+impl<F: Future + ?Sized> Future for GrowStack<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<F::Output> {
+        stacker::maybe_grow(256 * 1024, 2 * 1024 * 1024, || self.0.as_mut().poll(cx))
+    }
+}
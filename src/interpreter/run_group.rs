@@ -0,0 +1,24 @@
+use crate::{
+    ast,
+    interpreter::{
+        run_redirect::{redirect_error, run_redirects},
+        run_stmts, Shell, Stdin, Stdout,
+    },
+};
+use std::process::ExitStatus;
+use tokio::io;
+
+pub async fn run_group(
+    shell: &mut Shell,
+    group: &ast::Group,
+    stdin: Stdin,
+    stdout: Stdout,
+    stderr: Stdout,
+) -> io::Result<ExitStatus> {
+    let (stdin, stdout, stderr) =
+        match run_redirects(shell, &group.redirects, stdin, stdout, stderr.clone()).await {
+            Ok(stdio) => stdio,
+            Err(err) => return redirect_error(&stderr, err).await,
+        };
+    Box::pin(run_stmts(shell, &group.body, stdin, stdout, stderr)).await
+}
@@ -1,7 +1,75 @@
-use crate::ast;
-use std::process::ExitStatus;
+use crate::{
+    ast,
+    interpreter::{
+        run_redirect::{redirect_error, run_redirects},
+        run_stmts, Shell, Stdin, Stdout,
+    },
+};
+use std::{os::unix::process::ExitStatusExt, process::ExitStatus};
 use tokio::io;
 
-pub async fn run_if(if_: &ast::If) -> io::Result<ExitStatus> {
-    todo!()
+pub async fn run_if(
+    shell: &mut Shell,
+    if_: &ast::If,
+    stdin: Stdin,
+    stdout: Stdout,
+    stderr: Stdout,
+) -> io::Result<ExitStatus> {
+    let (stdin, stdout, stderr) =
+        match run_redirects(shell, &if_.redirects, stdin, stdout, stderr.clone()).await {
+            Ok(stdio) => stdio,
+            Err(err) => return redirect_error(&stderr, err).await,
+        };
+    let cond = run_cond(
+        shell,
+        &if_.cond,
+        stdin.clone(),
+        stdout.clone(),
+        stderr.clone(),
+    )
+    .await?;
+    // A `break`, `continue`, `return` or `exit` in the condition skips the branches.
+    if shell.control.is_some() {
+        return Ok(cond);
+    }
+    if cond.success() {
+        return Box::pin(run_stmts(shell, &if_.then, stdin, stdout, stderr)).await;
+    }
+    // `else_parts` holds (condition, body) pairs for every `elif`, followed by the `else` body.
+    let mut elifs = if_.else_parts.chunks_exact(2);
+    for elif in &mut elifs {
+        let cond = run_cond(
+            shell,
+            &elif[0],
+            stdin.clone(),
+            stdout.clone(),
+            stderr.clone(),
+        )
+        .await?;
+        if shell.control.is_some() {
+            return Ok(cond);
+        }
+        if cond.success() {
+            return Box::pin(run_stmts(shell, &elif[1], stdin, stdout, stderr)).await;
+        }
+    }
+    if let [else_] = elifs.remainder() {
+        return Box::pin(run_stmts(shell, else_, stdin, stdout, stderr)).await;
+    }
+    Ok(ExitStatus::from_raw(0))
+}
+
+/// Runs the condition of an `if`, `while` or `until`, in which failures do not end the script
+/// with `set -e`.
+pub async fn run_cond(
+    shell: &mut Shell,
+    cond: &[ast::Stmt],
+    stdin: Stdin,
+    stdout: Stdout,
+    stderr: Stdout,
+) -> io::Result<ExitStatus> {
+    shell.conditions += 1;
+    let result = Box::pin(run_stmts(shell, cond, stdin, stdout, stderr)).await;
+    shell.conditions -= 1;
+    result
 }
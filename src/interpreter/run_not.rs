@@ -0,0 +1,21 @@
+use crate::{
+    ast,
+    interpreter::{run_boxed_expr, Shell, Stdin, Stdout},
+};
+use std::{os::unix::process::ExitStatusExt, process::ExitStatus};
+use tokio::io;
+
+/// `! pipeline`, inverts the status. A failure of the pipeline is tested, so it does not end the
+/// script with `set -e`.
+pub async fn run_not(
+    shell: &mut Shell,
+    expr: &ast::Expr,
+    stdin: Stdin,
+    stdout: Stdout,
+    stderr: Stdout,
+) -> io::Result<ExitStatus> {
+    shell.conditions += 1;
+    let result = run_boxed_expr(shell, expr, stdin, stdout, stderr).await;
+    shell.conditions -= 1;
+    Ok(ExitStatus::from_raw(i32::from(result?.success()) << 8))
+}
@@ -1,12 +1,22 @@
 use crate::{
     ast,
-    interpreter::{run_cmd::run_cmd, Stdin, Stdout},
+    interpreter::{
+        run_arith::run_arith, run_assigns::run_assigns, run_atom::expansion_error,
+        run_case::run_case, run_cmd::run_cmd, run_cond_expr::run_cond_expr, run_for::run_for,
+        run_group::run_group, run_if::run_if, run_sub_shell::run_sub_shell, run_while::run_while,
+        Shell, Stdin, Stdout,
+    },
 };
 use futures::future::{join_all, BoxFuture};
-use std::{process::ExitStatus, sync::Arc};
-use tokio::{io, sync::Mutex};
+use std::{
+    os::{fd::OwnedFd, unix::process::ExitStatusExt},
+    process::ExitStatus,
+    sync::Arc,
+};
+use tokio::io;
 
 pub async fn run_pipeline(
+    shell: &mut Shell,
     pipeline: &ast::Pipeline,
     stdin: Stdin,
     stdout: Stdout,
@@ -15,31 +25,72 @@ pub async fn run_pipeline(
     let mut futures: Vec<BoxFuture<io::Result<ExitStatus>>> = vec![];
     let mut prev = None;
     for (i, item) in pipeline.items.iter().enumerate() {
-        let (reader, writer) = io::simplex(1024);
-        let stdout = if i == pipeline.items.len() - 1 {
-            stdout.clone()
-        } else {
-            Stdout::Pipe(Arc::new(Mutex::new(writer)))
-        };
         let stdin = if let Some(prev) = prev.take() {
-            Stdin::Pipe(Arc::new(Mutex::new(prev)))
+            Stdin::Fd(Arc::new(prev))
         } else {
             stdin.clone()
         };
-        match item {
-            ast::PipelineItem::Cmd(cmd) => {
-                futures.push(Box::pin(run_cmd(cmd, stdin, stdout, stderr.clone())));
-            }
-            ast::PipelineItem::Assigns(assigns) => todo!(),
-            ast::PipelineItem::SubShell(sub_shell) => todo!(),
-            ast::PipelineItem::If(_) => todo!(),
-            ast::PipelineItem::CondExpr(cond_expr) => todo!(),
-        }
-        prev = Some(reader);
+        let stdout = if i == pipeline.items.len() - 1 {
+            stdout.clone()
+        } else {
+            let (reader, writer) = std::io::pipe()?;
+            prev = Some(OwnedFd::from(reader));
+            Stdout::Fd(Arc::new(OwnedFd::from(writer)))
+        };
+        // Every stage of a pipeline runs in its own copy of the shell state. The stage drops its
+        // end of the pipe once it completes, which lets the next stage see EOF.
+        let mut shell = shell.clone();
+        let stderr = stderr.clone();
+        futures.push(Box::pin(async move {
+            run_pipeline_item(&mut shell, item, stdin, stdout, stderr).await
+        }));
     }
-    let mut exitcode = None;
-    for result in join_all(futures).await {
-        exitcode = Some(result?);
+    // The statuses are in the order of the stages, however they finished.
+    let exitstatuses = join_all(futures)
+        .await
+        .into_iter()
+        .collect::<io::Result<Vec<_>>>()?;
+    shell.pipestatus = exitstatuses.clone();
+    // The status is that of the rightmost stage, or with `set -o pipefail` that of the rightmost
+    // stage that failed.
+    let failed = exitstatuses
+        .iter()
+        .rev()
+        .find(|exitstatus| !exitstatus.success());
+    let exitstatus = match failed {
+        Some(failed) if shell.options.pipefail => Some(failed),
+        _ => exitstatuses.last(),
+    };
+    // A pipeline without stages succeeds, like an empty command.
+    Ok(exitstatus.copied().unwrap_or(ExitStatus::from_raw(0)))
+}
+
+async fn run_pipeline_item(
+    shell: &mut Shell,
+    item: &ast::PipelineItem,
+    stdin: Stdin,
+    stdout: Stdout,
+    stderr: Stdout,
+) -> io::Result<ExitStatus> {
+    let err_out = stderr.clone();
+    let proc_substs = shell.proc_substs.mark(&stdin, &stdout, &stderr);
+    let result = match item {
+        ast::PipelineItem::Cmd(cmd) => run_cmd(shell, cmd, stdin, stdout, stderr).await,
+        ast::PipelineItem::Assigns(assigns) => run_assigns(shell, assigns, &err_out).await,
+        ast::PipelineItem::SubShell(sub_shell) => {
+            run_sub_shell(shell, sub_shell, stdin, stdout, stderr).await
+        }
+        ast::PipelineItem::Group(group) => run_group(shell, group, stdin, stdout, stderr).await,
+        ast::PipelineItem::If(if_) => run_if(shell, if_, stdin, stdout, stderr).await,
+        ast::PipelineItem::For(for_) => run_for(shell, for_, stdin, stdout, stderr).await,
+        ast::PipelineItem::While(while_) => run_while(shell, while_, stdin, stdout, stderr).await,
+        ast::PipelineItem::Case(case) => run_case(shell, case, stdin, stdout, stderr).await,
+        ast::PipelineItem::CondExpr(cond_expr) => run_cond_expr(shell, cond_expr, stderr).await,
+        ast::PipelineItem::Arith(expr) => run_arith(shell, expr).await,
+    };
+    shell.proc_substs.finish(proc_substs).await;
+    match result {
+        Ok(exitstatus) => Ok(exitstatus),
+        Err(err) => expansion_error(&err_out, err).await,
     }
-    Ok(exitcode.unwrap_or_default())
 }
@@ -0,0 +1,33 @@
+use crate::{
+    ast,
+    interpreter::{run_script, Shell, Stdin, Stdout},
+};
+use bytes::Bytes;
+use std::{os::fd::OwnedFd, sync::Arc};
+use tokio::io;
+
+/// Starts `<(script)` or `>(script)` on a copy of the shell state, and returns the `/dev/fd/N`
+/// path through which the output of the script is read or its input is written. Its other
+/// streams are those of the command it is expanded for.
+///
+/// The substitution ends along with the command it is expanded for, see [`run_expr`].
+///
+/// [`run_expr`]: crate::interpreter::run_expr
+pub fn run_proc_subst(shell: &mut Shell, script: &ast::Script, input: bool) -> io::Result<Bytes> {
+    let (reader, writer) = std::io::pipe()?;
+    let (stdin, stdout, stderr) = shell.proc_substs.stdio();
+    let (fd, stdin, stdout) = if input {
+        let stdout = Stdout::Fd(Arc::new(OwnedFd::from(writer)));
+        (OwnedFd::from(reader), stdin, stdout)
+    } else {
+        let stdin = Stdin::Fd(Arc::new(OwnedFd::from(reader)));
+        (OwnedFd::from(writer), stdin, stdout)
+    };
+    let mut sub_shell = shell.clone();
+    let script = script.clone();
+    let handle = tokio::spawn(async move {
+        let _ = run_script(&mut sub_shell, &script, stdin, stdout, stderr).await;
+    });
+    let fd = shell.proc_substs.push(fd, handle);
+    Ok(format!("/dev/fd/{fd}").into())
+}
@@ -0,0 +1,139 @@
+use crate::{
+    ast,
+    interpreter::{
+        pyobject::{pyobject_herestring, pyobject_redirect, PyRedirect},
+        run_atom::{run_atom, run_atom_to_bytes},
+        Shell, Stdin, Stdout,
+    },
+    stringpool::StringPool,
+};
+use bytes::Bytes;
+use std::{
+    ffi::OsStr,
+    io::Write,
+    os::fd::OwnedFd,
+    os::unix::{ffi::OsStrExt, process::ExitStatusExt},
+    path::Path,
+    process::ExitStatus,
+    sync::Arc,
+};
+use tokio::{fs::OpenOptions, io};
+
+/// Reports a failed redirection, the command is not run and its status is 1.
+pub async fn redirect_error(stderr: &Stdout, err: io::Error) -> io::Result<ExitStatus> {
+    stderr
+        .write_all_err(format!("shl: {err}\n").as_bytes())
+        .await?;
+    Ok(ExitStatus::from_raw(1 << 8))
+}
+
+/// Applies redirections, in order, on top of the stdio a command or subshell would otherwise get.
+pub async fn run_redirects(
+    shell: &mut Shell,
+    redirects: &[ast::Redirection],
+    mut stdin: Stdin,
+    mut stdout: Stdout,
+    mut stderr: Stdout,
+) -> io::Result<(Stdin, Stdout, Stdout)> {
+    for redirect in redirects {
+        (stdin, stdout, stderr) = run_redirect(shell, redirect, stdin, stdout, stderr).await?;
+    }
+    Ok((stdin, stdout, stderr))
+}
+
+async fn run_redirect(
+    shell: &mut Shell,
+    redirect: &ast::Redirection,
+    stdin: Stdin,
+    stdout: Stdout,
+    stderr: Stdout,
+) -> io::Result<(Stdin, Stdout, Stdout)> {
+    let flags = redirect.flags;
+    if flags.duplicate_out {
+        // `2>&1` points stderr at stdout, `1>&2` points stdout at stderr.
+        return Ok(if flags.stderr {
+            (stdin, stdout.clone(), stdout)
+        } else {
+            (stdin, stderr.clone(), stderr)
+        });
+    }
+    let path = match &redirect.target {
+        Some(ast::Redirect::Atom(body)) if flags.heredoc => {
+            let body = run_atom_to_bytes(shell, body).await?;
+            return Ok((heredoc_stdin(body)?, stdout, stderr));
+        }
+        Some(ast::Redirect::Atom(word)) if flags.herestring => {
+            let mut word = run_atom_to_bytes(shell, word).await?.to_vec();
+            word.push(b'\n');
+            return Ok((heredoc_stdin(word.into())?, stdout, stderr));
+        }
+        Some(ast::Redirect::Atom(atom)) => {
+            let mut out = StringPool::new();
+            run_atom(shell, atom, &mut out).await?;
+            let [path] = out.get_strings() else {
+                return Err(io::Error::other("ambiguous redirect"));
+            };
+            shell.cwd.join(OsStr::from_bytes(path))
+        }
+        Some(ast::Redirect::PyObject(index)) if flags.herestring => {
+            let mut word = pyobject_herestring(&shell.pyobjects[*index])?.to_vec();
+            word.push(b'\n');
+            return Ok((heredoc_stdin(word.into())?, stdout, stderr));
+        }
+        Some(ast::Redirect::PyObject(index)) => {
+            match pyobject_redirect(&shell.pyobjects[*index], flags.stdin, flags.append)? {
+                PyRedirect::Path(path) => shell.cwd.join(path),
+                PyRedirect::Stdin(stdin) => return Ok((stdin, stdout, stderr)),
+                PyRedirect::Stdout(target) => {
+                    let (stdout, stderr) = redirect_output(flags, target, stdout, stderr);
+                    return Ok((stdin, stdout, stderr));
+                }
+            }
+        }
+        None => return Err(io::Error::other("redirection with no file")),
+    };
+    if flags.stdin {
+        let file = OpenOptions::new()
+            .read(true)
+            .open(&path)
+            .await
+            .map_err(|err| path_error(&path, err))?;
+        let fd = OwnedFd::from(file.into_std().await);
+        return Ok((Stdin::Fd(Arc::new(fd)), stdout, stderr));
+    }
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .append(flags.append)
+        .truncate(!flags.append)
+        .open(&path)
+        .await
+        .map_err(|err| path_error(&path, err))?;
+    let target = Stdout::Fd(Arc::new(OwnedFd::from(file.into_std().await)));
+    let (stdout, stderr) = redirect_output(flags, target, stdout, stderr);
+    Ok((stdin, stdout, stderr))
+}
+
+/// Feeds the body of a here-document or a here-string through a pipe, so that a command only reads as much of it
+/// as it needs and leaves the rest to the next one.
+fn heredoc_stdin(body: Bytes) -> io::Result<Stdin> {
+    let (reader, mut writer) = std::io::pipe()?;
+    // The write fails once every reader is gone, which is fine.
+    tokio::task::spawn_blocking(move || writer.write_all(&body));
+    Ok(Stdin::Fd(Arc::new(OwnedFd::from(reader))))
+}
+
+fn redirect_output(
+    flags: ast::RedirectFlags,
+    target: Stdout,
+    stdout: Stdout,
+    stderr: Stdout,
+) -> (Stdout, Stdout) {
+    let stdout = if flags.stdout { target.clone() } else { stdout };
+    let stderr = if flags.stderr { target } else { stderr };
+    (stdout, stderr)
+}
+
+fn path_error(path: &Path, err: io::Error) -> io::Error {
+    io::Error::new(err.kind(), format!("{}: {err}", path.display()))
+}
@@ -2,8 +2,33 @@ use std::process::ExitStatus;
 
 use tokio::io;
 
-use crate::ast;
+use crate::{
+    ast,
+    interpreter::{
+        run_redirect::{redirect_error, run_redirects},
+        run_script, Shell, Stdin, Stdout,
+    },
+};
 
-pub async fn run_sub_shell(sub_shell: &ast::SubShell) -> io::Result<ExitStatus> {
-    todo!()
+pub async fn run_sub_shell(
+    shell: &mut Shell,
+    sub_shell: &ast::SubShell,
+    stdin: Stdin,
+    stdout: Stdout,
+    stderr: Stdout,
+) -> io::Result<ExitStatus> {
+    let (stdin, stdout, stderr) =
+        match run_redirects(shell, &sub_shell.redirects, stdin, stdout, stderr.clone()).await {
+            Ok(stdio) => stdio,
+            Err(err) => return redirect_error(&stderr, err).await,
+        };
+    let mut shell = shell.clone();
+    Box::pin(run_script(
+        &mut shell,
+        &sub_shell.script,
+        stdin,
+        stdout,
+        stderr,
+    ))
+    .await
 }
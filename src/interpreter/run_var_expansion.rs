@@ -0,0 +1,274 @@
+use crate::{
+    ast,
+    interpreter::{
+        is_var_name, pattern,
+        run_arith::eval_arith,
+        run_atom::{run_atom_to_bytes, run_atom_to_pattern, unbound_error, ExpansionError},
+        Control, Shell,
+    },
+};
+use bytes::{Bytes, BytesMut};
+use std::str;
+use tokio::io;
+
+/// Expands `${name<op>...}` to a value, which is then split and globbed like that of `$name`.
+pub async fn run_var_expansion(
+    shell: &mut Shell,
+    expansion: &ast::VarExpansion,
+) -> io::Result<Bytes> {
+    let name = &expansion.name;
+    let value = shell.param(name);
+    // With `set -u`, only the operators that test whether a variable is set accept one that is
+    // not.
+    let tests_set = matches!(
+        expansion.op,
+        ast::VarExpansionOp::Default { .. }
+            | ast::VarExpansionOp::Assign { .. }
+            | ast::VarExpansionOp::Error { .. }
+            | ast::VarExpansionOp::Alternative { .. }
+    );
+    if value.is_none() && shell.options.nounset && !tests_set {
+        return Err(unbound_error(shell, name));
+    }
+    let is_set = |colon: bool| {
+        value
+            .as_ref()
+            .is_some_and(|value| !colon || !value.is_empty())
+    };
+    match &expansion.op {
+        ast::VarExpansionOp::Default { word, colon } => {
+            if is_set(*colon) {
+                return Ok(value.unwrap_or_default());
+            }
+            expand_word(shell, word).await
+        }
+        ast::VarExpansionOp::Assign { word, colon } => {
+            if is_set(*colon) {
+                return Ok(value.unwrap_or_default());
+            }
+            if !is_var_name(name) {
+                return Err(expansion_error(name, "cannot assign in this way"));
+            }
+            let value = expand_word(shell, word).await?;
+            shell.set_var(name.clone(), value.clone());
+            Ok(value)
+        }
+        ast::VarExpansionOp::Error { word, colon } => {
+            if is_set(*colon) {
+                return Ok(value.unwrap_or_default());
+            }
+            let message = expand_word(shell, word).await?;
+            // Like an unbound variable with `set -u`, this ends the script.
+            shell.control = Some(Control::Exit);
+            if message.is_empty() {
+                return Err(expansion_error(name, "parameter null or not set"));
+            }
+            Err(expansion_error(name, &String::from_utf8_lossy(&message)))
+        }
+        ast::VarExpansionOp::Alternative { word, colon } => {
+            if !is_set(*colon) {
+                return Ok(Bytes::new());
+            }
+            expand_word(shell, word).await
+        }
+        // `${#@}` is the number of arguments.
+        ast::VarExpansionOp::Length if matches!(&name[..], b"@" | b"*") => {
+            Ok(shell.args.len().to_string().into())
+        }
+        ast::VarExpansionOp::Length => {
+            let length = char_offsets(&value.unwrap_or_default()).len() - 1;
+            Ok(length.to_string().into())
+        }
+        ast::VarExpansionOp::RemovePrefix { pattern, longest } => {
+            let value = value.unwrap_or_default();
+            let pattern = expand_pattern(shell, pattern).await?;
+            let mut ends = char_offsets(&value);
+            if *longest {
+                ends.reverse();
+            }
+            Ok(
+                match ends
+                    .iter()
+                    .find(|&&end| pattern::matches(&pattern, &value[..end]))
+                {
+                    Some(&end) => value.slice(end..),
+                    None => value,
+                },
+            )
+        }
+        ast::VarExpansionOp::RemoveSuffix { pattern, longest } => {
+            let value = value.unwrap_or_default();
+            let pattern = expand_pattern(shell, pattern).await?;
+            let mut starts = char_offsets(&value);
+            if !*longest {
+                starts.reverse();
+            }
+            Ok(
+                match starts
+                    .iter()
+                    .find(|&&start| pattern::matches(&pattern, &value[start..]))
+                {
+                    Some(&start) => value.slice(..start),
+                    None => value,
+                },
+            )
+        }
+        ast::VarExpansionOp::Replace {
+            pattern,
+            string,
+            kind,
+        } => {
+            let value = value.unwrap_or_default();
+            let pattern = expand_pattern(shell, pattern).await?;
+            let string = expand_word(shell, string).await?;
+            Ok(replace(&value, &pattern, &string, *kind))
+        }
+        ast::VarExpansionOp::Substring { offset, length } => {
+            let value = value.unwrap_or_default();
+            let offsets = char_offsets(&value);
+            let count = offsets.len() as i64 - 1;
+            let offset = eval_arith(shell, offset).await?;
+            let start = if offset < 0 { count + offset } else { offset };
+            if !(0..=count).contains(&start) {
+                return Ok(Bytes::new());
+            }
+            let end = match length {
+                None => count,
+                Some(length) => match eval_arith(shell, length).await? {
+                    length if length < 0 => count + length,
+                    length => (start + length).min(count),
+                },
+            };
+            if end < start {
+                return Err(expansion_error(name, "substring expression < 0"));
+            }
+            Ok(value.slice(offsets[start as usize]..offsets[end as usize]))
+        }
+        ast::VarExpansionOp::Index(index) => array_element(shell, name, index).await,
+        ast::VarExpansionOp::Elements { join } => {
+            let separator = if *join {
+                shell.ifs().first().copied()
+            } else {
+                Some(b' ')
+            };
+            let separator = separator.as_slice();
+            Ok(shell.array(name).join(separator).into())
+        }
+        ast::VarExpansionOp::IndexLength(index) => {
+            let element = array_element(shell, name, index).await?;
+            Ok((char_offsets(&element).len() - 1).to_string().into())
+        }
+        ast::VarExpansionOp::Count => Ok(shell.array(name).len().to_string().into()),
+    }
+}
+
+/// The element of `${name[index]}`, where the index is an arithmetic expression.
+async fn array_element(shell: &mut Shell, name: &Bytes, index: &ast::Atom) -> io::Result<Bytes> {
+    let elements = shell.array(name);
+    let index = eval_arith(shell, index).await?;
+    // A negative index counts from the end.
+    let index = if index < 0 {
+        elements.len() as i64 + index
+    } else {
+        index
+    };
+    match usize::try_from(index).ok().and_then(|i| elements.get(i)) {
+        Some(element) => Ok(element.clone()),
+        None if shell.options.nounset => Err(unbound_error(shell, name)),
+        None => Ok(Bytes::new()),
+    }
+}
+
+/// Whether `${@<op>word}` expands to the arguments as they are, such as `${@:-word}` when there
+/// are arguments.
+pub fn expands_to_args(shell: &Shell, expansion: &ast::VarExpansion) -> bool {
+    let (ast::VarExpansionOp::Default { colon, .. }
+    | ast::VarExpansionOp::Assign { colon, .. }
+    | ast::VarExpansionOp::Error { colon, .. }) = &expansion.op
+    else {
+        return false;
+    };
+    // With a colon, a single empty argument counts as unset.
+    &expansion.name[..] == b"@"
+        && !shell.args.is_empty()
+        && (!*colon || shell.args.len() > 1 || !shell.args[0].is_empty())
+}
+
+async fn expand_word(shell: &mut Shell, word: &ast::Atom) -> io::Result<Bytes> {
+    Box::pin(run_atom_to_bytes(shell, word)).await
+}
+
+async fn expand_pattern(shell: &mut Shell, pattern: &ast::Atom) -> io::Result<Bytes> {
+    Box::pin(run_atom_to_pattern(shell, pattern)).await
+}
+
+fn expansion_error(name: &[u8], message: &str) -> io::Error {
+    let name = String::from_utf8_lossy(name);
+    io::Error::other(ExpansionError(format!("{name}: {message}")))
+}
+
+/// Replaces the longest matches of a pattern, starting from the left.
+fn replace(value: &Bytes, pattern: &[u8], string: &[u8], kind: ast::ReplaceKind) -> Bytes {
+    let offsets = char_offsets(value);
+    let mut out = BytesMut::new();
+    match kind {
+        ast::ReplaceKind::Prefix => {
+            let Some(&end) = offsets
+                .iter()
+                .rev()
+                .find(|&&end| pattern::matches(pattern, &value[..end]))
+            else {
+                return value.clone();
+            };
+            out.extend_from_slice(string);
+            out.extend_from_slice(&value[end..]);
+        }
+        ast::ReplaceKind::Suffix => {
+            let Some(&start) = offsets
+                .iter()
+                .find(|&&start| pattern::matches(pattern, &value[start..]))
+            else {
+                return value.clone();
+            };
+            out.extend_from_slice(&value[..start]);
+            out.extend_from_slice(string);
+        }
+        ast::ReplaceKind::First | ast::ReplaceKind::All => {
+            let mut copied = 0;
+            let mut i = 0;
+            while i + 1 < offsets.len() {
+                let start = offsets[i];
+                // Empty matches are never replaced.
+                let Some(j) = (i + 1..offsets.len())
+                    .rev()
+                    .find(|&j| pattern::matches(pattern, &value[start..offsets[j]]))
+                else {
+                    i += 1;
+                    continue;
+                };
+                out.extend_from_slice(&value[copied..start]);
+                out.extend_from_slice(string);
+                copied = offsets[j];
+                i = j;
+                if kind == ast::ReplaceKind::First {
+                    break;
+                }
+            }
+            out.extend_from_slice(&value[copied..]);
+        }
+    }
+    out.freeze()
+}
+
+/// The offsets at which the characters of a value start, followed by its length. A value that
+/// is not UTF-8 is treated as bytes.
+fn char_offsets(value: &[u8]) -> Vec<usize> {
+    match str::from_utf8(value) {
+        Ok(value) => value
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain([value.len()])
+            .collect(),
+        Err(_) => (0..=value.len()).collect(),
+    }
+}
@@ -0,0 +1,63 @@
+use crate::{
+    ast,
+    interpreter::{
+        run_if::run_cond,
+        run_redirect::{redirect_error, run_redirects},
+        run_stmts, Shell, Stdin, Stdout,
+    },
+};
+use std::{os::unix::process::ExitStatusExt, process::ExitStatus};
+use tokio::io;
+
+pub async fn run_while(
+    shell: &mut Shell,
+    while_: &ast::While,
+    stdin: Stdin,
+    stdout: Stdout,
+    stderr: Stdout,
+) -> io::Result<ExitStatus> {
+    let (stdin, stdout, stderr) =
+        match run_redirects(shell, &while_.redirects, stdin, stdout, stderr.clone()).await {
+            Ok(stdio) => stdio,
+            Err(err) => return redirect_error(&stderr, err).await,
+        };
+    shell.loop_depth += 1;
+    let result = run_loop(shell, while_, stdin, stdout, stderr).await;
+    shell.loop_depth -= 1;
+    result
+}
+
+async fn run_loop(
+    shell: &mut Shell,
+    while_: &ast::While,
+    stdin: Stdin,
+    stdout: Stdout,
+    stderr: Stdout,
+) -> io::Result<ExitStatus> {
+    let mut exitstatus = ExitStatus::from_raw(0);
+    loop {
+        let cond = run_cond(
+            shell,
+            &while_.cond,
+            stdin.clone(),
+            stdout.clone(),
+            stderr.clone(),
+        )
+        .await?;
+        if shell.loop_ends() || cond.success() == while_.until {
+            break;
+        }
+        exitstatus = Box::pin(run_stmts(
+            shell,
+            &while_.body,
+            stdin.clone(),
+            stdout.clone(),
+            stderr.clone(),
+        ))
+        .await?;
+        if shell.loop_ends() {
+            break;
+        }
+    }
+    Ok(exitstatus)
+}
@@ -0,0 +1,326 @@
+use crate::{
+    ast,
+    interpreter::{JobPolicy, Jobs, Options, ProcSubsts},
+};
+use bytes::Bytes;
+use pyo3::{Py, PyAny};
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    io,
+    os::unix::{ffi::OsStrExt, process::ExitStatusExt},
+    path::PathBuf,
+    process::ExitStatus,
+    sync::Arc,
+};
+
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1000;
+
+/// Interpreter state that is threaded through `run_script`, `run_stmt` and `run_expr`.
+///
+/// Subshells, command substitutions and pipeline stages run on a clone of it, so changes they
+/// make never leak to the parent.
+#[derive(Debug, Clone)]
+pub struct Shell {
+    vars: HashMap<Bytes, Var>,
+    /// The logical working directory, independent of the Python process' cwd.
+    pub cwd: PathBuf,
+    pub jobs: Jobs,
+    /// The pid of the most recent background job, `$!`.
+    pub last_bg_pid: Option<u32>,
+    /// What happens to background jobs that are still running when the script finishes.
+    pub job_policy: JobPolicy,
+    /// The objects interpolated into the script, referred to by index from the AST.
+    pub pyobjects: Arc<Vec<Py<PyAny>>>,
+    /// `$0`.
+    pub arg0: Bytes,
+    /// The positional parameters `$1`, `$2`, ...
+    pub args: Vec<Bytes>,
+    /// The status of the most recent expression, `$?`.
+    pub last_status: ExitStatus,
+    /// The number of loops that are running, which bounds the levels of `break` and `continue`.
+    pub loop_depth: usize,
+    /// A `break`, `continue` or `return` that is unwinding the statements up to its loop or
+    /// function.
+    pub control: Option<Control>,
+    /// The functions that have been defined, by name.
+    pub functions: HashMap<Bytes, Arc<ast::Expr>>,
+    /// For every running function call, the variables it made `local` along with the values
+    /// they had before.
+    locals: Vec<Vec<(Bytes, Option<Var>)>>,
+    /// Function calls nested deeper than this fail, which stops runaway recursion.
+    pub max_call_depth: usize,
+    pub proc_substs: ProcSubsts,
+    pub options: Options,
+    /// The statuses of the stages of the most recent pipeline, `${PIPESTATUS[@]}`.
+    pub pipestatus: Vec<ExitStatus>,
+    /// The number of conditions that are running, such as that of an `if` or the left side of
+    /// `&&`, in which a failure does not end the script with `set -e`.
+    pub conditions: usize,
+    /// The status of the most recent command substitution, which is the status of an
+    /// assignment such as `x=$(cmd)`.
+    pub cmd_subst_status: Option<ExitStatus>,
+}
+
+/// A control-flow signal, statements stop running while one is pending.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Control {
+    /// `break n`, ends the `n`th enclosing loop.
+    Break(usize),
+    /// `continue n`, skips to the next iteration of the `n`th enclosing loop.
+    Continue(usize),
+    /// `return`, ends the running function call.
+    Return,
+    /// `exit`, ends the script, or the subshell it runs in.
+    Exit,
+}
+
+#[derive(Debug, Clone)]
+pub struct Var {
+    pub value: Bytes,
+    pub exported: bool,
+}
+
+impl Shell {
+    pub fn new() -> io::Result<Self> {
+        let cwd = std::env::current_dir()?;
+        // The process environment is copied, so that `unset` and `cd` do not change it.
+        let mut vars: HashMap<Bytes, Var> = std::env::vars_os()
+            .map(|(name, value)| {
+                let name = Bytes::copy_from_slice(name.as_bytes());
+                let value = Bytes::copy_from_slice(value.as_bytes());
+                (
+                    name,
+                    Var {
+                        value,
+                        exported: true,
+                    },
+                )
+            })
+            .collect();
+        vars.insert(
+            Bytes::from_static(b"PWD"),
+            Var {
+                value: Bytes::copy_from_slice(cwd.as_os_str().as_bytes()),
+                exported: true,
+            },
+        );
+        Ok(Self {
+            vars,
+            cwd,
+            jobs: Jobs::default(),
+            last_bg_pid: None,
+            job_policy: JobPolicy::default(),
+            pyobjects: Arc::default(),
+            arg0: Bytes::from_static(b"shl"),
+            args: vec![],
+            last_status: ExitStatus::from_raw(0),
+            loop_depth: 0,
+            control: None,
+            functions: HashMap::new(),
+            locals: vec![],
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            proc_substs: ProcSubsts::default(),
+            options: Options::default(),
+            pipestatus: vec![ExitStatus::from_raw(0)],
+            conditions: 0,
+            cmd_subst_status: None,
+        })
+    }
+
+    /// Looks up a variable, including the special parameters `$!`, `$?`, `$#` and `$$`. An array
+    /// is its first element.
+    pub fn var(&self, name: &[u8]) -> Option<Bytes> {
+        match name {
+            b"PIPESTATUS" => return self.array(name).into_iter().next(),
+            b"!" => return self.last_bg_pid.map(|pid| pid.to_string().into()),
+            b"?" => return Some(exit_code(self.last_status).to_string().into()),
+            b"#" => return Some(self.args.len().to_string().into()),
+            b"$" => return Some(std::process::id().to_string().into()),
+            _ => {}
+        }
+        self.vars.get(name).map(|var| var.value.clone())
+    }
+
+    /// Assigns a variable, an exported one stays exported.
+    pub fn set_var(&mut self, name: Bytes, value: Bytes) {
+        if let Some(var) = self.vars.get_mut(&name) {
+            var.value = value;
+            return;
+        }
+        self.vars.insert(
+            name,
+            Var {
+                value,
+                exported: false,
+            },
+        );
+    }
+
+    /// `export name[=value]`, marks a variable to be passed to commands. A variable that is not
+    /// set stays unset.
+    pub fn export_var(&mut self, name: Bytes, value: Option<Bytes>) {
+        if let Some(value) = value {
+            self.set_var(name.clone(), value);
+        }
+        if let Some(var) = self.vars.get_mut(&name) {
+            var.exported = true;
+        }
+    }
+
+    /// `export -n name`, keeps a variable from being passed to commands.
+    pub fn unexport_var(&mut self, name: &[u8]) {
+        if let Some(var) = self.vars.get_mut(name) {
+            var.exported = false;
+        }
+    }
+
+    pub fn unset_var(&mut self, name: &[u8]) {
+        self.vars.remove(name);
+    }
+
+    /// Assigns variables that only last while a builtin runs, like `IFS= read line`. Returns
+    /// what [`Shell::restore_vars`] needs to undo them.
+    pub fn set_temp_vars(&mut self, assigns: &[(Bytes, Bytes)]) -> Vec<(Bytes, Option<Var>)> {
+        assigns
+            .iter()
+            .map(|(name, value)| {
+                let old = self.vars.get(name).cloned();
+                self.set_var(name.clone(), value.clone());
+                (name.clone(), old)
+            })
+            .collect()
+    }
+
+    /// The number of function calls that are running.
+    pub fn call_depth(&self) -> usize {
+        self.locals.len()
+    }
+
+    /// Starts a function call, which gets its own scope of `local` variables.
+    pub fn push_call(&mut self) {
+        self.locals.push(vec![]);
+    }
+
+    /// Ends a function call, restoring the variables it made `local`.
+    pub fn pop_call(&mut self) {
+        if let Some(saved) = self.locals.pop() {
+            self.restore_vars(saved);
+        }
+    }
+
+    /// Makes a variable local to the running function call, without a value it is unset. Returns
+    /// false outside of a function.
+    pub fn set_local_var(&mut self, name: Bytes, value: Option<Bytes>) -> bool {
+        let Some(frame) = self.locals.last_mut() else {
+            return false;
+        };
+        if !frame.iter().any(|(saved, _)| *saved == name) {
+            frame.push((name.clone(), self.vars.get(&name).cloned()));
+        }
+        match value {
+            Some(value) => self.set_var(name, value),
+            None => {
+                self.vars.remove(&name);
+            }
+        }
+        true
+    }
+
+    pub fn restore_vars(&mut self, saved: Vec<(Bytes, Option<Var>)>) {
+        for (name, var) in saved.into_iter().rev() {
+            match var {
+                Some(var) => self.vars.insert(name, var),
+                None => self.vars.remove(&name),
+            };
+        }
+    }
+
+    /// The environment of spawned processes.
+    pub fn exported_vars(&self) -> impl Iterator<Item = (&OsStr, &OsStr)> {
+        self.vars
+            .iter()
+            .filter(|(_, var)| var.exported)
+            .map(|(name, var)| (OsStr::from_bytes(name), OsStr::from_bytes(&var.value)))
+    }
+
+    /// Looks up a positional parameter, `0` is the name of the script.
+    pub fn arg(&self, index: usize) -> Option<Bytes> {
+        match index {
+            0 => Some(self.arg0.clone()),
+            index => self.args.get(index - 1).cloned(),
+        }
+    }
+
+    /// Looks up a parameter by its name in `${...}`, where a number is a positional parameter.
+    pub fn param(&self, name: &[u8]) -> Option<Bytes> {
+        if name.iter().all(u8::is_ascii_digit) {
+            let index = std::str::from_utf8(name).ok()?.parse().ok()?;
+            return self.arg(index);
+        }
+        match name {
+            b"@" | b"*" => Some(self.args.join(&b' ').into()),
+            name => self.var(name),
+        }
+    }
+
+    /// The elements of `${name[@]}`. `PIPESTATUS` is the only array, any other parameter is one
+    /// with its value as the only element.
+    pub fn array(&self, name: &[u8]) -> Vec<Bytes> {
+        match name {
+            b"PIPESTATUS" => self
+                .pipestatus
+                .iter()
+                .map(|exitstatus| exit_code(*exitstatus).to_string().into())
+                .collect(),
+            name => self.param(name).into_iter().collect(),
+        }
+    }
+
+    /// Called by a loop after its condition or body ran, takes one level off a pending `break`
+    /// or `continue` and returns whether the loop ends. Levels that remain are left pending for
+    /// the enclosing loops.
+    pub fn loop_ends(&mut self) -> bool {
+        match self.control.take() {
+            None => false,
+            Some(control @ (Control::Return | Control::Exit)) => {
+                self.control = Some(control);
+                true
+            }
+            Some(Control::Break(1)) => true,
+            Some(Control::Continue(1)) => false,
+            Some(Control::Break(n)) => {
+                self.control = Some(Control::Break(n - 1));
+                true
+            }
+            Some(Control::Continue(n)) => {
+                self.control = Some(Control::Continue(n - 1));
+                true
+            }
+        }
+    }
+
+    pub fn ifs(&self) -> Bytes {
+        self.var(b"IFS")
+            .unwrap_or_else(|| Bytes::from_static(b" \t\n"))
+    }
+}
+
+/// The status as reported by `$?`, a command killed by a signal has `128 + signal`.
+pub fn exit_code(exitstatus: ExitStatus) -> i32 {
+    exitstatus
+        .code()
+        .or_else(|| exitstatus.signal().map(|signal| 128 + signal))
+        .unwrap_or(128)
+}
+
+/// Whether a name can be assigned to, as opposed to special and positional parameters.
+pub fn is_var_name(name: &[u8]) -> bool {
+    match name {
+        [first, rest @ ..] => {
+            (first.is_ascii_alphabetic() || *first == b'_')
+                && rest.iter().all(|c| c.is_ascii_alphanumeric() || *c == b'_')
+        }
+        [] => false,
+    }
+}
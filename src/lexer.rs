@@ -1,12 +1,12 @@
 use bytes::{Bytes, BytesMut};
-use pyo3::{types::PyAnyMethods, PyResult};
+use pyo3::{exceptions::PyValueError, types::PyAnyMethods, PyErr, PyResult};
 
 use crate::{ast::RedirectFlags, tokens::Token};
 use std::{ops::Range, str};
 
 pub const PLACEHOLDER: u8 = 8;
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq)]
 enum State {
     Normal,
     Single,
@@ -34,6 +34,51 @@ pub struct Lexer<'a, 'b, 'c, 'py> {
     in_subshell: Option<SubShellKind>,
     pyobjects: &'py [pyo3::Bound<'py, pyo3::PyAny>],
     obj: usize,
+    /// Inside `[[ ... ]]`.
+    in_cond_expr: bool,
+    /// While lexing the regex after `=~`, the nesting depth of parentheses in it.
+    cond_regex: Option<usize>,
+    /// While lexing the word of a `${...}` expansion or of an arithmetic expression.
+    param_word: Option<ParamWord>,
+    /// The `case` commands being lexed, innermost last.
+    cases: Vec<Case>,
+    /// The here-documents on the current line, whose bodies start after the next newline.
+    heredocs: Vec<HereDoc>,
+}
+
+/// A here-document whose body is yet to be lexed.
+struct HereDoc {
+    /// Where the tokens of the body go, right after the `<<` redirection.
+    index: usize,
+    delimiter: Vec<u8>,
+    /// `<<-` strips leading tabs from the lines of the body and from the delimiter line.
+    strip_tabs: bool,
+    /// A quoted delimiter turns off expansion in the body.
+    quoted: bool,
+}
+
+/// Where the lexer is in a `case` command, which decides what `)` and `;;` mean.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Case {
+    /// Before the word that is matched.
+    Word,
+    /// Before `in`.
+    In,
+    /// In the patterns of an item, where `)` ends the patterns rather than a subshell.
+    Patterns,
+    /// In the body of an item, which ends at `;;`, `;&`, `;;&` or `esac`.
+    Body,
+}
+
+#[derive(Copy, Clone)]
+struct ParamWord {
+    /// The quoting state the word started in, it only ends in the same state.
+    state: State,
+    /// The characters that end the word.
+    end: &'static [u8],
+    /// In `$(( ... ))`, the nesting depth of parentheses, which must be balanced before the
+    /// word can end.
+    parens: Option<usize>,
 }
 
 struct BacktrackSnapshot {
@@ -44,6 +89,8 @@ struct BacktrackSnapshot {
     j: usize,
     word_start: usize,
     delimit_quote: bool,
+    tokens: usize,
+    obj: usize,
 }
 
 #[derive(Copy, Clone)]
@@ -73,6 +120,11 @@ impl<'a, 'b, 'c, 'py> Lexer<'a, 'b, 'c, 'py> {
             in_subshell: None,
             pyobjects,
             obj: 0,
+            in_cond_expr: false,
+            cond_regex: None,
+            param_word: None,
+            cases: vec![],
+            heredocs: vec![],
         }
     }
 
@@ -80,6 +132,7 @@ impl<'a, 'b, 'c, 'py> Lexer<'a, 'b, 'c, 'py> {
         'l: loop {
             let Some(input) = self.eat() else {
                 self.break_word(true);
+                self.eat_heredoc_bodies()?;
                 break;
             };
             let char = input.char;
@@ -88,14 +141,23 @@ impl<'a, 'b, 'c, 'py> Lexer<'a, 'b, 'c, 'py> {
             // we use 8 or \b which is a non printable char
             if char == PLACEHOLDER {
                 let pyobject = &self.pyobjects[self.obj];
-                if let Ok(text) = pyobject.extract::<&str>() {
+                // A string after `<` is the input itself rather than a file name.
+                let is_input = self.word_start == self.j
+                    && matches!(self.tokens.last(), Some(Token::Redirect(flags)) if flags.stdin);
+                let text = if is_input {
+                    None
+                } else {
+                    pyobject.extract::<&str>().ok()
+                };
+                if let Some(text) = text {
                     self.break_word(false);
                     let start = self.j;
                     for c in text.as_bytes() {
                         self.append_char_to_str_pool(*c);
                     }
                     let str = BytesMut::from(&self.arena[start..self.j]).freeze();
-                    self.tokens.push(Token::Text(str));
+                    // Interpolated strings are never reinterpreted as syntax or patterns.
+                    self.tokens.push(Token::SingleQuotedText(str));
                     self.word_start = self.j;
                 } else {
                     self.break_word(false);
@@ -109,12 +171,50 @@ impl<'a, 'b, 'c, 'py> Lexer<'a, 'b, 'c, 'py> {
             // 2. lexing state switchers (quotes)
             // 3. word breakers (spaces, etc.)
             else if !escaped {
+                if let Some(param_word) = &mut self.param_word {
+                    if let Some(parens) = param_word
+                        .parens
+                        .as_mut()
+                        .filter(|_| !matches!(self.state, State::Single))
+                    {
+                        let nested = match char {
+                            b'(' => Some(*parens + 1),
+                            b')' if *parens > 0 => Some(*parens - 1),
+                            _ => None,
+                        };
+                        if let Some(nested) = nested {
+                            *parens = nested;
+                            self.append_char_to_str_pool(char);
+                            continue 'l;
+                        }
+                    }
+                }
+                if let Some(param_word) = self.param_word {
+                    if param_word.end.contains(&char) && param_word.state == self.state {
+                        self.break_word(false);
+                        return Ok(());
+                    }
+                    // Operators and blanks are literal in the word of a `${...}`.
+                    if matches!(self.state, State::Normal) && b" \t\n;|&<>(){},#".contains(&char) {
+                        self.append_char_to_str_pool(char);
+                        continue 'l;
+                    }
+                }
                 'escaped: {
                     match char {
                         b'[' => {
                             if matches!(self.state, State::Single | State::Double) {
                                 break 'escaped;
                             };
+                            // `[[` only opens a conditional expression where a command starts,
+                            // elsewhere it is text such as in the pattern `[[:alpha:]]`.
+                            if self.in_cond_expr
+                                || self.cases.last() == Some(&Case::Patterns)
+                                || !self.at_word_start()
+                                || !self.at_command_start(self.tokens.len())
+                            {
+                                break 'escaped;
+                            }
                             let Some(p) = self.peek() else {
                                 break 'escaped;
                             };
@@ -125,9 +225,7 @@ impl<'a, 'b, 'c, 'py> Lexer<'a, 'b, 'c, 'py> {
                             self.eat();
                             'do_backtrack: {
                                 let Some(p2) = self.peek() else {
-                                    self.break_word(true);
-                                    self.tokens.push(Token::DoubleBracketClose);
-                                    continue 'l;
+                                    break 'do_backtrack;
                                 };
                                 if p2.escaped {
                                     break 'do_backtrack;
@@ -136,6 +234,7 @@ impl<'a, 'b, 'c, 'py> Lexer<'a, 'b, 'c, 'py> {
                                     b' ' | b'\r' | b'\n' | b'\t' => {
                                         self.break_word(true);
                                         self.tokens.push(Token::DoubleBracketOpen);
+                                        self.in_cond_expr = true;
                                     }
                                     _ => break 'do_backtrack,
                                 }
@@ -147,6 +246,10 @@ impl<'a, 'b, 'c, 'py> Lexer<'a, 'b, 'c, 'py> {
                             if matches!(self.state, State::Single | State::Double) {
                                 break 'escaped;
                             };
+                            // `]]` only closes a conditional expression as a word of its own.
+                            if !self.in_cond_expr || !self.at_word_start() {
+                                break 'escaped;
+                            }
                             let Some(p) = self.peek() else {
                                 break 'escaped;
                             };
@@ -159,6 +262,8 @@ impl<'a, 'b, 'c, 'py> Lexer<'a, 'b, 'c, 'py> {
                                 let Some(p2) = self.peek() else {
                                     self.break_word(true);
                                     self.tokens.push(Token::DoubleBracketClose);
+                                    self.in_cond_expr = false;
+                                    self.cond_regex = None;
                                     continue 'l;
                                 };
                                 if p2.escaped {
@@ -168,6 +273,8 @@ impl<'a, 'b, 'c, 'py> Lexer<'a, 'b, 'c, 'py> {
                                     b' ' | b'\r' | b'\n' | b'\t' | b';' | b'&' | b'|' | b'>' => {
                                         self.break_word(true);
                                         self.tokens.push(Token::DoubleBracketClose);
+                                        self.in_cond_expr = false;
+                                        self.cond_regex = None;
                                     }
                                     _ => break 'do_backtrack,
                                 }
@@ -196,6 +303,24 @@ impl<'a, 'b, 'c, 'py> Lexer<'a, 'b, 'c, 'py> {
                                 break 'escaped;
                             };
                             self.break_word(true);
+                            if self.cases.last() == Some(&Case::Body) {
+                                // A word like `"a";;` is not delimited yet.
+                                self.break_word_operator();
+                                let terminator = if self.eat_char(b'&') {
+                                    Token::SemicolonAmpersand
+                                } else if !self.eat_char(b';') {
+                                    Token::Semicolon
+                                } else if self.eat_char(b'&') {
+                                    Token::DoubleSemicolonAmpersand
+                                } else {
+                                    Token::DoubleSemicolon
+                                };
+                                if terminator != Token::Semicolon {
+                                    *self.cases.last_mut().unwrap() = Case::Patterns;
+                                }
+                                self.tokens.push(terminator);
+                                continue 'l;
+                            }
                             self.tokens.push(Token::Semicolon);
                             continue 'l;
                         }
@@ -205,6 +330,7 @@ impl<'a, 'b, 'c, 'py> Lexer<'a, 'b, 'c, 'py> {
                             };
                             self.break_word_impl(true, true, false);
                             self.tokens.push(Token::Newline);
+                            self.eat_heredoc_bodies()?;
                             continue 'l;
                         }
                         b'*' => {
@@ -223,6 +349,22 @@ impl<'a, 'b, 'c, 'py> Lexer<'a, 'b, 'c, 'py> {
                             self.tokens.push(Token::Asterisk);
                             continue 'l;
                         }
+                        b'~' => {
+                            // `~` is bitwise negation in arithmetic.
+                            let in_arith = self.param_word.is_some_and(|w| w.parens.is_some());
+                            if !matches!(self.state, State::Normal)
+                                || in_arith
+                                || !self.at_tilde_prefix()
+                            {
+                                break 'escaped;
+                            }
+                            let Some(name) = self.eat_tilde_prefix() else {
+                                break 'escaped;
+                            };
+                            self.break_word(false);
+                            self.tokens.push(Token::Tilde(name));
+                            continue 'l;
+                        }
                         b'{' => {
                             if matches!(self.state, State::Single | State::Double) {
                                 break 'escaped;
@@ -273,9 +415,26 @@ impl<'a, 'b, 'c, 'py> Lexer<'a, 'b, 'c, 'py> {
                             });
                             if !peeked.escaped && peeked.char == b'(' {
                                 self.break_word(false);
+                                let snapshot = self.make_snapshot();
+                                self.eat();
+                                let quoted = matches!(self.state, State::Double);
+                                if self.eat_char(b'(')
+                                    && self.eat_arith(Token::ArithBegin { quoted })?
+                                {
+                                    continue 'l;
+                                }
+                                // `$((cmd); cmd)` is a command substitution that starts with a
+                                // subshell.
+                                self.backtrack(snapshot);
                                 self.eat_subshell(SubShellKind::Dollar)?;
                                 continue 'l;
                             }
+                            if !peeked.escaped && peeked.char == b'{' {
+                                self.break_word(false);
+                                self.eat();
+                                self.eat_braced_var()?;
+                                continue 'l;
+                            }
                             self.break_word(false);
                             let var_tok = self.eat_var();
                             let str = BytesMut::from(&self.arena[self.word_start..self.j]).freeze();
@@ -285,15 +444,15 @@ impl<'a, 'b, 'c, 'py> Lexer<'a, 'b, 'c, 'py> {
                                     self.break_word(false);
                                 }
                                 1 => {
-                                    let c = self.chars[var_tok.start];
+                                    let c = self.arena[var_tok.start];
                                     if c.is_ascii_digit() {
-                                        self.tokens.push(Token::VarArgv(c - b'0'));
+                                        self.push_var_argv((c - b'0').into());
                                     } else {
-                                        self.tokens.push(Token::Var(str));
+                                        self.push_var(str);
                                     }
                                 }
                                 _ => {
-                                    self.tokens.push(Token::Var(str));
+                                    self.push_var(str);
                                 }
                             }
                             self.word_start = self.j;
@@ -303,7 +462,21 @@ impl<'a, 'b, 'c, 'py> Lexer<'a, 'b, 'c, 'py> {
                             if matches!(self.state, State::Single | State::Double) {
                                 break 'escaped;
                             };
+                            if let Some(depth) = &mut self.cond_regex {
+                                *depth += 1;
+                                break 'escaped;
+                            }
                             self.break_word(true);
+                            // The optional `(` before the patterns of a `case` item.
+                            if self.cases.last() == Some(&Case::Patterns) {
+                                continue 'l;
+                            }
+                            let snapshot = self.make_snapshot();
+                            if self.eat_char(b'(') && self.eat_arith(Token::ArithCmdBegin)? {
+                                continue 'l;
+                            }
+                            // `((cmd) )` is a subshell in a subshell.
+                            self.backtrack(snapshot);
                             self.eat_subshell(SubShellKind::Normal)?;
                             continue 'l;
                         }
@@ -311,13 +484,26 @@ impl<'a, 'b, 'c, 'py> Lexer<'a, 'b, 'c, 'py> {
                             if matches!(self.state, State::Single | State::Double) {
                                 break 'escaped;
                             };
+                            if let Some(depth) =
+                                self.cond_regex.as_mut().filter(|depth| **depth > 0)
+                            {
+                                *depth -= 1;
+                                break 'escaped;
+                            }
+                            self.break_word(true);
+                            if let Some(case @ Case::Patterns) = self.cases.last_mut() {
+                                *case = Case::Body;
+                                // A pattern like `*)` is not delimited yet.
+                                self.break_word_operator();
+                                self.tokens.push(Token::CasePatternsEnd);
+                                continue 'l;
+                            }
                             if !matches!(
                                 self.in_subshell,
                                 Some(SubShellKind::Dollar) | Some(SubShellKind::Normal)
                             ) {
                                 panic!("Unexpected `)`"); // TODO: handle errors!
                             }
-                            self.break_word(true);
                             if matches!(self.in_subshell, Some(SubShellKind::Dollar)) {
                                 if let Some(tok) = self.tokens.last() {
                                     match tok {
@@ -340,20 +526,30 @@ impl<'a, 'b, 'c, 'py> Lexer<'a, 'b, 'c, 'py> {
                             return Ok(());
                         }
                         b'0'..=b'9' => {
-                            if matches!(self.state, State::Normal) {
+                            // A file descriptor number is only special at the start of a word.
+                            if !matches!(self.state, State::Normal)
+                                || self.param_word.is_some()
+                                || self.word_start != self.j
+                                || self.last_token_is_word_part()
+                            {
                                 break 'escaped;
                             }
                             let snapshot = self.make_snapshot();
                             if let Some(redirect) = self.eat_redirect(input) {
                                 self.break_word(true);
                                 self.tokens.push(Token::Redirect(redirect));
+                                if redirect.heredoc {
+                                    self.eat_heredoc_delimiter();
+                                }
                                 continue 'l;
                             }
                             self.backtrack(snapshot);
                             break 'escaped;
                         }
                         b'|' => {
-                            if matches!(self.state, State::Single | State::Double) {
+                            if matches!(self.state, State::Single | State::Double)
+                                || self.cond_regex.is_some()
+                            {
                                 break 'escaped;
                             }
                             self.break_word_operator();
@@ -376,8 +572,15 @@ impl<'a, 'b, 'c, 'py> Lexer<'a, 'b, 'c, 'py> {
                             if matches!(self.state, State::Single | State::Double) {
                                 break 'escaped;
                             }
+                            if self.peek_char(b'(') {
+                                self.eat_proc_subst(false)?;
+                                continue 'l;
+                            }
                             self.break_word_operator();
-                            let redirect = self.eat_simple_redirect(false);
+                            let mut redirect = self.eat_simple_redirect(false);
+                            if redirect == RedirectFlags::right() && self.eat_duplicate_stderr() {
+                                redirect = RedirectFlags::one_right_and_two();
+                            }
                             self.tokens.push(Token::Redirect(redirect));
                             continue 'l;
                         }
@@ -385,9 +588,16 @@ impl<'a, 'b, 'c, 'py> Lexer<'a, 'b, 'c, 'py> {
                             if matches!(self.state, State::Single | State::Double) {
                                 break 'escaped;
                             }
+                            if self.peek_char(b'(') {
+                                self.eat_proc_subst(true)?;
+                                continue 'l;
+                            }
                             self.break_word_operator();
                             let redirect = self.eat_simple_redirect(true);
                             self.tokens.push(Token::Redirect(redirect));
+                            if redirect.heredoc {
+                                self.eat_heredoc_delimiter();
+                            }
                             continue 'l;
                         }
                         b'&' => {
@@ -420,6 +630,7 @@ impl<'a, 'b, 'c, 'py> Lexer<'a, 'b, 'c, 'py> {
                         b'\'' => {
                             match self.state {
                                 State::Single => {
+                                    self.push_empty_quoted_word(Token::SingleQuotedText);
                                     self.state = State::Normal;
                                 }
                                 State::Normal => {
@@ -438,6 +649,7 @@ impl<'a, 'b, 'c, 'py> Lexer<'a, 'b, 'c, 'py> {
                                 }
                                 State::Double => {
                                     self.break_word(false);
+                                    self.push_empty_quoted_word(Token::DoubleQuotedText);
                                     self.state = State::Normal;
                                 }
                             }
@@ -445,7 +657,13 @@ impl<'a, 'b, 'c, 'py> Lexer<'a, 'b, 'c, 'py> {
                         }
                         b' ' => {
                             if matches!(self.state, State::Normal) {
+                                if self.cond_regex.is_some_and(|depth| depth > 0) {
+                                    break 'escaped;
+                                }
                                 self.break_word_impl(true, true, false);
+                                if self.in_cond_expr {
+                                    self.update_cond_regex();
+                                }
                                 continue 'l;
                             }
                             break 'escaped;
@@ -462,8 +680,23 @@ impl<'a, 'b, 'c, 'py> Lexer<'a, 'b, 'c, 'py> {
                 }
                 continue 'l;
             }
+            // Escaped pattern characters are quoted so they are never globbed.
+            else if matches!(self.state, State::Normal)
+                && matches!(char, b'*' | b'?' | b'[' | b']')
+            {
+                self.break_word(false);
+                self.append_char_to_str_pool(char);
+                let str = BytesMut::from(&self.arena[self.word_start..self.j]).freeze();
+                self.tokens.push(Token::SingleQuotedText(str));
+                self.word_start = self.j;
+                continue 'l;
+            }
             self.append_char_to_str_pool(char);
         }
+        if self.param_word.is_some() {
+            // TODO: add error handling
+            panic!("Unclosed \"${{\"");
+        }
         if let Some(subshell_kind) = self.in_subshell {
             match subshell_kind {
                 SubShellKind::Dollar | SubShellKind::Backtick => {
@@ -478,20 +711,361 @@ impl<'a, 'b, 'c, 'py> Lexer<'a, 'b, 'c, 'py> {
         Ok(())
     }
 
+    /// The word after `=~` is a regex, in which `(`, `)` and `|` are not shell operators.
+    fn update_cond_regex(&mut self) {
+        match self.tokens.as_slice() {
+            [.., Token::Text(op), Token::Delimit] if &op[..] == b"=~" => self.cond_regex = Some(0),
+            [.., Token::Delimit] => self.cond_regex = None,
+            _ => {}
+        }
+    }
+
+    fn push_var(&mut self, name: Bytes) {
+        if matches!(self.state, State::Double) {
+            self.tokens.push(Token::DoubleQuotedVar(name));
+        } else {
+            self.tokens.push(Token::Var(name));
+        }
+    }
+
+    fn push_var_argv(&mut self, index: usize) {
+        if matches!(self.state, State::Double) {
+            self.tokens.push(Token::DoubleQuotedVarArgv(index));
+        } else {
+            self.tokens.push(Token::VarArgv(index));
+        }
+    }
+
+    /// Eats the rest of a `${...}` expansion after the `{`.
+    fn eat_braced_var(&mut self) -> PyResult<()> {
+        let quoted = matches!(self.state, State::Double);
+        let snapshot = self.make_snapshot();
+        // `${#name}` is the length, but `${#}` is the number of arguments.
+        if self.eat_char(b'#') && !self.peek_char(b'}') {
+            let name = self.eat_param_name()?;
+            if self.eat_char(b'[') {
+                // `${#name[@]}` is the number of elements, `${#name[index]}` the length of one.
+                self.tokens.push(Token::VarExpansionBegin {
+                    name,
+                    op: Bytes::from_static(b"#["),
+                    quoted,
+                });
+                self.eat_param_words(b"]")?;
+                return Ok(());
+            }
+            if !self.eat_char(b'}') {
+                return Err(bad_substitution(&name));
+            }
+            self.tokens.push(Token::VarLength(name));
+            self.word_start = self.j;
+            return Ok(());
+        }
+        self.backtrack(snapshot);
+        let name = self.eat_param_name()?;
+        let Some(input) = self.eat() else {
+            return Err(PyValueError::new_err("Unclosed \"${\""));
+        };
+        let c = if input.escaped { 0 } else { input.char };
+        let (op, end): (&'static [u8], &'static [u8]) = match c {
+            b'}' => {
+                match str::from_utf8(&name)
+                    .ok()
+                    .and_then(|name| name.parse().ok())
+                {
+                    Some(index) => self.push_var_argv(index),
+                    None if matches!(self.state, State::Normal) => {
+                        self.tokens.push(Token::BracedVar(name))
+                    }
+                    None => self.push_var(name),
+                }
+                self.word_start = self.j;
+                return Ok(());
+            }
+            b':' => match self.peek().filter(|p| !p.escaped).map(|p| p.char) {
+                Some(c @ (b'-' | b'=' | b'?' | b'+')) => {
+                    self.eat();
+                    let op: &'static [u8] = match c {
+                        b'-' => b":-",
+                        b'=' => b":=",
+                        b'?' => b":?",
+                        _ => b":+",
+                    };
+                    (op, b"}")
+                }
+                _ => (b":", b":}"),
+            },
+            b'-' => (b"-", b"}"),
+            b'=' => (b"=", b"}"),
+            b'?' => (b"?", b"}"),
+            b'+' => (b"+", b"}"),
+            b'#' if self.eat_char(b'#') => (b"##", b"}"),
+            b'#' => (b"#", b"}"),
+            b'%' if self.eat_char(b'%') => (b"%%", b"}"),
+            b'%' => (b"%", b"}"),
+            b'/' if self.eat_char(b'/') => (b"//", b"/}"),
+            b'/' if self.eat_char(b'#') => (b"/#", b"/}"),
+            b'/' if self.eat_char(b'%') => (b"/%", b"/}"),
+            b'/' => (b"/", b"/}"),
+            b'[' => (b"[", b"]"),
+            _ => return Err(bad_substitution(&name)),
+        };
+        self.tokens.push(Token::VarExpansionBegin {
+            name,
+            op: Bytes::from_static(op),
+            quoted,
+        });
+        self.eat_param_words(end)
+    }
+
+    /// Eats the words of a `${...}` expansion after its operator, the first one ends at one of the
+    /// `end` characters and the rest at `}`.
+    fn eat_param_words(&mut self, end: &'static [u8]) -> PyResult<()> {
+        let mut end = end;
+        while self.eat_param_word(end, false)? != b'}' {
+            end = b"}";
+        }
+        self.tokens.push(Token::VarExpansionEnd);
+        self.word_start = self.j;
+        Ok(())
+    }
+
+    /// Eats the name of a parameter in `${...}`: a number, a variable name or a special parameter.
+    fn eat_param_name(&mut self) -> PyResult<Bytes> {
+        let start = self.j;
+        while let Some(input) = self.peek() {
+            let c = input.char;
+            let name = &self.arena[start..self.j];
+            let continues = !input.escaped
+                && match name.first() {
+                    None => c.is_ascii_alphanumeric() || c == b'_' || is_special_param(&[c]),
+                    Some(first) if first.is_ascii_digit() => c.is_ascii_digit(),
+                    Some(first) if is_special_param(&[*first]) => false,
+                    Some(_) => c.is_ascii_alphanumeric() || c == b'_',
+                };
+            if !continues {
+                break;
+            }
+            self.eat();
+            self.append_char_to_str_pool(c);
+        }
+        let name = BytesMut::from(&self.arena[start..self.j]).freeze();
+        if name.is_empty() {
+            return Err(bad_substitution(&name));
+        }
+        Ok(name)
+    }
+
+    /// Eats the rest of `$((...))` or `((...))` after the `((`, the expression is a single word.
+    /// Returns false if it does not end with `))`, then the caller backtracks and lexes the
+    /// parentheses as subshells.
+    fn eat_arith(&mut self, begin: Token) -> PyResult<bool> {
+        self.tokens.push(begin);
+        if self.eat_param_word(b")", true)? != b')' || !self.eat_char(b')') {
+            return Ok(false);
+        }
+        self.tokens.push(Token::ArithEnd);
+        self.word_start = self.j;
+        Ok(true)
+    }
+
+    /// Lexes a word in `${...}`, or an arithmetic expression, up to one of the `end` characters,
+    /// which is returned. Each word is followed by a `Delimit`.
+    fn eat_param_word(&mut self, end: &'static [u8], arith: bool) -> PyResult<u8> {
+        self.word_start = self.j;
+        let prev_quote_state = self.state;
+        let mut sublexer = self.make_sublexer(SubShellKind::Normal);
+        sublexer.state = prev_quote_state;
+        sublexer.in_subshell = None;
+        sublexer.in_cond_expr = false;
+        sublexer.param_word = Some(ParamWord {
+            state: prev_quote_state,
+            end,
+            parens: arith.then_some(0),
+        });
+        sublexer.lex()?;
+        let i = sublexer.i;
+        let j = sublexer.j;
+        let word_start = sublexer.word_start;
+        let prev = sublexer.prev;
+        let current = sublexer.current;
+        let delimit_quote = sublexer.delimit_quote;
+        let obj = sublexer.obj;
+        self.i = i;
+        self.j = j;
+        self.word_start = word_start;
+        self.prev = prev;
+        self.current = current;
+        self.delimit_quote = delimit_quote;
+        self.state = prev_quote_state;
+        self.obj = obj;
+        self.tokens.push(Token::Delimit);
+        Ok(current.map_or(b'}', |input| input.char))
+    }
+
+    /// Eats the next character if it is an unescaped `c`.
+    fn eat_char(&mut self, c: u8) -> bool {
+        if self.peek_char(c) {
+            self.eat();
+            return true;
+        }
+        false
+    }
+
+    fn peek_char(&self, c: u8) -> bool {
+        self.peek()
+            .is_some_and(|input| !input.escaped && input.char == c)
+    }
+
     fn append_char_to_str_pool(&mut self, c: u8) {
         self.arena.push(c);
         self.j += 1;
     }
 
+    /// Eats a comment up to the newline, which still ends the command.
     fn eat_comment(&mut self) {
-        while let Some(peeked) = self.eat() {
-            if peeked.escaped {
-                continue;
+        while let Some(peeked) = self.peek() {
+            if !peeked.escaped && peeked.char == b'\n' {
+                break;
             }
-            if peeked.char == b'\n' {
+            self.eat();
+        }
+    }
+
+    /// Eats the delimiter after `<<` or `<<-`, the body is lexed once the line ends.
+    fn eat_heredoc_delimiter(&mut self) {
+        let strip_tabs = self.eat_char(b'-');
+        while self.eat_char(b' ') || self.eat_char(b'\t') {}
+        let mut delimiter = vec![];
+        let mut quoted = false;
+        let mut quote = None;
+        while let Some(input) = self.peek() {
+            let c = input.char;
+            if input.escaped {
+                quoted = true;
+            } else if quote == Some(c) {
+                quote = None;
+                self.eat();
+                continue;
+            } else if quote.is_none() && matches!(c, b'\'' | b'"') {
+                quote = Some(c);
+                quoted = true;
+                self.eat();
+                continue;
+            } else if quote.is_none() && b" \t\n;|&<>()".contains(&c) {
                 break;
             }
+            self.eat();
+            delimiter.push(c);
         }
+        if delimiter.is_empty() && !quoted {
+            // TODO: add error handling
+            panic!("Expected a here-document delimiter after \"<<\"");
+        }
+        self.heredocs.push(HereDoc {
+            index: self.tokens.len(),
+            delimiter,
+            strip_tabs,
+            quoted,
+        });
+    }
+
+    /// Lexes the bodies of the here-documents started on the line that just ended, each body is
+    /// a single word that goes right after its redirection.
+    fn eat_heredoc_bodies(&mut self) -> PyResult<()> {
+        let chars = self.chars;
+        let mut inserted = 0;
+        for heredoc in std::mem::take(&mut self.heredocs) {
+            let mut body = vec![];
+            while self.i < chars.len() {
+                let rest = &chars[self.i..];
+                let end = rest.iter().position(|&c| c == b'\n');
+                self.i += end.map_or(rest.len(), |end| end + 1);
+                let mut line = &rest[..end.unwrap_or(rest.len())];
+                if heredoc.strip_tabs {
+                    let tabs = line.iter().take_while(|&&c| c == b'\t').count();
+                    line = &line[tabs..];
+                }
+                if line == heredoc.delimiter {
+                    break;
+                }
+                body.extend_from_slice(line);
+                body.push(b'\n');
+            }
+            let start = self.tokens.len();
+            if heredoc.quoted {
+                self.push_heredoc_text(&body);
+            } else {
+                self.lex_heredoc(&body)?;
+            }
+            if self.tokens.len() == start {
+                self.tokens.push(Token::DoubleQuotedText(Bytes::new()));
+            }
+            self.tokens.push(Token::Delimit);
+            let tokens: Vec<_> = self.tokens.drain(start..).collect();
+            let index = heredoc.index + inserted;
+            inserted += tokens.len();
+            self.tokens.splice(index..index, tokens);
+        }
+        Ok(())
+    }
+
+    /// The body of a here-document with a quoted delimiter is literal, only Python
+    /// interpolations are substituted.
+    fn push_heredoc_text(&mut self, body: &[u8]) {
+        for (i, text) in body.split(|&c| c == PLACEHOLDER).enumerate() {
+            if i > 0 {
+                let token = match self.pyobjects[self.obj].extract::<&str>() {
+                    Ok(text) => Token::SingleQuotedText(Bytes::copy_from_slice(text.as_bytes())),
+                    Err(_) => Token::PyObject(self.obj),
+                };
+                self.tokens.push(token);
+                self.obj += 1;
+            }
+            if !text.is_empty() {
+                self.tokens
+                    .push(Token::SingleQuotedText(Bytes::copy_from_slice(text)));
+            }
+        }
+    }
+
+    /// Lexes the body of a here-document with an unquoted delimiter, which is expanded like a
+    /// double quoted string in which `"` is not special.
+    fn lex_heredoc(&mut self, body: &[u8]) -> PyResult<()> {
+        let mut chars = Vec::with_capacity(body.len() + 1);
+        let mut i = 0;
+        while i < body.len() {
+            match (body[i], body.get(i + 1)) {
+                (b'\\', Some(&next @ (b'$' | b'`' | b'\\' | b'\n'))) => {
+                    chars.extend_from_slice(&[b'\\', next]);
+                    i += 1;
+                }
+                (b'\\', _) => chars.extend_from_slice(b"\\\\"),
+                (b'"', _) => chars.extend_from_slice(b"\\\""),
+                (c, _) => chars.push(c),
+            }
+            i += 1;
+        }
+        // The closing quote ends the word.
+        chars.push(b'"');
+        let mut sublexer = self.make_sublexer(SubShellKind::Normal);
+        sublexer.chars = &chars;
+        sublexer.i = 0;
+        sublexer.word_start = sublexer.j;
+        sublexer.state = State::Double;
+        sublexer.in_subshell = None;
+        sublexer.in_cond_expr = false;
+        sublexer.param_word = Some(ParamWord {
+            state: State::Double,
+            end: b"\"",
+            parens: None,
+        });
+        sublexer.lex()?;
+        let j = sublexer.j;
+        let obj = sublexer.obj;
+        self.j = j;
+        self.word_start = j;
+        self.obj = obj;
+        Ok(())
     }
 
     fn make_snapshot(&self) -> BacktrackSnapshot {
@@ -503,6 +1077,8 @@ impl<'a, 'b, 'c, 'py> Lexer<'a, 'b, 'c, 'py> {
             j: self.j,
             word_start: self.word_start,
             delimit_quote: self.delimit_quote,
+            tokens: self.tokens.len(),
+            obj: self.obj,
         }
     }
 
@@ -514,6 +1090,10 @@ impl<'a, 'b, 'c, 'py> Lexer<'a, 'b, 'c, 'py> {
         self.j = snap.j;
         self.word_start = snap.word_start;
         self.delimit_quote = snap.delimit_quote;
+        // Anything lexed since the snapshot is discarded.
+        self.arena.truncate(snap.j);
+        self.tokens.truncate(snap.tokens);
+        self.obj = snap.obj;
     }
 
     fn peek(&self) -> Option<InputChar> {
@@ -582,44 +1162,221 @@ impl<'a, 'b, 'c, 'py> Lexer<'a, 'b, 'c, 'py> {
             }
             if add_delimiter {
                 self.tokens.push(Token::Delimit);
+                self.end_word();
             }
-        } else if (in_normal_space || in_operator)
-            && !self.tokens.is_empty()
-            && match self.tokens.last() {
-                Some(
-                    Token::Var(_)
-                    | Token::VarArgv(_)
-                    | Token::Text(_)
-                    | Token::SingleQuotedText(_)
-                    | Token::DoubleQuotedText(_)
-                    | Token::BraceBegin
-                    | Token::Comma
-                    | Token::BraceEnd
-                    | Token::CmdSubstEnd
-                    | Token::Asterisk,
-                ) => true,
-                _ => false,
-            }
-        {
+        } else if (in_normal_space || in_operator) && self.last_token_is_word_part() {
             self.tokens.push(Token::Delimit);
             self.delimit_quote = false;
+            self.end_word();
         }
         self.word_start = self.j;
     }
 
+    /// Keeps track of `case` commands after a word was delimited, since the reserved words are
+    /// what changes the meaning of `)` and `;;`.
+    fn end_word(&mut self) {
+        let end = self.tokens.len() - 1;
+        let word = self.reserved_word(end);
+        let case = match (self.cases.last(), word) {
+            (Some(Case::Word), _) => Some(Case::In),
+            (Some(Case::In), Some(b"in")) => Some(Case::Patterns),
+            (Some(Case::Patterns), Some(b"esac"))
+                if self.tokens.get(end - 2) != Some(&Token::Pipe) && !self.before_pattern_end() =>
+            {
+                None
+            }
+            (Some(Case::Body), Some(b"esac")) if self.at_command_start(end - 1) => None,
+            (Some(Case::Body) | None, Some(b"case")) if self.at_command_start(end - 1) => {
+                self.cases.push(Case::Word);
+                return;
+            }
+            _ => return,
+        };
+        self.cases.pop();
+        self.cases.extend(case);
+    }
+
+    /// Whether the word that was just delimited is followed by `)`, which makes it a pattern of
+    /// a `case` item even if it is a reserved word such as `esac`. In a subshell or a command
+    /// substitution, `esac)` is rather the end of `case` and of the subshell.
+    fn before_pattern_end(&self) -> bool {
+        if matches!(
+            self.in_subshell,
+            Some(SubShellKind::Normal | SubShellKind::Dollar)
+        ) {
+            return false;
+        }
+        let is_blank = |c: &u8| matches!(c, b' ' | b'\t');
+        let next = match self.current {
+            Some(current) if !current.escaped && !is_blank(&current.char) => Some(current.char),
+            _ => self.chars[self.i..].iter().copied().find(|c| !is_blank(c)),
+        };
+        next == Some(b')')
+    }
+
+    /// The text of the word delimited by the `Delimit` at `end`, if it is unquoted and made of
+    /// nothing else, which is what a reserved word must be.
+    fn reserved_word(&self, end: usize) -> Option<&[u8]> {
+        let text: &[u8] = match self.tokens.get(end.checked_sub(1)?)? {
+            Token::Text(text) => text,
+            // `{` is a token of its own because of brace expansion.
+            Token::BraceBegin => b"{",
+            _ => return None,
+        };
+        let is_whole = end < 2 || !is_word_part(&self.tokens[end - 2]);
+        is_whole.then_some(text)
+    }
+
+    /// Whether the word at `index` starts a command, where a reserved word is recognized.
+    fn at_command_start(&self, index: usize) -> bool {
+        let Some(prev) = index.checked_sub(1).map(|i| &self.tokens[i]) else {
+            return true;
+        };
+        match prev {
+            Token::Semicolon
+            | Token::Newline
+            | Token::Pipe
+            | Token::DoublePipe
+            | Token::Ampersand
+            | Token::DoubleAmpersand
+            | Token::DoubleSemicolon
+            | Token::SemicolonAmpersand
+            | Token::DoubleSemicolonAmpersand
+            | Token::OpenParen
+            | Token::CloseParen
+            | Token::CasePatternsEnd
+            | Token::CmdSubstBegin
+            | Token::CmdSubstQuoted => true,
+            // After a reserved word that is followed by a command.
+            Token::Delimit => {
+                matches!(
+                    self.reserved_word(index - 1),
+                    Some(
+                        b"if"
+                            | b"then"
+                            | b"elif"
+                            | b"else"
+                            | b"while"
+                            | b"until"
+                            | b"do"
+                            | b"!"
+                            | b"{"
+                    )
+                ) && self.at_command_start(index - 2)
+            }
+            _ => false,
+        }
+    }
+
+    /// Makes `""` and `''` an empty word rather than nothing at all, when the quotes that were
+    /// just closed are all there is of the word so far.
+    fn push_empty_quoted_word(&mut self, token: fn(Bytes) -> Token) {
+        if self.word_start == self.j && !self.last_token_is_word_part() {
+            self.tokens.push(token(Bytes::new()));
+        }
+    }
+
+    /// Whether nothing of the current word has been lexed yet.
+    fn at_word_start(&self) -> bool {
+        self.word_start == self.j && !self.last_token_is_word_part()
+    }
+
+    /// Whether the last token belongs to a word that has not been delimited yet.
+    fn last_token_is_word_part(&self) -> bool {
+        self.tokens.last().is_some_and(is_word_part)
+    }
+
+    /// Whether a `~` that was just eaten starts a tilde prefix, which it does at the start of a
+    /// word and after the `=` or any `:` of an assignment.
+    fn at_tilde_prefix(&self) -> bool {
+        let prev = if self.word_start != self.j {
+            self.arena.get(self.j - 1).copied()
+        } else if let Some(Token::Text(text)) = self.tokens.last() {
+            text.last().copied()
+        } else {
+            return !self.last_token_is_word_part();
+        };
+        matches!(prev, Some(b'=' | b':')) && self.in_assignment_word()
+    }
+
+    /// Whether the word being lexed starts with `NAME=`.
+    fn in_assignment_word(&self) -> bool {
+        let first = self
+            .tokens
+            .iter()
+            .rev()
+            .take_while(|token| is_word_part(token))
+            .last();
+        let text = match first {
+            Some(Token::Text(text)) => text,
+            Some(_) => return false,
+            None => &self.arena[self.word_start..self.j],
+        };
+        text.split_once(|&c| c == b'=').is_some_and(|(name, _)| {
+            !name.is_empty()
+                && !name[0].is_ascii_digit()
+                && name.iter().all(|&c| c.is_ascii_alphanumeric() || c == b'_')
+        })
+    }
+
+    /// Eats the login name, `+` or `-` after a `~`, if it is followed by the end of the word or
+    /// a `/` (or `:` in an assignment).
+    fn eat_tilde_prefix(&mut self) -> Option<Bytes> {
+        let snapshot = self.make_snapshot();
+        let mut name = vec![];
+        while let Some(peeked) = self.peek() {
+            if peeked.escaped
+                || !(peeked.char.is_ascii_alphanumeric() || b"._-+".contains(&peeked.char))
+            {
+                break;
+            }
+            self.eat();
+            name.push(peeked.char);
+        }
+        let ends_prefix = match self.peek() {
+            None => true,
+            Some(peeked) if peeked.escaped => false,
+            Some(peeked) => match peeked.char {
+                b'/' | b' ' | b'\t' | b'\n' | b';' | b'|' | b'&' | b'<' | b'>' | b')' => true,
+                b':' => self.in_assignment_word(),
+                _ => false,
+            },
+        };
+        let is_name = !matches!(name.first(), Some(b'+' | b'-')) && !name.contains(&b'+');
+        if ends_prefix && (is_name || name == b"+" || name == b"-") {
+            return Some(name.into());
+        }
+        self.backtrack(snapshot);
+        None
+    }
+
+    /// Eats the `&2` of `>&2`.
+    fn eat_duplicate_stderr(&mut self) -> bool {
+        let snapshot = self.make_snapshot();
+        if self.eat().is_some_and(|c| !c.escaped && c.char == b'&')
+            && self.eat().is_some_and(|c| !c.escaped && c.char == b'2')
+        {
+            return true;
+        }
+        self.backtrack(snapshot);
+        false
+    }
+
     fn eat_simple_redirect(&mut self, dir_in: bool) -> RedirectFlags {
         let is_double = self.eat_simple_redirect_operator(dir_in);
 
         if is_double {
-            if dir_in {
-                RedirectFlags::leftleft()
+            if dir_in && self.eat_simple_redirect_operator(true) {
+                RedirectFlags::herestring()
+            } else if dir_in {
+                RedirectFlags::heredoc()
             } else {
                 RedirectFlags::rightright()
             }
         } else if dir_in {
-            RedirectFlags::right()
-        } else {
             RedirectFlags::left()
+        } else {
+            RedirectFlags::right()
         }
     }
 
@@ -671,23 +1428,11 @@ impl<'a, 'b, 'c, 'py> Lexer<'a, 'b, 'c, 'py> {
                         let peeked2 = self.peek()?;
                         self.eat();
                         match peeked2.char {
-                            b'1' => {
-                                if !flags.stdout && flags.stderr {
-                                    flags.duplicate_out = true;
-                                    flags.stdout = true;
-                                    flags.stderr = false;
-                                } else {
-                                    return None;
-                                }
+                            b'1' if flags == RedirectFlags::two_right() => {
+                                flags = RedirectFlags::two_right_and_one();
                             }
-                            b'2' => {
-                                if !flags.stderr && flags.stdout {
-                                    flags.duplicate_out = true;
-                                    flags.stderr = true;
-                                    flags.stdout = false;
-                                } else {
-                                    return None;
-                                }
+                            b'2' if flags == RedirectFlags::right() => {
+                                flags = RedirectFlags::one_right_and_two();
                             }
                             _ => return None,
                         }
@@ -695,9 +1440,13 @@ impl<'a, 'b, 'c, 'py> Lexer<'a, 'b, 'c, 'py> {
                 }
             }
             b'<' => {
-                let is_double = self.eat_simple_redirect_operator(true);
-                if is_double {
-                    flags.append = true
+                self.eat();
+                if self.eat_simple_redirect_operator(true) {
+                    if self.eat_simple_redirect_operator(true) {
+                        flags.herestring = true;
+                    } else {
+                        flags.heredoc = true;
+                    }
                 }
                 return Some(flags);
             }
@@ -751,6 +1500,19 @@ impl<'a, 'b, 'c, 'py> Lexer<'a, 'b, 'c, 'py> {
                 }
             }
         }
+        self.lex_subshell(kind)
+    }
+
+    /// Eats `<(...)` or `>(...)` after the `<` or `>`, it is a word rather than a redirection.
+    fn eat_proc_subst(&mut self, input: bool) -> PyResult<()> {
+        self.break_word(false);
+        self.eat();
+        self.tokens.push(Token::ProcSubstBegin { input });
+        self.lex_subshell(SubShellKind::Dollar)
+    }
+
+    /// Lexes the commands of a subshell or a substitution up to its end.
+    fn lex_subshell(&mut self, kind: SubShellKind) -> PyResult<()> {
         let prev_quote_state = self.state;
         let mut sublexer = self.make_sublexer(kind);
         sublexer.lex()?;
@@ -787,6 +1549,11 @@ impl<'a, 'b, 'c, 'py> Lexer<'a, 'b, 'c, 'py> {
             in_subshell: Some(kind),
             pyobjects: self.pyobjects,
             obj: self.obj,
+            in_cond_expr: self.in_cond_expr && matches!(kind, SubShellKind::Normal),
+            cond_regex: None,
+            param_word: None,
+            cases: vec![],
+            heredocs: vec![],
         }
     }
 
@@ -808,6 +1575,12 @@ impl<'a, 'b, 'c, 'py> Lexer<'a, 'b, 'c, 'py> {
                         continue;
                     }
                     b'a'..=b'z' | b'A'..=b'Z' | b'_' => {}
+                    // Special parameters are a single character.
+                    c if is_special_param(&[c]) => {
+                        self.eat();
+                        self.append_char_to_str_pool(char);
+                        return start..self.j;
+                    }
                     _ => return start..self.j,
                 }
             }
@@ -829,7 +1602,7 @@ impl<'a, 'b, 'c, 'py> Lexer<'a, 'b, 'c, 'py> {
                     {
                         return start..self.j;
                     }
-                    if let b'0'..=b'9' | b'a'..=b'z' | b'_' = char {
+                    if let b'0'..=b'9' | b'a'..=b'z' | b'A'..=b'Z' | b'_' = char {
                         self.eat().unwrap();
                         self.append_char_to_str_pool(char);
                     } else {
@@ -842,3 +1615,41 @@ impl<'a, 'b, 'c, 'py> Lexer<'a, 'b, 'c, 'py> {
         start..self.j
     }
 }
+
+/// Whether the token is part of a word, as opposed to an operator or delimiter.
+fn is_word_part(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Var(_)
+            | Token::BracedVar(_)
+            | Token::DoubleQuotedVar(_)
+            | Token::VarArgv(_)
+            | Token::DoubleQuotedVarArgv(_)
+            | Token::Tilde(_)
+            | Token::Text(_)
+            | Token::SingleQuotedText(_)
+            | Token::DoubleQuotedText(_)
+            | Token::BraceBegin
+            | Token::Comma
+            | Token::BraceEnd
+            | Token::CmdSubstEnd
+            | Token::VarLength(_)
+            | Token::VarExpansionEnd
+            | Token::ArithEnd
+            | Token::PyObject(_)
+            | Token::Asterisk
+            | Token::DoubleAsterisk
+    )
+}
+
+/// `$?`, `$#`, `$$`, `$!`, `$@` and `$*`.
+fn is_special_param(name: &[u8]) -> bool {
+    matches!(name, [b'?' | b'#' | b'$' | b'!' | b'@' | b'*'])
+}
+
+fn bad_substitution(name: &[u8]) -> PyErr {
+    PyValueError::new_err(format!(
+        "Bad substitution: \"${{{}\"",
+        String::from_utf8_lossy(name)
+    ))
+}
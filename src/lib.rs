@@ -8,11 +8,14 @@ mod stringpool;
 mod templatelib;
 mod tokens;
 use crate::{
-    interpreter::{run_script, Stdin, Stdout},
+    interpreter::{exit_code, run_script, JobPolicy, Shell, Stdin, Stdout},
     lexer::{Lexer, PLACEHOLDER},
     parser::Parser,
 };
-use pyo3::prelude::*;
+use bytes::Bytes;
+use pyo3::{prelude::*, types::PyBytes};
+use std::sync::Arc;
+use tokio::{io, sync::Mutex};
 
 fn split_template<'py>(command: Bound<'py, PyAny>) -> PyResult<(Vec<Bound<'py, PyAny>>, Vec<u8>)> {
     let mut pyobjects = vec![];
@@ -61,17 +64,58 @@ fn _execute_command<'py>(
     py: Python<'py>,
     command: Bound<'py, PyAny>,
 ) -> PyResult<Bound<'py, PyAny>> {
-    let (pyobjects, bytes) = split_template(command)?;
+    let quiet: bool = command.getattr("_quiet")?.extract()?;
+    let text: Option<String> = command.getattr("_text")?.extract()?;
+    let kill_jobs: bool = command.getattr("_kill_jobs")?.extract()?;
+    let args: Vec<String> = command.getattr("_args")?.extract()?;
+    let max_call_depth: Option<usize> = command.getattr("_max_call_depth")?.extract()?;
+    let (pyobjects, bytes) = split_template(command.getattr("_command")?)?;
     let mut tokens = vec![];
     let mut arena = vec![];
     let mut lexer = Lexer::new(&bytes, &mut tokens, &mut arena, &pyobjects);
     lexer.lex()?;
     let mut parser = Parser::new(&tokens, &arena);
     let script = parser.parse();
+    let pyobjects = Arc::new(pyobjects.into_iter().map(Bound::unbind).collect());
+    let completed_command = py.import("shl")?.getattr("CompletedCommand")?.unbind();
     pyo3_async_runtimes::tokio::future_into_py(py, async move {
-        run_script(&script, Stdin::Inherit, Stdout::Inherit, Stdout::Inherit).await?;
-        let dbg = format!("{:?}", script);
-        Python::with_gil(|py| Ok(dbg.into_pyobject(py)?.into_any().unbind()))
+        let captured = Arc::new(Mutex::new(vec![]));
+        let (stdout, stderr) = if text.is_some() {
+            (
+                Stdout::Pipe(captured.clone()),
+                Stdout::Pipe(Arc::new(Mutex::new(io::sink()))),
+            )
+        } else if quiet {
+            let sink = Stdout::Pipe(Arc::new(Mutex::new(io::sink())));
+            (sink.clone(), sink)
+        } else {
+            (Stdout::Inherit, Stdout::Inherit)
+        };
+        let mut shell = Shell::new()?;
+        shell.pyobjects = pyobjects;
+        shell.args = args.into_iter().map(Bytes::from).collect();
+        if kill_jobs {
+            shell.job_policy = JobPolicy::Kill;
+        }
+        if let Some(max_call_depth) = max_call_depth {
+            shell.max_call_depth = max_call_depth;
+        }
+        let exitstatus = run_script(&mut shell, &script, Stdin::Inherit, stdout, stderr).await?;
+        let pipestatus: Vec<i32> = shell
+            .pipestatus
+            .iter()
+            .map(|&exitstatus| exit_code(exitstatus))
+            .collect();
+        let captured = captured.lock().await;
+        Python::with_gil(|py| match text.as_deref() {
+            Some("[bytes]") => Ok(PyBytes::new(py, &captured).into_any().unbind()),
+            Some(encoding) => Ok(PyBytes::new(py, &captured)
+                .call_method1("decode", (encoding,))?
+                .unbind()),
+            None => Ok(completed_command
+                .call1(py, (exit_code(exitstatus), pipestatus))?
+                .into_any()),
+        })
     })
 }
 
@@ -80,5 +124,7 @@ fn shl(m: &Bound<PyModule>) -> PyResult<()> {
     // m.add_function(wrap_pyfunction!(_lex_command, m)?)?;
     m.add_function(wrap_pyfunction!(_parse_command, m)?)?;
     m.add_function(wrap_pyfunction!(_execute_command, m)?)?;
+    // Whether echo, cat, ls and the like run in-process, see the `coreutils` feature.
+    m.add("COREUTILS", cfg!(feature = "coreutils"))?;
     Ok(())
 }
@@ -1,4 +1,5 @@
-use bytes::BytesMut;
+use bytes::{Bytes, BytesMut};
+use std::sync::Arc;
 
 use crate::{ast, tokens::Token};
 
@@ -55,11 +56,6 @@ impl From<&IfClauseTok> for &str {
     }
 }
 
-struct ParsedRedirect {
-    redirect: Option<ast::Redirect>,
-    flags: ast::RedirectFlags,
-}
-
 pub struct Parser<'a> {
     tokens: &'a [Token],
     current: usize,
@@ -130,11 +126,15 @@ impl<'a> Parser<'a> {
                 &self.inside_subshell.unwrap().into(),
             ])
         } {
+            if self.at_case_terminator() {
+                break;
+            }
             let expr = self.parse_expr();
             if self.matches(&Token::Ampersand) {
-                panic!("Background commands \"&\" are not supported yet.");
+                exprs.push(ast::Expr::Async(Box::new(expr)));
+            } else {
+                exprs.push(expr);
             }
-            exprs.push(expr);
         }
 
         ast::Stmt { exprs }
@@ -156,6 +156,10 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_pipeline(&mut self) -> ast::Expr {
+        if self.is_if_clause_text_token("!") && self.delimits(self.peek_n(1)) {
+            self.expect_if_clause_text_token("!");
+            return ast::Expr::Not(Box::new(self.parse_pipeline()));
+        }
         let mut expr = self.parse_compound_cmd();
 
         if self.peek() == &Token::Pipe {
@@ -184,23 +188,52 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_compound_cmd(&mut self) -> ast::Expr {
-        // Placeholder for when we fully support subshells
+        if self.is_if_clause_text_token("function") {
+            return ast::Expr::Function(self.parse_function());
+        }
+
+        if matches!(self.peek(), Token::Text(_))
+            && self.peek_n(1) == &Token::Delimit
+            && self.peek_n(2) == &Token::OpenParen
+            && self.peek_n(3) == &Token::CloseParen
+        {
+            return ast::Expr::Function(self.parse_function());
+        }
+
+        if self.peek() == &Token::BraceBegin && self.peek_n(1) == &Token::Delimit {
+            return ast::Expr::Group(self.parse_group());
+        }
+
         if self.peek() == &Token::OpenParen {
-            let subshell = self.parse_subshell();
-            if !subshell.redirect_flags.is_empty() {
-                panic!("Subshells with redirections are currently not supported. Please open a GitHub issue.");
-            }
-            return ast::Expr::SubShell(subshell);
+            return ast::Expr::SubShell(self.parse_subshell());
         }
 
         if self.is_if_clause_text_token("if") {
             return ast::Expr::If(self.parse_if_clause());
         }
 
+        if self.is_if_clause_text_token("for") {
+            return ast::Expr::For(self.parse_for());
+        }
+
+        if self.is_if_clause_text_token("while") || self.is_if_clause_text_token("until") {
+            return ast::Expr::While(self.parse_while());
+        }
+
+        if self.is_if_clause_text_token("case") {
+            return ast::Expr::Case(self.parse_case());
+        }
+
         if self.peek() == &Token::DoubleBracketOpen {
             return ast::Expr::CondExpr(self.parse_cond_expr());
         }
 
+        if self.matches(&Token::ArithCmdBegin) {
+            let expr = self.parse_arith();
+            self.matches(&Token::Delimit);
+            return ast::Expr::Arith(expr);
+        }
+
         match self.parse_simple_cmd() {
             ast::CmdOrAssigns::Cmd(cmd) => ast::Expr::Cmd(cmd),
             ast::CmdOrAssigns::Assigns(assigns) => ast::Expr::Assign(assigns),
@@ -212,11 +245,57 @@ impl<'a> Parser<'a> {
         let mut subparser = self.make_subparser(SubShellKind::Normal);
         let script = subparser.parse();
         self.continue_from_subparser(subparser);
-        let parsed_redirect = self.parse_redirect();
-        ast::SubShell {
-            script,
-            redirect: parsed_redirect.redirect,
-            redirect_flags: parsed_redirect.flags,
+        let redirects = self.parse_redirects();
+        ast::SubShell { script, redirects }
+    }
+
+    fn parse_group(&mut self) -> ast::Group {
+        self.expect(&Token::BraceBegin);
+        self.expect(&Token::Delimit);
+        let mut body = vec![];
+        loop {
+            self.skip_newlines();
+            if self.matches(&Token::BraceEnd) {
+                break;
+            }
+            if self.is_at_end() {
+                // TODO: add error handling
+                panic!("Expected \"}}\" but got: {:?}", self.peek());
+            }
+            body.push(self.parse_stmt());
+        }
+        self.matches(&Token::Delimit);
+        let redirects = self.parse_redirects();
+        ast::Group { body, redirects }
+    }
+
+    /// Parses `name() body`, `function name body` or `function name() body`.
+    fn parse_function(&mut self) -> ast::Function {
+        if self.is_if_clause_text_token("function") {
+            self.expect_if_clause_text_token("function");
+        }
+        let Token::Text(name) = self.advance().clone() else {
+            // TODO: add error handling
+            panic!("Expected a function name");
+        };
+        self.matches(&Token::Delimit);
+        if self.matches(&Token::OpenParen) {
+            self.expect(&Token::CloseParen);
+        }
+        self.skip_newlines();
+        let body = match self.parse_compound_cmd() {
+            ast::Expr::Cmd(_) | ast::Expr::Assign(_) | ast::Expr::Function(_) => {
+                // TODO: add error handling
+                panic!(
+                    "Expected a compound command as the body of \"{}\"",
+                    String::from_utf8_lossy(&name)
+                );
+            }
+            body => body,
+        };
+        ast::Function {
+            name,
+            body: Arc::new(body),
         }
     }
 
@@ -272,11 +351,6 @@ impl<'a> Parser<'a> {
                     panic!("Expected \"fi\" but got: {:?}", self.peek());
                 }
                 else_parts.push(else_part);
-                ast::If {
-                    cond,
-                    then,
-                    else_parts,
-                }
             }
             IfClauseTok::Elif => {
                 loop {
@@ -308,23 +382,180 @@ impl<'a> Parser<'a> {
                 if !self.match_if_clausetok(IfClauseTok::Fi) {
                     panic!("Expected \"fi\" but got: {:?}", self.peek());
                 }
-                ast::If {
-                    cond,
-                    then,
-                    else_parts,
-                }
             }
             IfClauseTok::Fi => {
                 self.expect_if_clause_text_token("fi");
-                ast::If {
-                    cond,
-                    then,
-                    else_parts: vec![],
+            }
+        }
+        let redirects = self.parse_redirects();
+        ast::If {
+            cond,
+            then,
+            else_parts,
+            redirects,
+        }
+    }
+
+    fn parse_for(&mut self) -> ast::For {
+        self.expect_if_clause_text_token("for");
+        let kind = if self.matches(&Token::ArithCmdBegin) {
+            let clauses = self.parse_arith();
+            self.matches(&Token::Delimit);
+            let Ok([init, cond, step]) = <[_; 3]>::try_from(split_arith_clauses(clauses)) else {
+                // TODO: add error handling
+                panic!("Expected three clauses separated by \";\" after \"for ((\"");
+            };
+            ast::ForKind::Arith { init, cond, step }
+        } else {
+            let Token::Text(name) = self.advance().clone() else {
+                // TODO: add error handling
+                panic!("Expected a variable name after \"for\"");
+            };
+            if !is_valid_var_name(&name) {
+                // TODO: add error handling
+                panic!(
+                    "`{}' is not a valid identifier",
+                    String::from_utf8_lossy(&name)
+                );
+            }
+            self.matches(&Token::Delimit);
+            self.skip_newlines();
+            let words = if self.is_if_clause_text_token("in") {
+                self.advance();
+                self.matches(&Token::Delimit);
+                let mut words = vec![];
+                while let Some(word) = self.parse_atom() {
+                    words.push(word);
+                }
+                Some(words)
+            } else {
+                None
+            };
+            ast::ForKind::In { name, words }
+        };
+        self.matches(&Token::Semicolon);
+        self.skip_newlines();
+        self.expect_if_clause_text_token("do");
+        let body = self.parse_body(&["done"]);
+        self.expect_end("done");
+        let redirects = self.parse_redirects();
+        ast::For {
+            kind,
+            body,
+            redirects,
+        }
+    }
+
+    fn parse_while(&mut self) -> ast::While {
+        let until = self.is_if_clause_text_token("until");
+        self.expect_if_clause_text_token(if until { "until" } else { "while" });
+        let cond = self.parse_body(&["do"]);
+        self.expect_if_clause_text_token("do");
+        let body = self.parse_body(&["done"]);
+        self.expect_end("done");
+        let redirects = self.parse_redirects();
+        ast::While {
+            cond,
+            body,
+            until,
+            redirects,
+        }
+    }
+
+    fn parse_case(&mut self) -> ast::Case {
+        self.expect_if_clause_text_token("case");
+        let Some(word) = self.parse_atom() else {
+            // TODO: add error handling
+            panic!("Expected a word after \"case\"");
+        };
+        self.skip_newlines();
+        self.expect_if_clause_text_token("in");
+        let mut items = vec![];
+        loop {
+            self.skip_newlines();
+            // Followed by `)`, `esac` is a pattern rather than the end.
+            if self.is_if_clause_text_token("esac") && self.peek_n(2) != &Token::CasePatternsEnd {
+                break;
+            }
+            let mut patterns = vec![];
+            loop {
+                let Some(pattern) = self.parse_atom() else {
+                    // TODO: add error handling
+                    panic!(
+                        "Expected a pattern but got: {:?} {}",
+                        self.peek(),
+                        self.current
+                    );
+                };
+                patterns.push(pattern);
+                if !self.matches(&Token::Pipe) {
+                    break;
                 }
             }
+            self.expect(&Token::CasePatternsEnd);
+            let body = self.parse_body(&["esac"]);
+            let terminator = match self.peek() {
+                Token::SemicolonAmpersand => ast::CaseTerminator::FallThrough,
+                Token::DoubleSemicolonAmpersand => ast::CaseTerminator::Continue,
+                _ => ast::CaseTerminator::Break,
+            };
+            self.matches_any(&[
+                &Token::DoubleSemicolon,
+                &Token::SemicolonAmpersand,
+                &Token::DoubleSemicolonAmpersand,
+            ]);
+            items.push(ast::CaseItem {
+                patterns,
+                body,
+                terminator,
+            });
+        }
+        self.expect_end("esac");
+        let redirects = self.parse_redirects();
+        ast::Case {
+            word,
+            items,
+            redirects,
         }
     }
 
+    /// Parses statements up to one of the `until` reserved words, which is not consumed.
+    fn parse_body(&mut self, until: &[&str]) -> Vec<ast::Stmt> {
+        let mut ret = vec![];
+        loop {
+            self.skip_newlines();
+            if matches!(self.peek(), Token::Text(text) if until.iter().any(|word| text == word))
+                || self.at_case_terminator()
+                || self.peek() == &Token::Eof
+                || self
+                    .inside_subshell
+                    .is_some_and(|kind| self.peek() == &kind.into())
+            {
+                break;
+            }
+            ret.push(self.parse_stmt());
+        }
+        ret
+    }
+
+    fn at_case_terminator(&self) -> bool {
+        matches!(
+            self.peek(),
+            Token::DoubleSemicolon | Token::SemicolonAmpersand | Token::DoubleSemicolonAmpersand
+        )
+    }
+
+    /// Eats the `done` that ends a loop or the `esac` that ends `case`, along with the delimiter
+    /// after it. Unlike other reserved words they can be followed by redirections.
+    fn expect_end(&mut self, end: &str) {
+        if !self.is_if_clause_text_token(end) {
+            // TODO: add error handling
+            panic!("Expected \"{end}\" but got: {:?}", self.peek());
+        }
+        self.advance();
+        self.matches(&Token::Delimit);
+    }
+
     fn parse_simple_cmd(&mut self) -> ast::CmdOrAssigns {
         let mut assigns: Vec<ast::Assign> = vec![];
 
@@ -372,16 +603,21 @@ impl<'a> Parser<'a> {
 
         let mut name_and_args: Vec<ast::Atom> = vec![];
         name_and_args.push(name);
-        while let Some(arg) = self.parse_atom() {
-            name_and_args.push(arg);
+        let mut redirects = vec![];
+        loop {
+            if matches!(self.peek(), Token::Redirect(..)) {
+                redirects.push(self.parse_redirect());
+            } else if let Some(arg) = self.parse_atom() {
+                name_and_args.push(arg);
+            } else {
+                break;
+            }
         }
-        let parsed_redirect = self.parse_redirect();
 
         ast::CmdOrAssigns::Cmd(ast::Cmd {
             assigns,
             name_and_args,
-            redirect: parsed_redirect.redirect,
-            redirect_flags: parsed_redirect.flags,
+            redirects,
         })
     }
 
@@ -452,23 +688,40 @@ impl<'a> Parser<'a> {
     }
 
     fn expect_var(&mut self) {
-        if !matches!(self.peek(), Token::Var(_)) {
+        if !matches!(
+            self.peek(),
+            Token::Var(_) | Token::BracedVar(_) | Token::DoubleQuotedVar(_)
+        ) {
             panic!("Expected var token")
         }
         self.advance();
     }
 
     fn expect_varargv(&mut self) {
-        if !matches!(self.peek(), Token::VarArgv(_)) {
+        if !matches!(
+            self.peek(),
+            Token::VarArgv(_) | Token::DoubleQuotedVarArgv(_)
+        ) {
             panic!("Expected varargv token")
         }
         self.advance();
     }
 
+    /// Parses the word of an arithmetic expression up to and including `ArithEnd`.
+    fn parse_arith(&mut self) -> ast::Atom {
+        let expr = self
+            .parse_atom()
+            .unwrap_or_else(|| ast::Atom::Simple(ast::SimpleAtom::Text(Bytes::new())));
+        if !self.matches(&Token::ArithEnd) {
+            // TODO: add error handling
+            panic!("Expected \"))\" but got: {:?}", self.peek());
+        }
+        expr
+    }
+
     fn parse_atom(&mut self) -> Option<ast::Atom> {
         let mut has_brace_open = false;
         let mut has_brace_close = false;
-        let mut has_comma = false;
         let mut has_glob_syntax = false;
         let mut atoms = vec![];
 
@@ -531,7 +784,6 @@ impl<'a> Parser<'a> {
                         }
                     }
                     Token::Comma => {
-                        has_comma = true;
                         self.expect(&Token::Comma);
                         atoms.push(ast::SimpleAtom::Comma);
                         if next_delimits {
@@ -556,18 +808,25 @@ impl<'a> Parser<'a> {
                             break;
                         }
                     }
+                    Token::ProcSubstBegin { input } => {
+                        self.advance();
+                        let mut subparser = self.make_subparser(SubShellKind::CmdSubst);
+                        let script = subparser.parse();
+                        atoms.push(ast::SimpleAtom::ProcSubst { script, input });
+                        self.continue_from_subparser(subparser);
+                        if self.delimits(self.peek()) {
+                            self.matches(&Token::Delimit);
+                            break;
+                        }
+                    }
                     Token::SingleQuotedText(text)
                     | Token::DoubleQuotedText(text)
                     | Token::Text(text) => {
                         self.advance();
-                        if peeked_is_text && !text.is_empty() && text.starts_with(b"~") {
-                            let text = text.slice(1..);
-                            atoms.push(ast::SimpleAtom::Tilde);
-                            if !text.is_empty() {
-                                atoms.push(ast::SimpleAtom::Text(text.into()));
-                            }
+                        if peeked_is_text {
+                            atoms.push(ast::SimpleAtom::Text(text));
                         } else {
-                            atoms.push(ast::SimpleAtom::Text(text.into()));
+                            atoms.push(ast::SimpleAtom::QuotedText(text));
                         }
                         if next_delimits {
                             self.matches(&Token::Delimit);
@@ -576,9 +835,15 @@ impl<'a> Parser<'a> {
                             }
                         }
                     }
-                    Token::Var(text) => {
+                    Token::Var(text) | Token::BracedVar(text) | Token::DoubleQuotedVar(text) => {
+                        let quoted = matches!(self.peek(), Token::DoubleQuotedVar(_));
+                        let braced = matches!(self.peek(), Token::BracedVar(_));
                         self.expect_var();
-                        atoms.push(ast::SimpleAtom::Var(text.into()));
+                        atoms.push(ast::SimpleAtom::Var {
+                            name: text,
+                            quoted,
+                            braced,
+                        });
                         if next_delimits {
                             self.matches(&Token::Delimit);
                             if should_break {
@@ -586,9 +851,18 @@ impl<'a> Parser<'a> {
                             }
                         }
                     }
-                    Token::VarArgv(int) => {
+                    Token::Tilde(name) => {
+                        self.advance();
+                        atoms.push(ast::SimpleAtom::Tilde(name));
+                        if next_delimits {
+                            self.matches(&Token::Delimit);
+                            break;
+                        }
+                    }
+                    Token::VarArgv(index) | Token::DoubleQuotedVarArgv(index) => {
+                        let quoted = matches!(self.peek(), Token::DoubleQuotedVarArgv(_));
                         self.expect_varargv();
-                        atoms.push(ast::SimpleAtom::VarArgv(int));
+                        atoms.push(ast::SimpleAtom::VarArgv { index, quoted });
                         if next_delimits {
                             self.matches(&Token::Delimit);
                             if should_break {
@@ -596,6 +870,57 @@ impl<'a> Parser<'a> {
                             }
                         }
                     }
+                    Token::VarExpansionBegin { name, op, quoted } => {
+                        self.advance();
+                        let mut words = vec![];
+                        while !self.matches(&Token::VarExpansionEnd) {
+                            // An empty word is only a `Delimit`.
+                            words.push(self.parse_atom().unwrap_or_else(|| {
+                                ast::Atom::Simple(ast::SimpleAtom::Text(Bytes::new()))
+                            }));
+                        }
+                        let Some(op) = ast::VarExpansionOp::parse(&op, words) else {
+                            panic!("Bad substitution: unknown operator in \"${{\"");
+                        };
+                        atoms.push(ast::SimpleAtom::VarExpansion(Box::new(ast::VarExpansion {
+                            name,
+                            op,
+                            quoted,
+                        })));
+                        if self.delimits(self.peek()) {
+                            self.matches(&Token::Delimit);
+                            break;
+                        }
+                    }
+                    Token::PyObject(index) => {
+                        self.advance();
+                        atoms.push(ast::SimpleAtom::PyObject(index));
+                        if next_delimits {
+                            self.matches(&Token::Delimit);
+                            break;
+                        }
+                    }
+                    Token::ArithBegin { quoted } => {
+                        self.advance();
+                        let expr = Box::new(self.parse_arith());
+                        atoms.push(ast::SimpleAtom::Arith { expr, quoted });
+                        if self.delimits(self.peek()) {
+                            self.matches(&Token::Delimit);
+                            break;
+                        }
+                    }
+                    Token::VarLength(name) => {
+                        self.advance();
+                        atoms.push(ast::SimpleAtom::VarExpansion(Box::new(ast::VarExpansion {
+                            name,
+                            op: ast::VarExpansionOp::Length,
+                            quoted: false,
+                        })));
+                        if next_delimits {
+                            self.matches(&Token::Delimit);
+                            break;
+                        }
+                    }
                     Token::OpenParen | Token::CloseParen => {
                         panic!("Unexpected parenthesis in atom parsing");
                     }
@@ -608,7 +933,7 @@ impl<'a> Parser<'a> {
             0 => None,
             1 => Some(ast::Atom::Simple(atoms.pop().unwrap())),
             _ => {
-                let brace_expansion_hint = has_brace_open && has_brace_close && has_comma;
+                let brace_expansion_hint = has_brace_open && has_brace_close;
                 let glob_hint = has_glob_syntax;
                 Some(ast::Atom::CompoundAtom(ast::CompoundAtom {
                     atoms,
@@ -619,37 +944,149 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn parse_redirect(&mut self) -> ParsedRedirect {
-        let has_redirect: bool = matches!(self.peek(), Token::Redirect(..));
-        let flags: ast::RedirectFlags = if has_redirect {
-            if let Token::Redirect(r) = self.advance() {
-                *r
-            } else {
-                unreachable!()
-            }
-        } else {
-            ast::RedirectFlags::default()
+    fn parse_redirects(&mut self) -> Vec<ast::Redirection> {
+        let mut redirects = vec![];
+        while matches!(self.peek(), Token::Redirect(..)) {
+            redirects.push(self.parse_redirect());
+        }
+        redirects
+    }
+
+    fn parse_redirect(&mut self) -> ast::Redirection {
+        let Token::Redirect(flags) = self.advance() else {
+            unreachable!()
         };
-        let redirect: Option<ast::Redirect> = if has_redirect {
-            if matches!(self.peek(), Token::PyObject(_)) {
-                todo!("implement python object redirection")
-            } else {
-                let Some(redirect_file) = self.parse_atom() else {
-                    // TODO: add error handling
-                    panic!("redirection with no file");
-                };
-                Some(ast::Redirect::Atom(redirect_file))
+        let flags = *flags;
+        let target = if flags.duplicate_out {
+            None
+        } else if flags.heredoc || flags.herestring {
+            // The lexer puts the body of a here-document right after the redirection. The word
+            // of a here-string is input even if it is a Python object, so a lone `{obj}` is kept
+            // as it is to use the contents of a bytes-like object.
+            match self.parse_atom() {
+                Some(ast::Atom::Simple(ast::SimpleAtom::PyObject(index))) if flags.herestring => {
+                    Some(ast::Redirect::PyObject(index))
+                }
+                atom => atom.map(ast::Redirect::Atom),
             }
+        } else if let Token::PyObject(index) = self.peek() {
+            let index = *index;
+            self.advance();
+            self.matches(&Token::Delimit);
+            Some(ast::Redirect::PyObject(index))
         } else {
-            None
+            let Some(redirect_file) = self.parse_atom() else {
+                // TODO: add error handling
+                panic!("redirection with no file");
+            };
+            Some(ast::Redirect::Atom(redirect_file))
         };
-        // TODO check for multiple redirects and error
-        ParsedRedirect { redirect, flags }
+        ast::Redirection { flags, target }
     }
 
     fn parse_cond_expr(&mut self) -> ast::CondExpr {
         self.expect(&Token::DoubleBracketOpen);
-        todo!("Conditional Expressions not implemented yet.")
+        let cond_expr = self.parse_cond_or();
+        self.skip_delimits();
+        if !self.matches(&Token::DoubleBracketClose) {
+            // TODO: add error handling
+            panic!("Expected \"]]\" but got: {:?}", self.peek());
+        }
+        cond_expr
+    }
+
+    fn parse_cond_or(&mut self) -> ast::CondExpr {
+        let mut left = self.parse_cond_and();
+        while self.matches(&Token::DoublePipe) {
+            let right = self.parse_cond_and();
+            left = ast::CondExpr::Or(Box::new(left), Box::new(right));
+        }
+        left
+    }
+
+    fn parse_cond_and(&mut self) -> ast::CondExpr {
+        let mut left = self.parse_cond_not();
+        while self.matches(&Token::DoubleAmpersand) {
+            let right = self.parse_cond_not();
+            left = ast::CondExpr::And(Box::new(left), Box::new(right));
+        }
+        left
+    }
+
+    fn parse_cond_not(&mut self) -> ast::CondExpr {
+        self.skip_delimits();
+        if self.match_cond_operator(b"!") {
+            return ast::CondExpr::Not(Box::new(self.parse_cond_not()));
+        }
+        if self.matches(&Token::OpenParen) {
+            let mut subparser = self.make_subparser(SubShellKind::Normal);
+            let cond_expr = subparser.parse_cond_or();
+            subparser.skip_delimits();
+            if subparser.peek() != &Token::CloseParen {
+                // TODO: add error handling
+                panic!("Expected \")\" but got: {:?}", subparser.peek());
+            }
+            self.continue_from_subparser(subparser);
+            self.skip_delimits();
+            return cond_expr;
+        }
+        if let Token::Text(text) = self.peek() {
+            if let Some(op) = ast::CondUnaryOp::parse(text) {
+                // `[[ -n ]]` tests whether the string "-n" is non-empty.
+                let has_operand = !matches!(
+                    self.peek_n(2),
+                    Token::DoubleBracketClose | Token::DoubleAmpersand | Token::DoublePipe
+                );
+                if has_operand && self.peek_n(1) == &Token::Delimit {
+                    self.advance();
+                    self.expect_delimit();
+                    return ast::CondExpr::Unary(op, self.parse_cond_word());
+                }
+            }
+        }
+        let left = self.parse_cond_word();
+        let op = match self.peek() {
+            Token::Text(text) if self.peek_n(1) == &Token::Delimit => {
+                ast::CondBinaryOp::parse(text)
+            }
+            Token::Redirect(flags) if *flags == ast::RedirectFlags::left() => {
+                Some(ast::CondBinaryOp::Less)
+            }
+            Token::Redirect(flags) if *flags == ast::RedirectFlags::right() => {
+                Some(ast::CondBinaryOp::Greater)
+            }
+            _ => None,
+        };
+        let Some(op) = op else {
+            return ast::CondExpr::Word(left);
+        };
+        self.advance();
+        self.skip_delimits();
+        let right = self.parse_cond_word();
+        ast::CondExpr::Binary(op, left, right)
+    }
+
+    fn parse_cond_word(&mut self) -> ast::Atom {
+        let Some(atom) = self.parse_atom() else {
+            // TODO: add error handling
+            panic!(
+                "Expected a word in conditional expression but got: {:?}",
+                self.peek()
+            );
+        };
+        atom
+    }
+
+    fn match_cond_operator(&mut self, op: &[u8]) -> bool {
+        let Token::Text(text) = self.peek() else {
+            return false;
+        };
+        if text == op && self.peek_n(1) == &Token::Delimit {
+            self.advance();
+            self.expect_delimit();
+            return true;
+        }
+        false
     }
 
     fn match_if_clausetok(&mut self, token: IfClauseTok) -> bool {
@@ -707,6 +1144,10 @@ impl<'a> Parser<'a> {
         while self.matches(&Token::Newline) {}
     }
 
+    fn skip_delimits(&mut self) {
+        while self.matches(&Token::Delimit) {}
+    }
+
     fn matches(&mut self, token: &Token) -> bool {
         if self.peek() == token {
             self.advance();
@@ -801,6 +1242,46 @@ impl<'a> Parser<'a> {
     }
 }
 
+/// Splits the word of `for ((init; cond; step))` at the `;` in its unquoted text, those in
+/// substitutions and quotes are left alone.
+fn split_arith_clauses(word: ast::Atom) -> Vec<ast::Atom> {
+    let atoms = match word {
+        ast::Atom::Simple(atom) => vec![atom],
+        ast::Atom::CompoundAtom(compound) => compound.atoms,
+    };
+    let mut clauses = vec![vec![]];
+    for atom in atoms {
+        let ast::SimpleAtom::Text(text) = atom else {
+            clauses.last_mut().unwrap().push(atom);
+            continue;
+        };
+        for (i, part) in text.split(|&c| c == b';').enumerate() {
+            if i > 0 {
+                clauses.push(vec![]);
+            }
+            if !part.is_empty() {
+                let part = text.slice_ref(part);
+                clauses
+                    .last_mut()
+                    .unwrap()
+                    .push(ast::SimpleAtom::Text(part));
+            }
+        }
+    }
+    clauses
+        .into_iter()
+        .map(|mut atoms| match atoms.len() {
+            0 => ast::Atom::Simple(ast::SimpleAtom::Text(Bytes::new())),
+            1 => ast::Atom::Simple(atoms.remove(0)),
+            _ => ast::Atom::CompoundAtom(ast::CompoundAtom {
+                atoms,
+                brace_expansion_hint: false,
+                glob_hint: false,
+            }),
+        })
+        .collect()
+}
+
 fn is_valid_var_name(var_name: &[u8]) -> bool {
     if var_name.is_empty() {
         return false;
@@ -14,6 +14,12 @@ pub enum Token {
     DoubleAsterisk,
     Eq,
     Semicolon,
+    /// `;;` that ends an item of `case`.
+    DoubleSemicolon,
+    /// `;&`, which falls through to the body of the next item of `case`.
+    SemicolonAmpersand,
+    /// `;;&`, which goes on to test the patterns of the next item of `case`.
+    DoubleSemicolonAmpersand,
     Newline,
     BraceBegin,
     Comma,
@@ -21,10 +27,39 @@ pub enum Token {
     CmdSubstBegin,
     CmdSubstQuoted,
     CmdSubstEnd,
+    /// `<(` or `>(`, followed by the commands and `CmdSubstEnd`.
+    ProcSubstBegin {
+        input: bool,
+    },
     OpenParen,
     CloseParen,
+    /// The `)` after the patterns of a `case` item.
+    CasePatternsEnd,
     Var(Bytes),
-    VarArgv(u8),
+    /// An unquoted `${name}`, whose name ends at the `}` even before brace expansion.
+    BracedVar(Bytes),
+    DoubleQuotedVar(Bytes),
+    VarArgv(usize),
+    DoubleQuotedVarArgv(usize),
+    /// `${name<op>...}`, followed by its words, each ending with a `Delimit`, and then
+    /// `VarExpansionEnd`.
+    VarExpansionBegin {
+        name: Bytes,
+        op: Bytes,
+        quoted: bool,
+    },
+    VarExpansionEnd,
+    /// `$((`, followed by the expression as a word ending with a `Delimit`, and `ArithEnd`.
+    ArithBegin {
+        quoted: bool,
+    },
+    /// `((` of the arithmetic command.
+    ArithCmdBegin,
+    ArithEnd,
+    /// `${#name}`.
+    VarLength(Bytes),
+    /// A tilde prefix such as `~` or `~user`, holding what follows the `~`.
+    Tilde(Bytes),
     Text(Bytes),
     SingleQuotedText(Bytes),
     DoubleQuotedText(Bytes),